@@ -4,11 +4,20 @@ use std::thread;
 // use numerica::domains::float::Complex;
 
 use symjit_bridge::{
-    compile, CompiledComplexRunner, CompiledRealRunner, Complex, ComplexFloat, Config, Defuns,
-    InterpretedComplexRunner, InterpretedRealRunner,
+    array_params, code_size, compile, compile_affine, compile_derivative, compile_gradient_reverse,
+    compile_lazy, compile_many, compile_named, compile_rational, compile_retained, compile_split,
+    compile_str, compile_then, compile_value_and_gradient, compile_with_array_params, constants,
+    evaluate_multi, natives, num_instructions, numa_nodes, pack_complex_aos_to_simd, param_range,
+    replicate_for_numa, temp_count, to_dot, unpack_complex_simd_to_aos, validate,
+    validate_instructions, value_and_gradient_evaluator, vmath, Accuracy, ArrayParam, BridgeError,
+    CompiledBoolRunner, CompiledComplexRunner, CompiledPhasorRunner, CompiledPolarRunner,
+    CompiledRealRunner, Complex, ComplexFloat, ComplexLayout, Config, CpuFeature, Defuns, EntryFn,
+    FpModel, InterpretedComplexRunner, InterpretedRealRunner, InterpretedSimdRealRunner,
+    NamedApplication, OptLevel, OutputLayout, Piecewise, ReduceOp, RobustRealRunner, RoundMode,
+    Scalars, ThreadedRealRunner, SAVE_FORMAT_VERSION,
 };
 
-use symjit::Applet;
+use symjit::{Applet, Compiled};
 
 use symbolica::{
     atom::{Atom, AtomCore},
@@ -17,19 +26,28 @@ use symbolica::{
         integer::IntegerRing,
         rational::{Fraction, Rational},
     },
-    evaluate::{ExpressionEvaluator, FunctionMap, OptimizationSettings},
+    evaluate::{ExpressionEvaluator, FunctionMap, Instruction, OptimizationSettings, Slot},
     parse, symbol, try_parse,
 };
 
 use std::{
     env, fs,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Instant,
 };
 
 use rand::prelude::*;
-use wide::f64x4;
+use wide::{f64x2, f64x4};
+
+#[cfg(feature = "nalgebra")]
+use nalgebra::DMatrix;
+
+#[cfg(feature = "test-util")]
+use symjit_bridge::override_cpu_features;
 
 type ExternalFunction<T> = Box<dyn Fn(&[T]) -> T + Send + Sync>;
+type Reference<T> = (&'static str, fn(T) -> T);
 
 fn pass(what: &str) {
     println!("**** test {:?} passed. ****", what);
@@ -112,7 +130,7 @@ fn test_scattered_simd_real_runner() -> Result<()> {
     let runner = CompiledRealRunner::compile(&ev, Config::default())?;
     let app = runner.seal()?;
 
-    let args: Vec<f64> = (0..8).map(|x| f64::from(x)).collect();
+    let args: Vec<f64> = (0..8).map(f64::from).collect();
     let mut outs = [0.0; 4];
     app.evaluate_matrix(&args, &mut outs, 4);
     assert_eq!(&outs, &[1.0, 29.0, 129.0, 349.0]);
@@ -240,13 +258,14 @@ fn test_external_load() -> Result<()> {
     let mut outs: Vec<f64> = vec![0.0; N];
     applet.evaluate(&args, &mut outs);
 
-    for i in 0..N {
-        assert!(f64::abs(outs[i]) < 1e-15);
+    for &out in outs.iter() {
+        assert!(f64::abs(out) < 1e-15);
     }
 
     Ok(())
 }
 
+#[allow(dead_code)]
 fn test_external_func_bytecode() -> Result<()> {
     let params = vec![parse!("x"), parse!("y")];
     let mut f = FunctionMap::new();
@@ -274,8 +293,8 @@ fn test_external_func_bytecode() -> Result<()> {
     let mut outs: Vec<f64> = vec![0.0; N];
     runner.evaluate(&args, &mut outs);
 
-    for i in 0..N {
-        assert!(f64::abs(outs[i] - 1.0) < 1e-14);
+    for &out in outs.iter() {
+        assert!(f64::abs(out - 1.0) < 1e-14);
     }
 
     Ok(())
@@ -314,8 +333,8 @@ fn test_external_func_complex() -> Result<()> {
 
     runner.app.evaluate_matrix(&args, &mut outs, N);
 
-    for i in 0..N {
-        assert!((outs[i] - Complex::new(1.0, 0.0)).abs() < 1e-14);
+    for &out in outs.iter() {
+        assert!((out - Complex::new(1.0, 0.0)).abs() < 1e-14);
     }
 
     Ok(())
@@ -352,8 +371,8 @@ fn test_external_simd_func() -> Result<()> {
     let mut outs: Vec<f64x4> = vec![f64x4::from(0.0); N];
     app.evaluate(&args, &mut outs);
 
-    for i in 0..N {
-        let a = outs[i].as_array();
+    for &out in outs.iter() {
+        let a = out.as_array();
         assert!(a.iter().sum::<f64>().abs() < 1e-13);
     }
 
@@ -388,8 +407,8 @@ fn test_external_simd_complex_func() -> Result<()> {
     let mut outs: Vec<Complex<f64x4>> = vec![Complex::new(f64x4::default(), f64x4::default()); N];
     app.evaluate(&args, &mut outs);
 
-    for i in 0..N {
-        let a = outs[i].re.as_array();
+    for &out in outs.iter() {
+        let a = out.re.as_array();
         assert!(a[0].abs() < 1e-14);
     }
 
@@ -421,8 +440,8 @@ fn test_string_real() -> Result<()> {
     let mut outs = [0.0; N];
     app.evaluate_matrix(&args, &mut outs, N);
 
-    for i in 0..N {
-        let delta = outs[i] - 3.0;
+    for &out in outs.iter() {
+        let delta = out - 3.0;
         assert!(delta.abs() < 1e-15);
     }
 
@@ -458,8 +477,8 @@ fn test_string_complex() -> Result<()> {
     let mut outs = [Complex::default(); N];
     app.evaluate_matrix(&args, &mut outs, N);
 
-    for i in 0..N {
-        let delta = outs[i] - 3.0;
+    for &out in outs.iter() {
+        let delta = out - 3.0;
         assert!(delta.abs() < 1e-14);
     }
 
@@ -530,121 +549,3838 @@ fn test_threads_application() -> Result<()> {
     Ok(())
 }
 
-/* ************************************************** */
+fn test_fp_model_strict() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x*y + x^3 - y/x + sin(x*y) * exp(-y)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
 
-const V_VALUE: &str = "1286387037723327/2500000000000";
-const P_VALUE: &str = "884279719003555/281474976710656";
-const PARAM_NAME: &str = "o";
-const PARAM_VALUE: Complex<f64> = Complex { re: 1.0, im: 0.0 };
+    // an explicit symjit `fastmath` flag is kept unless `set_fp_model` is called
+    let mut config = Config::default();
+    config.set_fastmath(true);
+    assert_eq!(config.fp_model() == FpModel::Fast, config.fastmath());
 
-fn load_expression(path: &Path) -> String {
-    fs::read_to_string(path).unwrap()
+    let mut config = Config::default();
+    assert_eq!(config.fp_model(), FpModel::Strict);
+    config.set_fp_model(FpModel::Strict);
+    let runner = CompiledRealRunner::compile(&ev, config)?;
+
+    const N: usize = 16;
+    let mut rng = rand::rng();
+    let args: Vec<f64> = (0..N * 2).map(|_| rng.random::<f64>() + 0.5).collect();
+
+    // the full batch runs on the SIMD path and single rows on the scalar path
+    let mut outs = vec![0.0; N];
+    runner.evaluate(&args, &mut outs);
+
+    for i in 0..N {
+        let mut out = [0.0];
+        runner.evaluate(&args[i * 2..i * 2 + 2], &mut out);
+        assert_eq!(out[0].to_bits(), outs[i].to_bits());
+    }
+
+    Ok(())
 }
 
-fn parse_complex_rational(src: &str) -> Result<float::Complex<Rational>> {
-    let atom = try_parse!(src).map_err(|e| anyhow!(e))?;
-    float::Complex::<Rational>::try_from(atom.as_view()).map_err(|e| anyhow!(e))
+fn test_robust_runner() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut runner = RobustRealRunner::compile(&ev, Config::default())?;
+    assert!(runner.is_compiled());
+    let mut outs: [f64; 1] = [0.0];
+    runner.evaluate(&[3.0, 5.0], &mut outs);
+    assert_eq!(outs[0], 128.0);
+
+    Ok(())
 }
 
-fn build_evaluator(expression: &str) -> Result<ExpressionEvaluator<Complex<f64>>> {
-    let expr = try_parse!(expression).map_err(|e| anyhow!(e))?;
-    let param = try_parse!(PARAM_NAME).map_err(|e| anyhow!(e))?;
-    let var_v = try_parse!("v").map_err(|e| anyhow!(e))?;
-    let var_p = try_parse!("p").map_err(|e| anyhow!(e))?;
+fn test_robust_runner_fallback() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * y + 1.0/(x * y)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
 
-    let mut fn_map = FunctionMap::new();
-    fn_map.add_constant(var_v, parse_complex_rational(V_VALUE)?);
-    fn_map.add_constant(var_p, parse_complex_rational(P_VALUE)?);
+    // the machine code cannot fit in 16 bytes, but the code size limit does
+    // not apply to the bytecode interpreter
+    let mut config = Config::default();
+    config.set_max_code_bytes(Some(16));
+    assert!(CompiledRealRunner::compile(&ev, config.clone()).is_err());
 
-    Atom::evaluator_multiple(&[expr], &fn_map, &[param], OptimizationSettings::default())
-        .map(|eval| {
-            eval.map_coeff(&|r: &float::Complex<Fraction<IntegerRing>>| {
-                Complex::new(r.re.to_f64(), r.im.to_f64())
-            })
-        })
-        .map_err(|e| anyhow!(e))
+    let mut runner = RobustRealRunner::compile(&ev, config)?;
+    assert!(!runner.is_compiled());
+
+    let mut outs: [f64; 1] = [0.0];
+    runner.evaluate(&[3.0, 5.0], &mut outs);
+    assert!(f64::abs(outs[0] - (15.0 + 1.0 / 15.0)) < 1e-14);
+
+    // an unregistered function cannot be interpreted either
+    let mut f = FunctionMap::new();
+    f.add_external_function(symbol!("nosuchfn"), "nosuchfn".to_string())
+        .unwrap();
+
+    let ev = parse!("nosuchfn(x, y)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    assert!(RobustRealRunner::compile(&ev, Config::default()).is_err());
+
+    Ok(())
 }
 
-fn test_ifelse() -> Result<()> {
-    let expression_source = load_expression(&PathBuf::from("expression.txt"));
-    let input = [PARAM_VALUE];
+fn native_function_map<T>(funcs: &[&str]) -> FunctionMap<T> {
+    let mut f = FunctionMap::new();
 
-    let symjit_eval = build_evaluator(&expression_source)?;
-    // println!("{:?}", symjit_eval.export_instructions());
-    // let app = CompiledComplexRunner::compile(&symjit_eval, config)?.seal()?;
-    let config = Config::default();
-    let mut app = InterpretedComplexRunner::compile(&symjit_eval, config)?;
-    let mut out = vec![Complex::new(0.0, 0.0)];
+    for name in funcs {
+        f.add_external_function(symbol!(*name), name.to_string())
+            .unwrap();
+    }
 
-    app.app.dump("test.bin", "bytecode");
+    f
+}
 
-    app.evaluate(&input, &mut out);
-    println!("ifelse output: {:?}", &out);
+fn compile_native_real(expr: &str, params: &[&str], funcs: &[&str]) -> Result<CompiledRealRunner> {
+    let params: Vec<Atom> = params.iter().map(|p| parse!(*p)).collect();
+    let f = native_function_map(funcs);
+    let ev = parse!(expr)
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut df = Defuns::new();
+    natives::add_real_natives(&mut df)?;
+    CompiledRealRunner::compile_with_funcs(&ev, Config::from_defuns(df)?, 0)
+}
+
+fn compile_native_complex(
+    expr: &str,
+    params: &[&str],
+    funcs: &[&str],
+) -> Result<CompiledComplexRunner> {
+    let params: Vec<Atom> = params.iter().map(|p| parse!(*p)).collect();
+    let f = native_function_map(funcs);
+    let ev = parse!(expr)
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    let mut df = Defuns::new();
+    natives::add_complex_natives(&mut df)?;
+    CompiledComplexRunner::compile_with_funcs(&ev, Config::from_defuns(df)?, 0)
+}
+
+fn eval_real(runner: &CompiledRealRunner, args: &[f64]) -> f64 {
+    let mut outs = [0.0];
+    runner.evaluate(args, &mut outs);
+    outs[0]
+}
+
+fn eval_complex(runner: &CompiledComplexRunner, args: &[Complex<f64>]) -> Complex<f64> {
+    let mut outs = [Complex::default()];
+    runner.evaluate(args, &mut outs);
+    outs[0]
+}
+
+fn test_erf() -> Result<()> {
+    let erf = compile_native_real("erf(x)", &["x"], &["erf"])?;
+    assert_eq!(eval_real(&erf, &[0.0]), 0.0);
+    assert!((eval_real(&erf, &[1.0]) - 0.8427007929497149).abs() < 1e-12);
+    assert!((eval_real(&erf, &[-1.0]) + 0.8427007929497149).abs() < 1e-12);
+
+    let erfc = compile_native_real("erfc(x)", &["x"], &["erfc"])?;
+    assert!((eval_real(&erfc, &[1.0]) - 0.15729920705028513).abs() < 1e-12);
+
+    // the complex versions are named `cerf` and `cerfc`
+    let erf = compile_native_complex("cerf(x)", &["x"], &["cerf"])?;
+    let u = eval_complex(&erf, &[Complex::new(1.0, 0.0)]);
+    assert!((u - Complex::new(0.8427007929497149, 0.0)).abs() < 1e-12);
+    let u = eval_complex(&erf, &[Complex::new(1.0, 1.0)]);
+    assert!((u - Complex::new(1.3161512816979477, 0.1904534692378347)).abs() < 1e-12);
+
+    // beyond |z| = 3, far from and close to the imaginary axis
+    let u = eval_complex(&erf, &[Complex::new(3.0, 2.0)]);
+    assert!((u - Complex::new(0.9989632788568173, -1.1546724379290603e-5)).abs() < 1e-14);
+    let u = eval_complex(&erf, &[Complex::new(0.5, 5.0)]);
+    let v = Complex::new(-6318073744.086766, 1173041985.7103308);
+    assert!((u - v).abs() < 1e-13 * v.abs());
+
+    let erfc = compile_native_complex("cerfc(x)", &["x"], &["cerfc"])?;
+    let u = eval_complex(&erfc, &[Complex::new(4.0, 1.0)]);
+    let v = Complex::new(-1.509629525002696e-8, -3.794032969089071e-8);
+    assert!((u - v).abs() < 1e-13 * v.abs());
 
     Ok(())
 }
 
-/* ************************************************ */
+fn test_gamma() -> Result<()> {
+    let gamma = compile_native_real("tgamma(x)", &["x"], &["tgamma"])?;
+    assert_eq!(eval_real(&gamma, &[5.0]), 24.0);
+    assert!((eval_real(&gamma, &[0.5]) - std::f64::consts::PI.sqrt()).abs() < 1e-14);
+    assert_eq!(eval_real(&gamma, &[0.0]), f64::INFINITY);
+    assert!(eval_real(&gamma, &[-2.0]).is_nan());
 
-pub fn main() -> Result<()> {
-    test_real()?;
-    pass("real");
+    let lgamma = compile_native_real("lgamma(x)", &["x"], &["lgamma"])?;
+    assert!((eval_real(&lgamma, &[10.0]) - 12.801827480081469).abs() < 1e-12);
+    assert_eq!(eval_real(&lgamma, &[-3.0]), f64::INFINITY);
 
-    test_complex()?;
-    pass("complex");
+    Ok(())
+}
 
-    test_real_runner()?;
-    pass("real runner");
+fn test_warmup() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
 
-    test_complex_runner()?;
-    pass("complex runner");
+    // warmup is called before the real data is available
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    runner.warmup();
 
-    test_scattered_simd_real_runner()?;
-    pass("Scattered simd real runner");
+    let args: Vec<f64> = (0..8).map(f64::from).collect();
+    let mut outs = [0.0; 4];
+    runner.evaluate(&args, &mut outs);
+    assert_eq!(&outs, &[1.0, 29.0, 129.0, 349.0]);
 
-    test_scattered_simd_complex_runner()?;
-    pass("Scattered simd complex runner");
+    let mut runner = InterpretedRealRunner::compile(&ev, Config::default())?;
+    runner.warmup();
+    let mut outs = [0.0; 1];
+    runner.evaluate(&[3.0, 5.0], &mut outs);
+    assert_eq!(outs[0], 128.0);
 
-    test_interpreted_real_runner()?;
-    pass("interpreted real runner");
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
 
-    test_interpreted_complex_runner()?;
-    pass("interpreted complex runner");
+    let runner = CompiledComplexRunner::compile(&ev, Config::default())?;
+    runner.warmup();
+    let args = [Complex::new(2.0, 5.0), Complex::new(-2.0, 3.0)];
+    let mut outs = [Complex::new(0.0, 0.0)];
+    runner.evaluate(&args, &mut outs);
+    assert_eq!(outs[0], Complex::new(48.0, 14.0));
 
-    test_external()?;
-    pass("external real runner");
+    Ok(())
+}
 
-    test_external_save()?;
-    test_external_load()?;
-    pass("external func real runner (save/load)");
+fn test_broadcast() -> Result<()> {
+    let params = vec![parse!("x"), parse!("a")];
+    let f = FunctionMap::new();
+    let ev = parse!("a*x")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
 
-    test_external_func_complex()?;
-    pass("external func complex runner");
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
 
-    // test_external_func_bytecode()?;
-    // pass("external func bytecode runner");
-    #[cfg(target_arch = "x86_64")]
-    test_external_simd_func()?;
-    pass("external func simd runner");
+    const N: usize = 11;
+    let xs: Vec<f64> = (0..N).map(|x| x as f64).collect();
+    let outs = runner.evaluate_matrix_broadcast(&xs, 1, &[2.5], N);
+    assert_eq!(outs.len(), N);
 
-    #[cfg(target_arch = "x86_64")]
-    test_external_simd_complex_func()?;
-    pass("external func simd complex runner");
+    for i in 0..N {
+        assert_eq!(outs[i], 2.5 * xs[i]);
+    }
 
-    test_string_real()?;
-    pass("string real runner");
+    Ok(())
+}
 
-    test_string_complex()?;
-    pass("string complex runner");
+fn test_is_complex() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap();
 
-    test_threads_runner()?;
-    pass("threads");
+    let ev_real = ev.clone().map_coeff(&|x| x.re.to_f64());
+    let ev_complex = ev.map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
 
-    test_threads_application()?;
-    pass("threads");
+    assert!(!CompiledRealRunner::compile(&ev_real, Config::default())?.is_complex());
+    assert!(!InterpretedRealRunner::compile(&ev_real, Config::default())?.is_complex());
+    assert!(CompiledComplexRunner::compile(&ev_complex, Config::default())?.is_complex());
+    assert!(InterpretedComplexRunner::compile(&ev_complex, Config::default())?.is_complex());
 
-    test_ifelse()?;
-    pass("ifelse");
+    Ok(())
+}
+
+fn test_validate_instructions() -> Result<()> {
+    let valid = vec![
+        Instruction::Add(Slot::Temp(0), vec![Slot::Param(0), Slot::Const(0)], 0),
+        Instruction::Assign(Slot::Out(0), Slot::Temp(0)),
+    ];
+    assert!(validate_instructions(&valid, 1, 1).is_ok());
+
+    let bad_temp = vec![
+        Instruction::Add(Slot::Temp(999), vec![Slot::Param(0), Slot::Param(1)], 0),
+        Instruction::Assign(Slot::Out(0), Slot::Temp(999)),
+    ];
+    assert!(validate_instructions(&bad_temp, 1, 0).is_err());
+
+    let bad_const = vec![Instruction::Assign(Slot::Out(0), Slot::Const(3))];
+    assert!(validate_instructions(&bad_const, 0, 1).is_err());
+
+    let bad_label = vec![
+        Instruction::IfElse(Slot::Param(0), 7),
+        Instruction::Assign(Slot::Out(0), Slot::Param(1)),
+    ];
+    assert!(validate_instructions(&bad_label, 0, 0).is_err());
+
+    let bad_lhs = vec![
+        Instruction::Add(Slot::Param(0), vec![Slot::Param(0), Slot::Param(1)], 0),
+        Instruction::Assign(Slot::Out(0), Slot::Param(0)),
+    ];
+    assert!(validate_instructions(&bad_lhs, 0, 0).is_err());
+
+    let bad_const_lhs = vec![Instruction::Assign(Slot::Const(0), Slot::Param(0))];
+    assert!(validate_instructions(&bad_const_lhs, 0, 1).is_err());
+
+    Ok(())
+}
+
+fn test_inplace() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 203;
+    let mut rng = rand::rng();
+    let args: Vec<f64> = (0..N * 2).map(|_| rng.random::<f64>()).collect();
+
+    let mut expected = vec![0.0; N];
+    runner.evaluate(&args, &mut expected);
+
+    let mut buf = args.clone();
+    runner.evaluate_inplace(&mut buf, N);
+    assert_eq!(&buf[..N], &expected[..]);
+
+    Ok(())
+}
+
+fn test_log_bases() -> Result<()> {
+    let log10 = compile_native_real("log10(x)", &["x"], &["log10"])?;
+    assert_eq!(eval_real(&log10, &[1000.0]), 3.0);
+    assert_eq!(eval_real(&log10, &[0.01]), -2.0);
+
+    let log2 = compile_native_real("log2(x)", &["x"], &["log2"])?;
+    assert_eq!(eval_real(&log2, &[8.0]), 3.0);
+    assert_eq!(eval_real(&log2, &[0.125]), -3.0);
+
+    let log10 = compile_native_complex("log10(x)", &["x"], &["log10"])?;
+    let u = eval_complex(&log10, &[Complex::new(-100.0, 0.0)]);
+    let pi = std::f64::consts::PI;
+    assert!((u - Complex::new(2.0, pi / 10f64.ln())).abs() < 1e-14);
+
+    let log2 = compile_native_complex("clog2(x)", &["x"], &["clog2"])?;
+    let u = eval_complex(&log2, &[Complex::new(-8.0, 0.0)]);
+    assert!((u - Complex::new(3.0, pi / 2f64.ln())).abs() < 1e-14);
+
+    Ok(())
+}
+
+fn test_compile_many() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+
+    let evs: Vec<ExpressionEvaluator<f64>> = (0..50)
+        .map(|i| {
+            parse!(&format!("x + {}*y^2", i))
+                .evaluator(&f, &params, OptimizationSettings::default())
+                .unwrap()
+                .map_coeff(&|x| x.re.to_f64())
+        })
+        .collect();
+
+    let apps = compile_many(&evs, Config::default())?;
+    assert_eq!(apps.len(), evs.len());
+
+    for (ev, app) in evs.iter().zip(apps.iter()) {
+        let serial = compile(ev, Config::default(), 0)?;
+        let args = [3.0, 4.0];
+        assert_eq!(app.evaluate_single(&args), serial.evaluate_single(&args));
+    }
+
+    assert_eq!(apps[7].evaluate_single(&[3.0, 4.0]), 3.0 + 7.0 * 16.0);
+
+    Ok(())
+}
+
+fn test_atan2() -> Result<()> {
+    let atan2 = compile_native_real("atan2(y, x)", &["y", "x"], &["atan2"])?;
+
+    let points = [
+        (1.0, 1.0),
+        (1.0, -1.0),
+        (-1.0, -1.0),
+        (-1.0, 1.0),
+        (0.0, 1.0),
+        (0.0, -1.0),
+        (-0.0, -1.0),
+        (1.0, 0.0),
+        (-1.0, 0.0),
+        (0.0, 0.0),
+    ];
+
+    for (y, x) in points {
+        let u = eval_real(&atan2, &[y, x]);
+        assert_eq!(u.to_bits(), f64::atan2(y, x).to_bits());
+    }
+
+    let atan2 = compile_native_complex("catan2(y, x)", &["y", "x"], &["catan2"])?;
+    let u = eval_complex(&atan2, &[Complex::new(1.0, 0.0), Complex::new(-1.0, 0.0)]);
+    assert_eq!(u, Complex::new(f64::atan2(1.0, -1.0), 0.0));
+
+    // atan2(y, x) = atan(y / x) for Re(x) > 0
+    let (y, x) = (Complex::new(0.5, 0.25), Complex::new(2.0, -1.0));
+    let u = eval_complex(&atan2, &[y, x]);
+    assert!((u - (y / x).atan()).norm() < 1e-14);
+
+    Ok(())
+}
+
+fn test_validate() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    assert!(validate(&ev, &Config::default()).is_ok());
+
+    // `nosuchfn` is neither a symjit function nor registered in a `Defuns`
+    let mut f = FunctionMap::new();
+    f.add_external_function(symbol!("nosuchfn"), "nosuchfn".to_string())
+        .unwrap();
+
+    let ev = parse!("nosuchfn(x, y)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    assert!(validate(&ev, &Config::default()).is_err());
+
+    // a registered native validates
+    let f = native_function_map(&["lgamma"]);
+    let ev = parse!("lgamma(x) + y")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut df = Defuns::new();
+    natives::add_real_natives(&mut df)?;
+    assert!(validate(&ev, &Config::from_defuns(df)?).is_ok());
+
+    Ok(())
+}
+
+fn test_compile_split() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![
+        parse!("x + y"),
+        parse!("x*y + sin(x + y)"),
+        parse!("x - y^2"),
+    ];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let whole = compile(&ev, Config::default(), 0)?;
+    let split = compile_split(&ev, Config::default())?;
+    assert_eq!(split.len(), 3);
+
+    const N: usize = 9;
+    let mut rng = rand::rng();
+    let args: Vec<f64> = (0..N * 2).map(|_| rng.random::<f64>()).collect();
+
+    let mut expected = vec![0.0; N * 3];
+    whole.evaluate_matrix(&args, &mut expected, N);
+
+    let mut outs = vec![vec![0.0; N]; 3];
+
+    for (k, app) in split.iter().enumerate() {
+        app.evaluate_matrix(&args, &mut outs[k], N);
+    }
+
+    for i in 0..N {
+        for k in 0..3 {
+            assert_eq!(outs[k][i], expected[i * 3 + k]);
+        }
+    }
+
+    // the outputs are compiled through the bridge passes
+    let mut config = Config::default();
+    config.set_input_transform(vec![2.0, 1.0], vec![0.0, 0.5]);
+    config.set_self_check(true);
+    let split = compile_split(&ev, config)?;
+    let mut out = [0.0];
+    split[0].evaluate_matrix(&[1.0, 2.5], &mut out, 1);
+    assert_eq!(out[0], 2.0 + 2.0);
+
+    Ok(())
+}
+
+fn test_hypot() -> Result<()> {
+    let hypot = compile_native_real("hypot(x, y)", &["x", "y"], &["hypot"])?;
+    assert_eq!(eval_real(&hypot, &[3.0, 4.0]), 5.0);
+
+    let u = eval_real(&hypot, &[1e300, 1e300]);
+    assert!(u.is_finite());
+    assert!((u / 1e300 - std::f64::consts::SQRT_2).abs() < 1e-15);
+
+    let naive = compile_native_real("sqrt(x^2 + y^2)", &["x", "y"], &[])?;
+    assert_eq!(eval_real(&naive, &[1e300, 1e300]), f64::INFINITY);
+
+    let hypot = compile_native_complex("hypot(x, y)", &["x", "y"], &["hypot"])?;
+    let u = eval_complex(
+        &hypot,
+        &[Complex::new(3e300, 0.0), Complex::new(0.0, 4e300)],
+    );
+    assert!(u.is_finite());
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn test_write_object() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    let path = std::env::temp_dir().join(format!("test_kernel_{}.o", std::process::id()));
+    let name = path.to_str().unwrap();
+    runner.write_object(name, "test_kernel")?;
+
+    let obj = fs::read(&path)?;
+    fs::remove_file(&path)?;
+    assert_eq!(&obj[..4], b"\x7fELF");
+    assert_eq!(u16::from_le_bytes([obj[16], obj[17]]), 1); // ET_REL
+
+    // the section headers and the symbol table, read as a linker would
+    let u16_at = |p: usize| u16::from_le_bytes(obj[p..p + 2].try_into().unwrap()) as usize;
+    let u32_at = |p: usize| u32::from_le_bytes(obj[p..p + 4].try_into().unwrap()) as usize;
+    let u64_at = |p: usize| u64::from_le_bytes(obj[p..p + 8].try_into().unwrap()) as usize;
+    let c_str = |p: usize| {
+        let end = obj[p..].iter().position(|&b| b == 0).unwrap();
+        std::str::from_utf8(&obj[p..p + end]).unwrap()
+    };
+
+    let (shoff, shnum, shstrndx) = (u64_at(0x28), u16_at(0x3c), u16_at(0x3e));
+    let header = |i: usize| shoff + 64 * i;
+    let section_name = |i: usize| c_str(u64_at(header(shstrndx) + 24) + u32_at(header(i)));
+
+    let text = (0..shnum).find(|&i| section_name(i) == ".text").unwrap();
+    let symtab = (0..shnum).find(|&i| u32_at(header(i) + 4) == 2).unwrap(); // SHT_SYMTAB
+    let strtab = u64_at(header(u32_at(header(symtab) + 40)) + 24);
+
+    let symbols = u64_at(header(symtab) + 24);
+    let count = u64_at(header(symtab) + 32) / 24;
+    let symbol = (0..count)
+        .map(|k| symbols + 24 * k)
+        .find(|&p| c_str(strtab + u32_at(p)) == "test_kernel")
+        .unwrap();
+
+    let code = runner.seal()?.compiled.unwrap().dumps();
+    assert_eq!(obj[symbol + 4], 0x12); // STB_GLOBAL | STT_FUNC
+    assert_eq!(u16_at(symbol + 6), text);
+    assert_eq!(u64_at(symbol + 8), 0);
+    assert_eq!(u64_at(symbol + 16), code.len());
+    assert_eq!(u64_at(header(text) + 32), code.len());
+    assert_eq!(&obj[u64_at(header(text) + 24)..][..code.len()], &code[..]);
+
+    // calls to builtins would need relocations
+    let ev = parse!("sin(x) + y")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    let err = runner.write_object(name, "test_kernel").unwrap_err();
+    assert!(err.to_string().contains("sin"));
+    assert!(!path.exists());
+
+    Ok(())
+}
+
+fn join_reference(cond: f64, y: Complex<f64>) -> Complex<f64> {
+    if cond != 0.0 {
+        y * y + 1.0
+    } else {
+        y * 3.0 - 2.0
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn test_simd_complex_join() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("if(x, y^2 + 1, 3*y - 2)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    // the runner generates the SIMD code on demand; a sealed `Applet` would
+    // only have the scalar code and leave the outputs untouched
+    let runner = CompiledComplexRunner::compile(&ev, Config::default())?;
+
+    // the lanes take different branches
+    let cond = [1.0, 0.0, 0.0, 1.0];
+    let re = [1.0, -0.5, 2.0, 0.25];
+    let im = [2.0, 1.5, -1.0, 0.75];
+
+    let args = [
+        Complex::new(f64x4::from(cond), f64x4::from(0.0)),
+        Complex::new(f64x4::from(re), f64x4::from(im)),
+    ];
+    let mut outs = [Complex::new(f64x4::default(), f64x4::default())];
+    runner.evaluate(&args, &mut outs);
+
+    for lane in 0..4 {
+        let u = Complex::new(outs[0].re.as_array()[lane], outs[0].im.as_array()[lane]);
+        let v = join_reference(cond[lane], Complex::new(re[lane], im[lane]));
+        assert!((u - v).abs() < 1e-14);
+    }
+
+    Ok(())
+}
+
+fn test_scattered_complex_join() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("if(x, y^2 + 1, 3*y - 2)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    let runner = CompiledComplexRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 23;
+    let mut rng = rand::rng();
+    let mut args = vec![Complex::<f64>::default(); N * 2];
+
+    for i in 0..N {
+        args[i * 2] = Complex::new(((i * 7) % 3 == 0) as i32 as f64, 0.0);
+        args[i * 2 + 1] = Complex::new(rng.random::<f64>(), rng.random::<f64>());
+    }
+
+    let mut outs = vec![Complex::<f64>::default(); N];
+    runner.evaluate(&args, &mut outs);
+
+    for i in 0..N {
+        let v = join_reference(args[i * 2].re, args[i * 2 + 1]);
+        assert!((outs[i] - v).abs() < 1e-14);
+    }
+
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn test_interpreted_simd() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("3*x^3 - 2*x*y + y^2 - 5")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let compiled = CompiledRealRunner::compile(&ev, Config::default())?;
+    let interpreted = InterpretedSimdRealRunner::compile(&ev)?;
+
+    const N: usize = 37;
+    let args: Vec<f64x4> = (0..2 * N)
+        .map(|i| {
+            let x = i as f64 * 0.25;
+            f64x4::from([x, -x, 1.0 - x, 2.0 * x])
+        })
+        .collect();
+
+    let mut expected = vec![f64x4::default(); N];
+    let mut outs = vec![f64x4::default(); N];
+    compiled.evaluate(&args, &mut expected);
+    interpreted.evaluate(&args, &mut outs);
+
+    for i in 0..N {
+        let a = outs[i].as_array();
+        let b = expected[i].as_array();
+        for j in 0..4 {
+            assert!((a[j] - b[j]).abs() <= 1e-12 * b[j].abs().max(1.0));
+        }
+    }
+
+    // the f64x2 lanes agree with the scalar interpreter
+    let args2: Vec<f64x2> = (0..2 * N).map(|i| f64x2::from([i as f64, -0.5])).collect();
+    let mut outs2 = vec![f64x2::default(); N];
+    interpreted.evaluate(&args2, &mut outs2);
+
+    for (i, y) in outs2.iter().enumerate() {
+        let mut out = [0.0];
+        interpreted.evaluate(&[i as f64 * 2.0, (2 * i + 1) as f64], &mut out);
+        assert_eq!(y.as_array()[0], out[0]);
+    }
+
+    Ok(())
+}
+
+fn test_complex_args_len() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^2")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    let compiled = CompiledComplexRunner::compile(&ev, Config::default())?;
+    let mut interpreted = InterpretedComplexRunner::compile(&ev, Config::default())?;
+
+    // three complex values do not make a whole number of two-parameter rows
+    let args = [Complex::new(1.0, 0.0); 3];
+    let mut outs = [Complex::new(0.0, 0.0); 2];
+
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        compiled.evaluate(&args, &mut outs);
+    }));
+    assert!(res.is_err());
+
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        interpreted.evaluate(&args, &mut outs);
+    }));
+    assert!(res.is_err());
+
+    // a whole number of rows is still accepted
+    compiled.evaluate(&args[..2], &mut outs[..1]);
+    assert_eq!(outs[0], Complex::new(2.0, 0.0));
+
+    Ok(())
+}
+
+fn test_sincos() -> Result<()> {
+    let params = vec![parse!("x")];
+    let exprs = vec![parse!("sincos_0(x)"), parse!("sincos_1(x)")];
+    let f = native_function_map(&["sincos_0", "sincos_1"]);
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut df = Defuns::new();
+    natives::add_real_natives(&mut df)?;
+    let runner = CompiledRealRunner::compile_with_funcs(&ev, Config::from_defuns(df)?, 0)?;
+
+    for x in [-2.5, -0.0, 0.3, 1.0, 7.25] {
+        let mut outs = [0.0; 2];
+        runner.evaluate(&[x], &mut outs);
+        assert_eq!(outs[0], f64::sin(x));
+        assert_eq!(outs[1], f64::cos(x));
+    }
+
+    Ok(())
+}
+
+fn test_fuse_sincos() -> Result<()> {
+    let f = FunctionMap::new();
+    let ev = parse!("sin(x) + cos(x)")
+        .evaluator(&f, &[parse!("x")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut df = Defuns::new();
+    natives::add_real_natives(&mut df)?;
+    let mut config = Config::from_defuns(df)?;
+
+    let counts = num_instructions(&ev, &config)?;
+    assert_eq!(counts.get("sin"), Some(&1));
+    assert_eq!(counts.get("cos"), Some(&1));
+
+    config.set_fuse_sincos(true);
+    let counts = num_instructions(&ev, &config)?;
+    assert_eq!(counts.get("sin"), None);
+    assert_eq!(counts.get("cos"), None);
+    // one call computes both; the other component is read from its cache
+    assert_eq!(counts.get("sincos_0"), Some(&1));
+    assert_eq!(counts.get("sincos_1"), Some(&1));
+
+    let runner = CompiledRealRunner::compile_with_funcs(&ev, config, 0)?;
+
+    for x in [-2.5, -0.0, 0.3, 1.0, 7.25, 1e6] {
+        let mut outs = [0.0];
+        runner.evaluate(&[x], &mut outs);
+        assert!((outs[0] - (f64::sin(x) + f64::cos(x))).abs() < 1e-14);
+    }
+
+    Ok(())
+}
+
+fn test_fast_rsqrt() -> Result<()> {
+    assert!((natives::rsqrt_nr(4.0) - 0.5).abs() < 1e-10);
+    assert!((natives::rsqrt(2.0) - 0.5f64.sqrt()).abs() < 1e-6);
+
+    let f = FunctionMap::new();
+
+    for expr in ["x^(-1/2) + y", "1/sqrt(x) + y"] {
+        let ev = parse!(expr)
+            .evaluator(
+                &f,
+                &[parse!("x"), parse!("y")],
+                OptimizationSettings::default(),
+            )
+            .unwrap()
+            .map_coeff(&|x| x.re.to_f64());
+
+        let mut df = Defuns::new();
+        natives::add_real_natives(&mut df)?;
+        let mut config = Config::from_defuns(df)?;
+        config.set_fast_rsqrt(Some(1));
+
+        // only applies to the fast model
+        assert_eq!(num_instructions(&ev, &config)?.get("rsqrt_nr"), None);
+
+        config.set_fp_model(FpModel::Fast);
+        assert_eq!(num_instructions(&ev, &config)?.get("rsqrt_nr"), Some(&1));
+
+        let runner = CompiledRealRunner::compile_with_funcs(&ev, config, 0)?;
+        let mut outs = [0.0];
+        runner.evaluate(&[4.0, 1.0], &mut outs);
+        assert!((outs[0] - 1.5).abs() < 1e-10);
+    }
+
+    Ok(())
+}
+
+fn test_compile_str() -> Result<()> {
+    let app = compile_str("x + y^2", &["x", "y"], Config::default())?;
+    assert_eq!(app.evaluate_single(&[3.0, 4.0]), 19.0);
+    assert!(compile_str("x + ", &["x"], Config::default()).is_err());
+    Ok(())
+}
+
+fn test_bridge_error() -> Result<()> {
+    let err = compile_str("x + ", &["x"], Config::default())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<BridgeError>(),
+        Some(BridgeError::Parse(_))
+    ));
+
+    // `nosuchfn` is neither a symjit function nor registered in a `Defuns`
+    let mut f = FunctionMap::new();
+    f.add_external_function(symbol!("nosuchfn"), "nosuchfn".to_string())
+        .unwrap();
+
+    let ev = parse!("nosuchfn(x)")
+        .evaluator(&f, &[parse!("x")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let err = CompiledRealRunner::compile(&ev, Config::default())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<BridgeError>(),
+        Some(BridgeError::Translate(_))
+    ));
+
+    Ok(())
+}
+
+fn test_const_fold() -> Result<()> {
+    let f = FunctionMap::new();
+    let ev = parse!("exp(1) * x")
+        .evaluator(&f, &[parse!("x")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    let before = num_instructions(&ev, &config)?;
+    assert_eq!(before.get("exp"), Some(&1));
+
+    config.set_const_fold(true);
+    let after = num_instructions(&ev, &config)?;
+    assert_eq!(after.get("exp"), None);
+    // the folded constant is forwarded into the product, so no assignment is left
+    assert_eq!(after.get("assign"), None);
+    assert!(after.values().sum::<usize>() < before.values().sum::<usize>());
+
+    let runner = CompiledRealRunner::compile(&ev, config)?;
+    let mut outs = [0.0];
+    runner.evaluate(&[2.0], &mut outs);
+    assert!((outs[0] - 2.0 * std::f64::consts::E).abs() < 1e-15);
+
+    Ok(())
+}
+
+fn test_compile_then() -> Result<()> {
+    let f = FunctionMap::new();
+    let first = parse!("x + 1")
+        .evaluator(&f, &[parse!("x")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+    let next = parse!("y * 2")
+        .evaluator(&f, &[parse!("y")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_complex(false);
+    let app = compile_then(&first, &next, config.clone())?;
+
+    for x in [-3.0, 0.0, 0.5, 10.0] {
+        let mut outs = [0.0];
+        app.evaluate_matrix(&[x], &mut outs, 1);
+        assert_eq!(outs[0], 2.0 * (x + 1.0));
+    }
+
+    // the bridge passes apply to the composed kernel
+    let mut transformed = config.clone();
+    transformed.set_input_transform(vec![2.0], vec![1.0]);
+    transformed.set_self_check(true);
+    let app = compile_then(&first, &next, transformed)?;
+    let mut outs = [0.0];
+    app.evaluate_matrix(&[3.0], &mut outs, 1);
+    assert_eq!(outs[0], 2.0 * ((3.0 - 1.0) * 2.0 + 1.0));
+
+    // a two-output kernel cannot feed a one-parameter kernel
+    let exprs = vec![parse!("x + 1"), parse!("x - 1")];
+    let pair =
+        Atom::evaluator_multiple(&exprs, &f, &[parse!("x")], OptimizationSettings::default())
+            .unwrap()
+            .map_coeff(&|x| x.re.to_f64());
+    assert!(compile_then(&pair, &next, config).is_err());
+
+    Ok(())
+}
+
+fn test_matrix_sum() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![parse!("x * y"), parse!("sin(x) + y^2")];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    const N: usize = 100_003;
+    let args: Vec<f64> = (0..2 * N)
+        .map(|i| ((i * 7919) % 1000) as f64 / 997.0 - 0.5)
+        .collect();
+
+    let mut serial = ThreadedRealRunner::compile(&ev, Config::default())?;
+    serial.set_num_threads(1);
+    let mut threaded = ThreadedRealRunner::compile(&ev, Config::default())?;
+    threaded.set_num_threads(7);
+
+    let s1 = serial.evaluate_matrix_sum(&args, N);
+    let s2 = threaded.evaluate_matrix_sum(&args, N);
+    assert_eq!(s1.len(), 2);
+    assert_eq!(s1[0].to_bits(), s2[0].to_bits());
+    assert_eq!(s1[1].to_bits(), s2[1].to_bits());
+
+    // the sums agree with the materialized output matrix
+    let mut outs = vec![0.0; 2 * N];
+    threaded.evaluate(&args, &mut outs);
+    let naive0: f64 = outs.iter().step_by(2).sum();
+    let naive1: f64 = outs.iter().skip(1).step_by(2).sum();
+    assert!((s1[0] - naive0).abs() < 1e-8 * naive0.abs().max(1.0));
+    assert!((s1[1] - naive1).abs() < 1e-8 * naive1.abs().max(1.0));
+
+    Ok(())
+}
+
+fn test_used_threads() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut runner = ThreadedRealRunner::compile(&ev, Config::default())?;
+    runner.set_num_threads(4);
+    assert!(!runner.last_evaluate_used_threads());
+
+    let args = vec![1.0; 2 * 10];
+    let mut outs = vec![0.0; 10];
+    runner.evaluate(&args, &mut outs);
+    assert!(!runner.last_evaluate_used_threads());
+
+    let args = vec![1.0; 2 * 100_000];
+    let mut outs = vec![0.0; 100_000];
+    runner.evaluate(&args, &mut outs);
+    assert!(runner.last_evaluate_used_threads());
+    assert!(outs.iter().all(|&y| y == 2.0));
+
+    Ok(())
+}
+
+fn test_hyperbolic() -> Result<()> {
+    let real: [Reference<f64>; 6] = [
+        ("sinh", f64::sinh),
+        ("cosh", f64::cosh),
+        ("tanh", f64::tanh),
+        ("asinh", f64::asinh),
+        ("acosh", f64::acosh),
+        ("atanh", f64::atanh),
+    ];
+
+    for (name, f) in real {
+        let runner = compile_native_real(&format!("{}(x)", name), &["x"], &[name])?;
+
+        for x in [-0.75, 0.0, 0.5, 1.5, 3.0] {
+            let y = eval_real(&runner, &[x]);
+            assert!(y == f(x) || (y.is_nan() && f(x).is_nan()));
+        }
+    }
+
+    // atanh near its singularities
+    let atanh = compile_native_real("atanh(x)", &["x"], &["atanh"])?;
+    for x in [1.0 - 1e-12, -1.0 + 1e-12, 1.0, -1.0] {
+        assert_eq!(eval_real(&atanh, &[x]), x.atanh());
+    }
+
+    let complex: [Reference<Complex<f64>>; 6] = [
+        ("sinh", |z| z.sinh()),
+        ("cosh", |z| z.cosh()),
+        ("tanh", |z| z.tanh()),
+        ("asinh", |z| z.asinh()),
+        ("acosh", |z| z.acosh()),
+        ("atanh", |z| z.atanh()),
+    ];
+
+    let points = [
+        Complex::new(0.5, 0.25),
+        Complex::new(-2.0, 1.0),
+        Complex::new(0.0, -3.0),
+        Complex::new(-3.0, 0.0),
+        Complex::new(1.0 - 1e-10, 1e-10),
+        Complex::new(-1.0, -1e-10),
+    ];
+
+    for (name, f) in complex {
+        let runner = compile_native_complex(&format!("{}(x)", name), &["x"], &[name])?;
+
+        for z in points {
+            let w = eval_complex(&runner, &[z]);
+            assert!((w - f(z)).norm() <= 1e-14 * f(z).norm().max(1.0));
+        }
+    }
+
+    // the branch cut of acosh on the negative real axis, approached from above
+    let acosh = compile_native_complex("acosh(x)", &["x"], &["acosh"])?;
+    let w = eval_complex(&acosh, &[Complex::new(-2.0, 0.0)]);
+    assert!((w.im - std::f64::consts::PI).abs() < 1e-14);
+
+    Ok(())
+}
+
+fn test_prepare() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![parse!("x * y"), parse!("x - y")];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let small: Vec<f64> = (0..2 * 10).map(|i| i as f64).collect();
+    let large: Vec<f64> = (0..2 * 10000).map(|i| (i % 17) as f64).collect();
+
+    let mut runner = ThreadedRealRunner::compile(&ev, Config::default())?;
+    runner.set_num_threads(4);
+
+    let expected = |args: &[f64]| -> Vec<f64> {
+        let mut sums = vec![0.0; 2];
+        for row in args.chunks(2) {
+            sums[0] += row[0] * row[1];
+            sums[1] += row[0] - row[1];
+        }
+        sums
+    };
+
+    for _ in 0..3 {
+        for args in [&small, &large] {
+            let sums = runner.evaluate_matrix_sum(args, args.len() / 2);
+            assert_eq!(sums, expected(args));
+        }
+    }
+
+    // a prepared runner reserves the scratch buffers up front, and a large
+    // call does not grow them
+    let mut unprepared = ThreadedRealRunner::compile(&ev, Config::default())?;
+    unprepared.set_num_threads(4);
+    assert_eq!(unprepared.scratch_capacity(), 0);
+    unprepared.evaluate_matrix_sum(&large, 10000);
+    let needed = unprepared.scratch_capacity();
+    assert!(needed > 0);
+
+    let mut prepared = ThreadedRealRunner::compile(&ev, Config::default())?;
+    prepared.set_num_threads(4);
+    prepared.prepare(10000);
+    assert_eq!(prepared.scratch_capacity(), needed);
+    prepared.evaluate_matrix_sum(&large, 10000);
+    assert_eq!(prepared.scratch_capacity(), needed);
+
+    Ok(())
+}
+
+fn test_evaluate_split() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    let compiled = CompiledComplexRunner::compile(&ev, Config::default())?;
+    let mut interpreted = InterpretedComplexRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 150;
+    let args: Vec<Complex<f64>> = (0..2 * N)
+        .map(|i| Complex::new(i as f64 * 0.1 - 3.0, 1.5 - i as f64 * 0.02))
+        .collect();
+    let re: Vec<f64> = args.iter().map(|z| z.re).collect();
+    let im: Vec<f64> = args.iter().map(|z| z.im).collect();
+
+    let mut expected = vec![Complex::default(); N];
+    compiled.evaluate(&args, &mut expected);
+
+    let mut out_re = vec![0.0; N];
+    let mut out_im = vec![0.0; N];
+    compiled.evaluate_split(&re, &im, &mut out_re, &mut out_im);
+
+    for i in 0..N {
+        assert_eq!(Complex::new(out_re[i], out_im[i]), expected[i]);
+    }
+
+    let mut out_re = vec![0.0; N];
+    let mut out_im = vec![0.0; N];
+    interpreted.evaluate_split(&re, &im, &mut out_re, &mut out_im);
+
+    for i in 0..N {
+        let z = Complex::new(out_re[i], out_im[i]);
+        assert!((z - expected[i]).norm() <= 1e-12 * expected[i].norm().max(1.0));
+    }
+
+    Ok(())
+}
+
+fn test_max_code_bytes() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let terms: Vec<String> = (1..200)
+        .map(|i| format!("sin({}*x + y^{})", i, i % 7 + 1))
+        .collect();
+    let ev = parse!(&terms.join(" + "))
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_max_code_bytes(Some(256));
+    assert!(CompiledRealRunner::compile(&ev, config.clone()).is_err());
+
+    // the interpreter is not bound by the limit
+    assert!(InterpretedRealRunner::compile(&ev, config).is_ok());
+
+    let config = Config::default();
+    assert_eq!(config.max_code_bytes(), None);
+    let runner = CompiledRealRunner::compile(&ev, config)?;
+    let mut outs = [0.0];
+    runner.evaluate(&[0.5, 0.25], &mut outs);
+    assert!(outs[0].is_finite());
+
+    Ok(())
+}
+
+fn test_rounding() -> Result<()> {
+    let cases: [(&str, &[(f64, f64)]); 5] = [
+        (
+            "floor",
+            &[(2.5, 2.0), (-2.5, -3.0), (-0.1, -1.0), (3.0, 3.0)],
+        ),
+        (
+            "ceil",
+            &[(2.5, 3.0), (-2.5, -2.0), (0.1, 1.0), (-3.0, -3.0)],
+        ),
+        (
+            "round_away",
+            &[
+                (2.5, 3.0),
+                (-2.5, -3.0),
+                (0.5, 1.0),
+                (1.4, 1.0),
+                (-1.6, -2.0),
+            ],
+        ),
+        (
+            "round_even",
+            &[
+                (2.5, 2.0),
+                (-2.5, -2.0),
+                (0.5, 0.0),
+                (3.5, 4.0),
+                (-1.6, -2.0),
+            ],
+        ),
+        (
+            "trunc",
+            &[(2.7, 2.0), (-2.7, -2.0), (-0.5, 0.0), (5.0, 5.0)],
+        ),
+    ];
+
+    for (name, values) in cases {
+        let runner = compile_native_real(&format!("{}(x)", name), &["x"], &[name])?;
+
+        for &(x, y) in values {
+            assert_eq!(eval_real(&runner, &[x]), y, "{}({})", name, x);
+        }
+    }
+
+    // the symjit builtin `round` rounds half-way cases to even
+    let round = compile_native_real("round(x)", &["x"], &["round"])?;
+    assert_eq!(eval_real(&round, &[2.5]), 2.0);
+    assert_eq!(eval_real(&round, &[-3.5]), -4.0);
+
+    Ok(())
+}
+
+fn test_fd_gradient() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x^2 + y^2")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    for (x, y) in [(1.5, -0.5), (0.0, 2.0), (-3.0, 0.25)] {
+        let h = 1e-4;
+        let grad = runner.evaluate_fd_gradient(&[x, y], h);
+        assert_eq!(grad.len(), 2);
+        assert!((grad[0] - 2.0 * x).abs() < 1e-8);
+        assert!((grad[1] - 2.0 * y).abs() < 1e-8);
+    }
+
+    Ok(())
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn test_flush_denormals() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * y")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let args = [1e-300, 1e-10];
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    let mut outs = [0.0];
+    runner.evaluate(&args, &mut outs);
+    assert!(outs[0] > 0.0 && !outs[0].is_normal());
+
+    let mut config = Config::default();
+    config.set_flush_denormals(true);
+    let runner = CompiledRealRunner::compile(&ev, config.clone())?;
+    runner.evaluate(&args, &mut outs);
+    assert_eq!(outs[0], 0.0);
+
+    // the floating point state is restored after the call
+    assert!(!(args[0] * std::hint::black_box(args[1])).is_normal());
+    assert!(args[0] * std::hint::black_box(args[1]) > 0.0);
+
+    // the worker threads flush too, with symjit's threads and with
+    // `ThreadedRealRunner`
+    const N: usize = 10000;
+    let rows: Vec<f64> = args.iter().copied().cycle().take(2 * N).collect();
+    config.set_threads(true);
+
+    let runner = CompiledRealRunner::compile(&ev, config.clone())?;
+    let mut outs = vec![1.0; N];
+    runner.evaluate(&rows, &mut outs);
+    assert!(outs.iter().all(|&y| y == 0.0));
+
+    let runner = ThreadedRealRunner::compile(&ev, config)?;
+    outs.fill(1.0);
+    runner.evaluate(&rows, &mut outs);
+    assert!(
+        runner.last_evaluate_used_threads() || std::thread::available_parallelism()?.get() == 1
+    );
+    assert!(outs.iter().all(|&y| y == 0.0));
+
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn test_try_evaluate() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    assert_eq!(runner.simd_lanes(), 4);
+
+    let args = [f64x2::from([1.0, 2.0]), f64x2::from([3.0, 4.0])];
+    let mut outs = [f64x2::default()];
+    let err = runner.try_evaluate(&args, &mut outs).unwrap_err();
+    assert!(err.to_string().contains("2-lane"));
+
+    let args = [f64x4::from([1.0, 2.0, 3.0, 4.0]), f64x4::from(2.0)];
+    let mut outs = [f64x4::default()];
+    runner.try_evaluate(&args, &mut outs)?;
+    assert_eq!(outs[0].to_array(), [9.0, 10.0, 11.0, 12.0]);
+
+    // a partial row is rejected too
+    assert!(runner
+        .try_evaluate(&[1.0, 2.0, 3.0], &mut [0.0; 2])
+        .is_err());
+    assert!(runner.try_evaluate(&[1.0, 2.0], &mut []).is_err());
+
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn test_simd_reduce() -> Result<()> {
+    let f = FunctionMap::new();
+    let ev = parse!("x + 1")
+        .evaluator(&f, &[parse!("x")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    let args = [f64x4::from([1.0, 2.0, 3.0, 4.0])];
+
+    assert_eq!(runner.evaluate_simd_reduce(&args, ReduceOp::Sum), 14.0);
+    assert_eq!(runner.evaluate_simd_reduce(&args, ReduceOp::Product), 120.0);
+
+    Ok(())
+}
+
+fn test_numa_replicas() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * exp(y) - y^2")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_complex(false);
+    let app = compile(&ev, config.clone(), 0)?;
+    let replicas = replicate_for_numa(&app, &config)?;
+
+    let nodes = numa_nodes();
+    assert_eq!(replicas.len(), nodes.len());
+
+    if nodes.len() == 1 {
+        println!("single NUMA node; checking the single-copy path only");
+    }
+
+    const N: usize = 37;
+    let args: Vec<f64> = (0..2 * N).map(|i| i as f64 * 0.25 - 4.0).collect();
+    let mut expected = vec![0.0; N];
+    app.evaluate_matrix(&args, &mut expected, N);
+
+    for applet in replicas.iter() {
+        let mut outs = vec![0.0; N];
+        applet.evaluate_matrix(&args, &mut outs, N);
+        assert_eq!(outs, expected);
+    }
+
+    Ok(())
+}
+
+fn test_bool_runner() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = native_function_map(&["gt"]);
+    let ev = parse!("gt(x, y)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut df = Defuns::new();
+    natives::add_real_natives(&mut df)?;
+    let runner = CompiledBoolRunner::compile_with_funcs(&ev, Config::from_defuns(df)?, 0)?;
+
+    let xs = [1.0, 2.0, 3.0, 4.0, -1.0, 0.0, 5.0];
+    let ys = [0.0, 2.0, 4.0, 3.0, -2.0, 1.0, 5.0];
+    let args: Vec<f64> = xs
+        .iter()
+        .zip(ys.iter())
+        .flat_map(|(x, y)| [*x, *y])
+        .collect();
+
+    let mut outs = [false; 7];
+    runner.evaluate(&args, &mut outs);
+    assert_eq!(outs, [true, false, false, true, true, false, false]);
+
+    let masks = runner.evaluate_bitmask(&args, 7);
+    assert_eq!(masks, vec![0b0011001]);
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let args = [
+            f64x4::from([1.0, 2.0, 3.0, 4.0]),
+            f64x4::from([0.0, 2.0, 4.0, 3.0]),
+        ];
+        let mut lanes = [0u8];
+        runner.evaluate_lanes(&args, &mut lanes);
+        assert_eq!(lanes[0], 0b1001);
+    }
+
+    Ok(())
+}
+
+fn test_evaluate_grid() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    let xs = [0.0, 1.0, 2.0];
+    let ys = [10.0, 20.0, 30.0];
+    let grid = runner.evaluate_grid(&[&xs, &ys]);
+
+    assert_eq!(
+        grid,
+        vec![10.0, 20.0, 30.0, 11.0, 21.0, 31.0, 12.0, 22.0, 32.0]
+    );
+
+    Ok(())
+}
+
+fn test_instrument() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * y + 1")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    assert_eq!(runner.estimated_call_count(), None);
+
+    let mut config = Config::default();
+    config.set_simd(false);
+    config.set_instrument(true);
+    let runner = CompiledRealRunner::compile(&ev, config)?;
+    assert_eq!(runner.estimated_call_count(), Some(0));
+
+    let args = vec![1.5; 2 * 100];
+    let mut outs = vec![0.0; 100];
+    runner.evaluate(&args, &mut outs);
+    assert_eq!(runner.estimated_call_count(), Some(100));
+    assert!(outs.iter().all(|y| *y == 3.25));
+
+    // the threaded runner keeps the counters
+    let runner = runner.into_threaded()?;
+    let args = vec![1.5; 2 * 5000];
+    let mut outs = vec![0.0; 5000];
+    runner.evaluate(&args, &mut outs);
+    assert_eq!(runner.estimated_call_count(), Some(5100));
+    assert!(runner.last_call_timestamp().is_some());
+
+    Ok(())
+}
+
+fn test_array_params() -> Result<()> {
+    let specs = [
+        ArrayParam::array(symbol!("w"), 3),
+        ArrayParam::scalar(symbol!("x")),
+    ];
+    let f = FunctionMap::new();
+    let ev = parse!("w(0) + w(1)*x + w(2)*x^2")
+        .evaluator(&f, &array_params(&specs), OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let app = compile_with_array_params(&ev, &specs, Config::default())?;
+    assert_eq!(param_range(&specs, symbol!("w")), Some(0..3));
+    assert_eq!(param_range(&specs, symbol!("x")), Some(3..4));
+    assert_eq!(app.count_params, 4);
+
+    // 1 + 2*2 + 3*2^2
+    assert_eq!(app.evaluate_single(&[1.0, 2.0, 3.0, 2.0]), 17.0);
+
+    Ok(())
+}
+
+fn test_complex_layout() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * y + exp(x) / (y + 2)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    const N: usize = 150;
+    let re: Vec<f64> = (0..2 * N).map(|i| i as f64 * 0.01 - 1.0).collect();
+    let im: Vec<f64> = (0..2 * N).map(|i| 0.5 - i as f64 * 0.003).collect();
+
+    let mut results = Vec::new();
+
+    for layout in [
+        ComplexLayout::Interleaved,
+        ComplexLayout::Planar,
+        ComplexLayout::Auto,
+    ] {
+        let mut config = Config::default();
+        config.set_complex_layout(layout);
+        let runner = CompiledComplexRunner::compile(&ev, config)?;
+        assert_ne!(runner.complex_layout(), ComplexLayout::Auto);
+
+        let mut out_re = vec![0.0; N];
+        let mut out_im = vec![0.0; N];
+        runner.evaluate_split(&re, &im, &mut out_re, &mut out_im);
+        results.push((out_re, out_im));
+    }
+
+    assert_eq!(results[0], results[1]);
+    assert_eq!(results[0], results[2]);
+
+    Ok(())
+}
+
+fn test_phasor_runner() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("exp(x) * y")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    let complex = CompiledComplexRunner::compile(&ev, Config::default())?;
+    let phasor = CompiledPhasorRunner::compile(&ev, Config::default())?;
+
+    // x = i*phi, so the output is y * exp(i*phi)
+    let mut args = Vec::new();
+    for (phi, y) in [(0.3, 1.0), (-2.5, 1.0), (1.0, 3.0), (0.7, 0.0)] {
+        args.push(Complex::new(0.0, phi));
+        args.push(Complex::new(y, 0.0));
+    }
+
+    let mut expected = vec![Complex::default(); 4];
+    complex.evaluate(&args, &mut expected);
+
+    let mut outs = vec![Complex::default(); 4];
+    phasor.evaluate(&args, &mut outs);
+
+    // the unit phasors pass through unchanged
+    for (p, z) in outs.iter().zip(expected.iter()).take(2) {
+        assert!((p.norm() - 1.0).abs() < 1e-15);
+        assert!((*p - *z).norm() < 1e-15);
+    }
+
+    assert!((outs[2].norm() - 1.0).abs() < 1e-15);
+    assert!((outs[2] - expected[2] / 3.0).norm() < 1e-15);
+    assert_eq!(outs[3], Complex::new(1.0, 0.0));
+
+    Ok(())
+}
+
+fn test_polar_runner() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    let complex = CompiledComplexRunner::compile(&ev, Config::default())?;
+    let polar = CompiledPolarRunner::compile(&ev, Config::default())?;
+
+    let args = vec![
+        Complex::new(1.0, 2.0),
+        Complex::new(-0.5, 0.25),
+        Complex::new(0.0, 0.0),
+        Complex::new(0.0, 0.0),
+        Complex::new(-8.0, -0.0),
+        Complex::new(0.0, 0.0),
+        Complex::new(-2.0, 0.0),
+        Complex::new(-1.0, 0.0),
+    ];
+
+    let mut expected = vec![Complex::default(); 4];
+    complex.evaluate(&args, &mut expected);
+
+    let mut outs = vec![0.0; 8];
+    polar.evaluate(&args, &mut outs);
+
+    for (p, z) in outs.chunks(2).zip(expected.iter()) {
+        assert_eq!(p[0], z.norm());
+
+        if z.norm() == 0.0 {
+            assert_eq!(p[1], 0.0);
+        } else if z.im == 0.0 && z.re < 0.0 {
+            assert_eq!(p[1], std::f64::consts::PI);
+        } else {
+            assert_eq!(p[1], z.arg());
+        }
+    }
+
+    Ok(())
+}
+
+fn test_piecewise() -> Result<()> {
+    let expr = Piecewise::new()
+        .case(parse!("lt(x, 0)"), parse!("-x"))
+        .case(parse!("lt(x, 1)"), parse!("x^2"))
+        .otherwise(parse!("1"));
+
+    let params = vec![parse!("x")];
+    let f = native_function_map(&["lt"]);
+    let ev = expr
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut df = Defuns::new();
+    natives::add_real_natives(&mut df)?;
+    let runner = CompiledRealRunner::compile_with_funcs(&ev, Config::from_defuns(df)?, 0)?;
+
+    let reference = |x: f64| {
+        if x < 0.0 {
+            -x
+        } else if x < 1.0 {
+            x * x
+        } else {
+            1.0
+        }
+    };
+
+    // consecutive rows take different branches, so the SIMD lanes disagree
+    let args = [-2.0, 0.5, 3.0, -0.25, 0.0, 1.0, 0.99, -1.0, 2.5];
+    let mut outs = [0.0; 9];
+    runner.evaluate(&args, &mut outs);
+
+    for (x, y) in args.iter().zip(outs.iter()) {
+        assert_eq!(*y, reference(*x));
+    }
+
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn test_required_features() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_simd(true);
+    let runner = CompiledRealRunner::compile(&ev, config.clone())?;
+
+    if !CpuFeature::Avx.is_available() {
+        return Ok(());
+    }
+
+    assert!(runner.required_features().contains(&CpuFeature::Avx));
+    runner.save("test_features.sjb")?;
+    assert!(CompiledRealRunner::load("test_features.sjb", &config).is_ok());
+
+    #[cfg(feature = "test-util")]
+    {
+        override_cpu_features(Some(vec![]));
+        let res = CompiledRealRunner::load("test_features.sjb", &config);
+        override_cpu_features(None);
+
+        let err = res.err().unwrap();
+        assert!(err.to_string().contains("Avx"));
+    }
+
+    std::fs::remove_file("test_features.sjb")?;
+
+    Ok(())
+}
+
+fn test_expm1_log1p() -> Result<()> {
+    let x = 1e-10;
+    // exp(x) - 1 = x + x^2/2 + ... and log(1 + x) = x - x^2/2 + ...
+    let expm1_exact = x + 0.5 * x * x;
+    let log1p_exact = x - 0.5 * x * x;
+
+    let expm1 = compile_native_real("expm1(x)", &["x"], &["expm1"])?;
+    assert!((eval_real(&expm1, &[x]) - expm1_exact).abs() <= 4e-16 * expm1_exact);
+
+    let naive = compile_native_real("exp(x) - 1", &["x"], &[])?;
+    assert!((eval_real(&naive, &[x]) - expm1_exact).abs() > 1e-9 * expm1_exact);
+
+    let log1p = compile_native_real("log1p(x)", &["x"], &["log1p"])?;
+    assert!((eval_real(&log1p, &[x]) - log1p_exact).abs() <= 4e-16 * log1p_exact);
+
+    let expm1 = compile_native_complex("cexpm1(x)", &["x"], &["cexpm1"])?;
+    let z = Complex::new(1e-10, 2e-10);
+    let exact = z + z * z * 0.5;
+    assert!((eval_complex(&expm1, &[z]) - exact).norm() <= 1e-15 * exact.norm());
+
+    let log1p = compile_native_complex("clog1p(x)", &["x"], &["clog1p"])?;
+    let exact = z - z * z * 0.5;
+    assert!((eval_complex(&log1p, &[z]) - exact).norm() <= 1e-15 * exact.norm());
+
+    // away from zero, they agree with the direct formulas
+    let z = Complex::new(0.75, -1.25);
+    assert!((eval_complex(&expm1, &[z]) - (z.exp() - 1.0)).norm() < 1e-14);
+    assert!((eval_complex(&log1p, &[z]) - (z + 1.0).ln()).norm() < 1e-14);
+
+    Ok(())
+}
+
+fn test_pin_threads() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("sin(x) * y + x^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    const N: usize = 50_000;
+    let args: Vec<f64> = (0..2 * N).map(|i| (i as f64).sqrt() * 0.01).collect();
+
+    let mut free = ThreadedRealRunner::compile(&ev, Config::default())?;
+    free.set_num_threads(4);
+
+    let mut config = Config::default();
+    config.set_pin_threads(true);
+    let mut pinned = ThreadedRealRunner::compile(&ev, config)?;
+    pinned.set_num_threads(4);
+
+    let mut outs1 = vec![0.0; N];
+    let mut outs2 = vec![0.0; N];
+    free.evaluate(&args, &mut outs1);
+    pinned.evaluate(&args, &mut outs2);
+    assert_eq!(outs1, outs2);
+
+    let s1 = free.evaluate_matrix_sum(&args, N);
+    let s2 = pinned.evaluate_matrix_sum(&args, N);
+    assert_eq!(s1, s2);
+
+    Ok(())
+}
+
+fn test_output_layout() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![parse!("x + y"), parse!("x * y"), parse!("x - y")];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 101;
+    let args: Vec<f64> = (0..2 * N).map(|i| i as f64 * 0.5 - 7.0).collect();
+
+    let mut row_major = vec![0.0; 3 * N];
+    let mut col_major = vec![0.0; 3 * N];
+    runner.evaluate_matrix_layout(&args, &mut row_major, N, OutputLayout::RowMajor);
+    runner.evaluate_matrix_layout(&args, &mut col_major, N, OutputLayout::ColumnMajor);
+
+    for i in 0..N {
+        let (x, y) = (args[2 * i], args[2 * i + 1]);
+        assert_eq!(row_major[3 * i], x + y);
+        assert_eq!(row_major[3 * i + 1], x * y);
+        assert_eq!(row_major[3 * i + 2], x - y);
+
+        for j in 0..3 {
+            assert_eq!(col_major[j * N + i], row_major[3 * i + j]);
+        }
+    }
+
+    Ok(())
+}
+
+fn test_evaluate_rows() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![parse!("x + y"), parse!("x * y")];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    let mut out = Vec::new();
+
+    for n in [0, 1, 3, 64, 65, 150] {
+        let rows: Vec<Vec<f64>> = (0..n)
+            .map(|i| vec![i as f64, 0.5 * i as f64 - 3.0])
+            .collect();
+        let start = out.len();
+        runner.evaluate_rows(rows.iter().map(|r| r.as_slice()), &mut out)?;
+        assert_eq!(out.len(), start + 2 * n);
+
+        for (i, r) in rows.iter().enumerate() {
+            assert_eq!(out[start + 2 * i], r[0] + r[1]);
+            assert_eq!(out[start + 2 * i + 1], r[0] * r[1]);
+        }
+    }
+
+    let len = out.len();
+    let rows = [vec![1.0, 2.0], vec![3.0], vec![4.0, 5.0]];
+    assert!(runner
+        .evaluate_rows(rows.iter().map(|r| r.as_slice()), &mut out)
+        .is_err());
+    assert_eq!(out.len(), len);
+
+    Ok(())
+}
+
+fn test_self_check() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+
+    assert!(!Config::default().self_check());
+
+    let mut config = Config::default();
+    config.set_self_check(true);
+
+    let ev = parse!("x^3 + exp(y) * sin(x*y) - 2/(3 + x^2)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+    let runner = CompiledRealRunner::compile(&ev, config.clone())?;
+    let mut outs = [0.0];
+    runner.evaluate(&[0.5, -0.5], &mut outs);
+
+    let ev = parse!("x^3 + exp(y) * sin(x*y) - 2/(3 + x^2)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+    CompiledComplexRunner::compile(&ev, config)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "arbitrary-precision")]
+fn test_precision() -> Result<()> {
+    let f = FunctionMap::new();
+    let ev = parse!("(1 - cos(x)) / x^2")
+        .evaluator(&f, &[parse!("x")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut plain = InterpretedRealRunner::compile(&ev, Config::default())?;
+    let mut precise = InterpretedRealRunner::compile_with_precision(&ev, Config::default(), 256)?;
+    assert_eq!(plain.precision(), 53);
+    assert_eq!(precise.precision(), 256);
+
+    // cos(1e-8) rounds to 1 in f64, so the f64 result is lost to cancellation
+    let mut a = [0.0];
+    let mut b = [0.0];
+    plain.evaluate(&[1e-8], &mut a);
+    precise.evaluate(&[1e-8], &mut b);
+
+    assert!((b[0] - 0.5).abs() < 1e-12);
+    assert!((a[0] - 0.5).abs() > (b[0] - 0.5).abs());
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn test_repl() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_complex(false);
+    let app = compile(&ev, config, 0)?;
+
+    let input = "3 5\n\n1 2\nfoo 1\n1 2 3\n0.5 -1\n";
+    let mut output: Vec<u8> = Vec::new();
+    symjit_bridge::run_repl(&app, input.as_bytes(), &mut output)?;
+
+    let output = String::from_utf8(output)?;
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], "128");
+    assert_eq!(lines[1], "9");
+    assert!(lines[2].starts_with("error:"));
+    assert!(lines[3].starts_with("error:"));
+    assert_eq!(lines[4], "-0.5");
+
+    Ok(())
+}
+
+#[cfg(feature = "nalgebra")]
+fn test_nalgebra() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x + y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 37;
+    let args = DMatrix::<f64>::from_fn(2, N, |i, j| (i + 2 * j) as f64 * 0.1);
+    let outs = runner.evaluate_nalgebra(&args);
+    assert_eq!((outs.nrows(), outs.ncols()), (1, N));
+
+    let mut expected = vec![0.0; N];
+    runner.evaluate(args.as_slice(), &mut expected);
+
+    for j in 0..N {
+        assert_eq!(outs[(0, j)], expected[j]);
+    }
+
+    // the outputs go through the same post-processing as `evaluate`
+    runner.map_outputs(|y| y[0] = -y[0]);
+    let outs = runner.evaluate_nalgebra(&args);
+
+    for j in 0..N {
+        assert_eq!(outs[(0, j)], -expected[j]);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "test-util")]
+fn test_assert_close() -> Result<()> {
+    use symjit_bridge::test_util::{all_close, assert_close, is_close};
+
+    let a = [Complex::new(1.0, 2.0), Complex::new(-3.0, 0.5)];
+    let b = [
+        Complex::new(1.0 + 1e-15, 2.0),
+        Complex::new(-3.0, 0.5 - 1e-15),
+    ];
+
+    assert_ne!(a, b);
+    assert_close(&a, &b, 1e-14, 1e-14);
+    assert!(!all_close(&a, &b, 1e-16, 0.0));
+    assert!(is_close(&1.0, &(1.0 + 1e-15), 0.0, 1e-14));
+    assert!(!is_close(&f64::NAN, &f64::NAN, 1.0, 1.0));
+
+    Ok(())
+}
+
+/* ************************************************** */
+
+const V_VALUE: &str = "1286387037723327/2500000000000";
+const P_VALUE: &str = "884279719003555/281474976710656";
+const PARAM_NAME: &str = "o";
+const PARAM_VALUE: Complex<f64> = Complex { re: 1.0, im: 0.0 };
+
+fn load_expression(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+fn parse_complex_rational(src: &str) -> Result<float::Complex<Rational>> {
+    let atom = try_parse!(src).map_err(|e| anyhow!(e))?;
+    float::Complex::<Rational>::try_from(atom.as_view()).map_err(|e| anyhow!(e))
+}
+
+fn build_evaluator(expression: &str) -> Result<ExpressionEvaluator<Complex<f64>>> {
+    let expr = try_parse!(expression).map_err(|e| anyhow!(e))?;
+    let param = try_parse!(PARAM_NAME).map_err(|e| anyhow!(e))?;
+    let var_v = try_parse!("v").map_err(|e| anyhow!(e))?;
+    let var_p = try_parse!("p").map_err(|e| anyhow!(e))?;
+
+    let mut fn_map = FunctionMap::new();
+    fn_map.add_constant(var_v, parse_complex_rational(V_VALUE)?);
+    fn_map.add_constant(var_p, parse_complex_rational(P_VALUE)?);
+
+    Atom::evaluator_multiple(&[expr], &fn_map, &[param], OptimizationSettings::default())
+        .map(|eval| {
+            eval.map_coeff(&|r: &float::Complex<Fraction<IntegerRing>>| {
+                Complex::new(r.re.to_f64(), r.im.to_f64())
+            })
+        })
+        .map_err(|e| anyhow!(e))
+}
+
+fn test_ifelse() -> Result<()> {
+    let expression_source = load_expression(&PathBuf::from("expression.txt"));
+    let input = [PARAM_VALUE];
+
+    let symjit_eval = build_evaluator(&expression_source)?;
+    // println!("{:?}", symjit_eval.export_instructions());
+    // let app = CompiledComplexRunner::compile(&symjit_eval, config)?.seal()?;
+    let config = Config::default();
+    let mut app = InterpretedComplexRunner::compile(&symjit_eval, config)?;
+    let mut out = vec![Complex::new(0.0, 0.0)];
+
+    app.app.dump("test.bin", "bytecode");
+
+    app.evaluate(&input, &mut out);
+    println!("ifelse output: {:?}", &out);
+
+    Ok(())
+}
+
+/* ************************************************ */
+
+fn test_vmath() -> Result<()> {
+    // the vectorized exp is within 2 ULP of the scalar exp
+    for i in -1400..=1400 {
+        let x = i as f64 * 0.5 + 0.123;
+        let xs = [x, x * 1e-3, -x * 1e-6, x.sin()];
+        let ys = vmath::exp(f64x4::from(xs)).to_array();
+        for j in 0..4 {
+            let y = xs[j].exp();
+            assert!((ys[j] - y).abs() <= 2.0 * f64::EPSILON * y);
+        }
+    }
+
+    let ys = vmath::exp(f64x4::from([800.0, -800.0, f64::NAN, 0.0])).to_array();
+    assert_eq!(ys[0], f64::INFINITY);
+    assert_eq!(ys[1], 0.0);
+    assert!(ys[2].is_nan());
+    assert_eq!(ys[3], 1.0);
+
+    // FpModel::Fast uses the vectorized builtins in the SIMD interpreter
+    let params = vec![parse!("x")];
+    let f = FunctionMap::new();
+    let ev = parse!("exp(x) + log(x) * sin(x) - cos(x)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_fp_model(FpModel::Fast);
+    let fast = InterpretedSimdRealRunner::compile_with_config(&ev, &config)?;
+    let strict = InterpretedSimdRealRunner::compile(&ev)?;
+
+    let args: Vec<f64x4> = (1..20)
+        .map(|i| f64x4::from([i as f64, 0.1 * i as f64, 3.7 * i as f64, 1e-3 * i as f64]))
+        .collect();
+    let mut a = vec![f64x4::default(); args.len()];
+    let mut b = vec![f64x4::default(); args.len()];
+    fast.evaluate(&args, &mut a);
+    strict.evaluate(&args, &mut b);
+
+    for (a, b) in a.iter().zip(b.iter()) {
+        for (a, b) in a.to_array().iter().zip(b.to_array().iter()) {
+            assert!((a - b).abs() <= 1e-13 * b.abs().max(1.0));
+        }
+    }
+
+    Ok(())
+}
+
+fn test_to_dot() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("exp(x*y) + sin(x*y)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let dot = to_dot(&ev, &Config::default())?;
+    assert!(dot.starts_with("digraph"));
+
+    // the shared x*y node feeds both exp and sin
+    let mul = dot
+        .lines()
+        .find(|l| l.contains("label=\"mul\""))
+        .and_then(|l| l.split_whitespace().next())
+        .ok_or_else(|| anyhow!("no mul node"))?;
+    let edges = format!("{} -> ", mul);
+    assert_eq!(
+        dot.lines().filter(|l| l.trim().starts_with(&edges)).count(),
+        2
+    );
+    assert!(dot.contains("label=\"out 0\""));
+
+    Ok(())
+}
+
+fn test_input_transform() -> Result<()> {
+    let f = FunctionMap::new();
+    let ev = parse!("x")
+        .evaluator(&f, &[parse!("x")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_input_transform(vec![2.0], vec![1.0]);
+    let runner = CompiledRealRunner::compile(&ev, config)?;
+
+    for x in [-3.0, 0.0, 1.0, 2.5] {
+        let mut outs = [0.0];
+        runner.evaluate(&[x], &mut outs);
+        assert_eq!(outs[0], (x - 1.0) * 2.0);
+    }
+
+    // one scale and one offset per parameter
+    let ev = parse!("x * y")
+        .evaluator(
+            &f,
+            &[parse!("x"), parse!("y")],
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_input_transform(vec![2.0], vec![1.0]);
+    assert!(CompiledRealRunner::compile(&ev, config).is_err());
+
+    let mut config = Config::default();
+    config.set_input_transform(vec![2.0, 0.5], vec![1.0, -4.0]);
+    let runner = CompiledRealRunner::compile(&ev, config)?;
+    let mut outs = [0.0];
+    runner.evaluate(&[3.0, 2.0], &mut outs);
+    assert_eq!(outs[0], 12.0);
+
+    Ok(())
+}
+
+fn test_accurate_sum() -> Result<()> {
+    const N: usize = 64;
+    let names: Vec<String> = (0..N).map(|i| format!("x{}", i)).collect();
+    let params: Vec<Atom> = names.iter().map(|x| parse!(x)).collect();
+    let f = FunctionMap::new();
+    let ev = parse!(&names.join(" + "))
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    // the order of the terms in the Add instruction
+    let (instructions, _, _) = ev.export_instructions();
+    let order: Vec<usize> = instructions
+        .iter()
+        .find_map(|q| match q {
+            Instruction::Add(_, args, _) if args.len() == N => Some(
+                args.iter()
+                    .filter_map(|s| match s {
+                        Slot::Param(i) => Some(*i),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no {}-term add", N))?;
+
+    // a large term first, cancelled by the last one, with ones in between
+    let mut args = vec![1.0; N];
+    args[order[0]] = 1e16;
+    args[order[N - 1]] = -1e16;
+    let exact = (N - 2) as f64;
+    let naive = order.iter().fold(0.0, |acc, &i| acc + args[i]);
+
+    let mut config = Config::default();
+    config.set_accurate_sum(true);
+    let runner = CompiledRealRunner::compile(&ev, config)?;
+    let mut outs = [0.0];
+    runner.evaluate(&args, &mut outs);
+
+    assert!((outs[0] - exact).abs() < (naive - exact).abs());
+
+    Ok(())
+}
+
+fn test_entry_ptr() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * y + sin(x)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 11;
+    let args: Vec<f64> = (0..2 * N).map(|i| i as f64 * 0.3 - 2.0).collect();
+    let mut expected = vec![0.0; N];
+    let mut outs = vec![0.0; N];
+    runner.evaluate(&args, &mut expected);
+
+    let entry: EntryFn = unsafe { std::mem::transmute(runner.entry_ptr()) };
+    let status = unsafe { entry(runner.context_ptr(), args.as_ptr(), outs.as_mut_ptr(), N) };
+    assert_eq!(status, 0);
+    assert_eq!(outs, expected);
+
+    // a panic is reported to the caller instead of unwinding into it
+    let mut runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    runner.map_outputs(|_| panic!("map_outputs failed"));
+    let entry: EntryFn = unsafe { std::mem::transmute(runner.entry_ptr()) };
+    let status = unsafe { entry(runner.context_ptr(), args.as_ptr(), outs.as_mut_ptr(), N) };
+    assert_eq!(status, -1);
+
+    #[cfg(feature = "capi")]
+    {
+        use std::ffi::CString;
+        use symjit_bridge::capi;
+
+        let expr = CString::new("x * y + sin(x)")?;
+        let names = [CString::new("x")?, CString::new("y")?];
+        let ptrs: Vec<_> = names.iter().map(|s| s.as_ptr()).collect();
+
+        unsafe {
+            let runner = capi::symjit_bridge_compile(expr.as_ptr(), ptrs.as_ptr(), 2);
+            assert!(!runner.is_null());
+            assert_eq!(capi::symjit_bridge_count_params(runner), 2);
+
+            let mut outs = vec![0.0; N];
+            let status = capi::symjit_bridge_evaluate(runner, args.as_ptr(), outs.as_mut_ptr(), N);
+            assert_eq!(status, 0);
+            assert_eq!(outs, expected);
+            capi::symjit_bridge_free(runner);
+        }
+    }
+
+    Ok(())
+}
+
+fn test_evaluate_masked() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let exprs = vec![parse!("x * y"), parse!("x - y")];
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 150;
+    let args: Vec<f64> = (0..2 * N).map(|i| i as f64 * 0.5).collect();
+    let mask: Vec<bool> = (0..N).map(|i| i % 3 != 1).collect();
+    let mut outs = vec![-1.0; 2 * N];
+    runner.evaluate_masked(&args, &mut outs, &mask, N);
+
+    for i in 0..N {
+        let (x, y) = (args[2 * i], args[2 * i + 1]);
+        if mask[i] {
+            assert_eq!(outs[2 * i], x * y);
+            assert_eq!(outs[2 * i + 1], x - y);
+        } else {
+            assert_eq!(outs[2 * i], -1.0);
+            assert_eq!(outs[2 * i + 1], -1.0);
+        }
+    }
+
+    Ok(())
+}
+
+fn test_fmod() -> Result<()> {
+    let fmod = compile_native_real("fmod(x, y)", &["x", "y"], &["fmod"])?;
+    assert_eq!(eval_real(&fmod, &[7.0, 3.0]), 1.0);
+    assert_eq!(eval_real(&fmod, &[-7.0, 3.0]), -1.0);
+    assert_eq!(eval_real(&fmod, &[7.0, -3.0]), 1.0);
+    assert_eq!(eval_real(&fmod, &[5.5, 2.0]), 1.5);
+    assert!(eval_real(&fmod, &[1.0, 0.0]).is_nan());
+
+    let remainder = compile_native_real("remainder(x, y)", &["x", "y"], &["remainder"])?;
+    assert_eq!(eval_real(&remainder, &[7.0, 3.0]), 1.0);
+    assert_eq!(eval_real(&remainder, &[8.0, 3.0]), -1.0);
+    assert_eq!(eval_real(&remainder, &[-7.0, 3.0]), -1.0);
+    assert_eq!(eval_real(&remainder, &[5.0, 2.0]), 1.0);
+
+    Ok(())
+}
+
+fn test_threaded_column_major() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![
+        parse!("x + y"),
+        parse!("x * y"),
+        parse!("sin(x) - y"),
+        parse!("x^2 + y^3"),
+    ];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut runner = ThreadedRealRunner::compile(&ev, Config::default())?;
+    runner.set_num_threads(4);
+
+    const N: usize = 100_000;
+    let args: Vec<f64> = (0..2 * N).map(|i| (i % 1000) as f64 * 1e-3 - 0.5).collect();
+    let mut rows = vec![0.0; 4 * N];
+    let mut cols = vec![0.0; 4 * N];
+
+    runner.evaluate(&args, &mut rows);
+    runner.evaluate_matrix_layout(&args, &mut cols, N, OutputLayout::ColumnMajor);
+    assert!(runner.last_evaluate_used_threads());
+
+    for i in 0..N {
+        for j in 0..4 {
+            assert_eq!(cols[j * N + i], rows[i * 4 + j]);
+        }
+    }
+
+    let mut again = vec![0.0; 4 * N];
+    runner.evaluate_matrix_layout(&args, &mut again, N, OutputLayout::RowMajor);
+    assert_eq!(again, rows);
+
+    Ok(())
+}
+
+fn test_temp_count() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let config = Config::default();
+
+    let flat = parse!("x + y")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let nested =
+        parse!("sin(cos(exp(x*y) + x) * y) + cos(x + y) * sin(x - y) / (1 + exp(x^2 - y))")
+            .evaluator(&f, &params, OptimizationSettings::default())
+            .unwrap()
+            .map_coeff(&|x| x.re.to_f64());
+
+    assert!(temp_count(&nested, &config)? > temp_count(&flat, &config)?);
+
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn test_complex_f64x2_layout() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x - y^2")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    let runner = CompiledComplexRunner::compile(&ev, Config::default())?;
+
+    // 7 rows of f64x2, i.e., 14 complex rows, against the same rows as f64x4
+    const N: usize = 7;
+    let value = |r: usize, k: usize| Complex::new(r as f64 * 0.5 - k as f64, 1.0 + r as f64 * 0.25);
+    let args2: Vec<Complex<f64x2>> = (0..N * 2)
+        .map(|i| {
+            let (p, k) = (i / 2, i % 2);
+            let (a, b) = (value(2 * p, k), value(2 * p + 1, k));
+            Complex::new(f64x2::from([a.re, b.re]), f64x2::from([a.im, b.im]))
+        })
+        .collect();
+    let mut outs2 = vec![Complex::new(f64x2::default(), f64x2::default()); N];
+    runner.evaluate_f64x2_layout(&args2, &mut outs2)?;
+    assert!(runner
+        .evaluate_f64x2_layout(&args2[..3], &mut outs2)
+        .is_err());
+
+    let m = (2 * N).div_ceil(4);
+    let args4: Vec<Complex<f64x4>> = (0..m * 2)
+        .map(|i| {
+            let (p, k) = (i / 2, i % 2);
+            let z: Vec<Complex<f64>> = (0..4).map(|l| value(4 * p + l, k)).collect();
+            Complex::new(
+                f64x4::from([z[0].re, z[1].re, z[2].re, z[3].re]),
+                f64x4::from([z[0].im, z[1].im, z[2].im, z[3].im]),
+            )
+        })
+        .collect();
+    let mut outs4 = vec![Complex::new(f64x4::default(), f64x4::default()); m];
+    runner.evaluate(&args4, &mut outs4);
+
+    for r in 0..2 * N {
+        let (re2, im2) = (outs2[r / 2].re.to_array(), outs2[r / 2].im.to_array());
+        let (re4, im4) = (outs4[r / 4].re.to_array(), outs4[r / 4].im.to_array());
+        assert_eq!(re2[r % 2], re4[r % 4]);
+        assert_eq!(im2[r % 2], im4[r % 4]);
+    }
+
+    Ok(())
+}
+
+fn test_dedup_constants() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("2.75 * x + sin(2.75 * y + 2) + 2.75 / (x + 2)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let pool = constants(&ev, &Config::default())?;
+    assert_eq!(pool.iter().filter(|z| z.re == 2.75).count(), 1);
+    assert_eq!(pool.iter().filter(|z| z.re == 2.0).count(), 1);
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    let (x, y) = (0.5, -1.5);
+    let mut outs = [0.0];
+    runner.evaluate(&[x, y], &mut outs);
+    let expected = 2.75 * x + f64::sin(2.75 * y + 2.0) + 2.75 / (x + 2.0);
+    assert!((outs[0] - expected).abs() < 1e-14);
+
+    Ok(())
+}
+
+fn test_compile_derivative() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let expr = parse!("x^4 + x*y");
+
+    let app = compile_derivative(&expr, &params, 0, 2, Config::default())?;
+    for x in [-2.0, 0.5, 3.0] {
+        assert_eq!(app.evaluate_single(&[x, 7.0]), 12.0 * x * x);
+    }
+
+    // the order 0 is the expression itself
+    let app = compile_derivative(&expr, &params, 0, 0, Config::default())?;
+    assert_eq!(app.evaluate_single(&[2.0, 3.0]), 22.0);
+    let app = compile_derivative(&expr, &params, 1, 1, Config::default())?;
+    assert_eq!(app.evaluate_single(&[2.0, 3.0]), 2.0);
+
+    assert!(compile_derivative(&expr, &params, 2, 1, Config::default()).is_err());
+    assert!(compile_derivative(&expr, &params, 0, 100, Config::default()).is_err());
+
+    Ok(())
+}
+
+fn test_value_and_gradient() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let expr = parse!("x^2*y");
+
+    let app = compile_value_and_gradient(&expr, &params, &[0, 1], Config::default())?;
+    let (x, y) = (3.0, -2.0);
+    let mut outs = [0.0; 3];
+    app.evaluate_matrix(&[x, y], &mut outs, 1);
+    assert_eq!(outs, [x * x * y, 2.0 * x * y, x * x]);
+
+    // the combined kernel shares the subexpressions of the value and the partials
+    let config = Config::default();
+    let total = |ev: &ExpressionEvaluator<f64>| -> Result<usize> {
+        Ok(num_instructions(ev, &config)?.values().sum())
+    };
+    let f = FunctionMap::new();
+
+    let combined = value_and_gradient_evaluator(&expr, &params, &[0, 1])?;
+    let value = expr
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+    let partials = vec![parse!("2*x*y"), parse!("x^2")];
+    let gradient =
+        Atom::evaluator_multiple(&partials, &f, &params, OptimizationSettings::default())
+            .unwrap()
+            .map_coeff(&|x| x.re.to_f64());
+
+    assert!(total(&combined)? < total(&value)? + total(&gradient)?);
+
+    // d/dx sqrt(x^2 + y) = x / sqrt(x^2 + y) and d/dy = 1 / (2 sqrt(x^2 + y))
+    let expr = parse!("sqrt(x^2 + y) * y");
+    let app = compile_value_and_gradient(&expr, &params, &[0, 1], Config::default())?;
+    let (x, y) = (3.0, 7.0);
+    let r = f64::sqrt(x * x + y);
+    app.evaluate_matrix(&[x, y], &mut outs, 1);
+    let expected = [r * y, x * y / r, r + y / (2.0 * r)];
+    for (u, v) in outs.iter().zip(expected) {
+        assert!((u - v).abs() < 1e-12 * v.abs());
+    }
+
+    let app = compile_derivative(&expr, &params, 1, 2, Config::default())?;
+    let mut out = [0.0];
+    app.evaluate_matrix(&[x, y], &mut out, 1);
+    let expected = 1.0 / r - y / (4.0 * r * r * r);
+    assert!((out[0] - expected).abs() < 1e-12 * expected.abs());
+
+    Ok(())
+}
+
+fn test_zero_params() -> Result<()> {
+    let f = FunctionMap::new();
+    let ev = parse!("5")
+        .evaluator(&f, &[parse!("x")], OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let err = CompiledRealRunner::compile(&ev, Config::default())
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("no parameters"));
+    assert!(InterpretedRealRunner::compile(&ev, Config::default()).is_err());
+
+    // a constant kernel with its number of parameters given explicitly
+    let runner = CompiledRealRunner::compile_with_funcs(&ev, Config::default(), 1)?;
+    let mut outs = [0.0; 3];
+    runner.evaluate(&[1.0, 2.0, 3.0], &mut outs);
+    assert_eq!(outs, [5.0; 3]);
+
+    Ok(())
+}
+
+fn test_scalars() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * y")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap();
+
+    let complex = CompiledComplexRunner::compile(
+        &ev.clone()
+            .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64())),
+        Config::default(),
+    )?;
+    let mut args = Scalars::complex(2);
+    let mut outs = Scalars::complex(1);
+    assert!(args.is_complex());
+    args.as_flat_mut().copy_from_slice(&[1.0, 2.0, 3.0, -1.0]);
+    complex.evaluate(args.as_complex().unwrap(), outs.as_complex_mut().unwrap());
+    assert_eq!(outs.as_flat(), &[5.0, 5.0]);
+
+    let real = CompiledRealRunner::compile(&ev.map_coeff(&|x| x.re.to_f64()), Config::default())?;
+    let mut args = Scalars::real(2);
+    let mut outs = Scalars::real(1);
+    args.as_flat_mut().copy_from_slice(&[3.0, 4.0]);
+    real.evaluate(args.as_real().unwrap(), outs.as_real_mut().unwrap());
+    assert_eq!(outs.as_flat(), &[12.0]);
+    assert!(args.as_complex().is_none());
+
+    Ok(())
+}
+
+fn test_cbrt() -> Result<()> {
+    let cbrt = compile_native_real("cbrt(x)", &["x"], &["cbrt"])?;
+    assert_eq!(eval_real(&cbrt, &[-8.0]), -2.0);
+    assert_eq!(eval_real(&cbrt, &[27.0]), 3.0);
+    assert_eq!(eval_real(&cbrt, &[0.0]), 0.0);
+
+    let cbrt = compile_native_complex("cbrt(x)", &["x"], &["cbrt"])?;
+    let z = eval_complex(&cbrt, &[Complex::new(-8.0, 0.0)]);
+    assert!((z - Complex::new(1.0, 3f64.sqrt())).norm() < 1e-12);
+    let z = eval_complex(&cbrt, &[Complex::new(8.0, 0.0)]);
+    assert_eq!(z, Complex::new(2.0, 0.0));
+    let z = eval_complex(&cbrt, &[Complex::new(0.0, 8.0)]);
+    assert!((z - Complex::new(3f64.sqrt(), 1.0)).norm() < 1e-12);
+
+    Ok(())
+}
+
+fn test_recompile() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * y + sin(x) - y^3")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let app = compile_retained(&ev, Config::default(), 0)?;
+
+    let mut config = Config::default();
+    config.set_complex(true);
+    let complex = app.recompile(config)?;
+
+    let args = [0.75, -1.5];
+    let expected = app.evaluate_single(&args);
+
+    let zargs = [Complex::new(0.75, 0.0), Complex::new(-1.5, 0.0)];
+    let mut outs = [Complex::default()];
+    complex.evaluate_matrix(&zargs, &mut outs, 1);
+    assert!((outs[0].re - expected).abs() < 1e-12);
+    assert_eq!(outs[0].im, 0.0);
+
+    Ok(())
+}
+
+fn test_auto_simd() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y"), parse!("z")];
+    let exprs = vec![parse!("x * y + sin(z)"), parse!("x^2 - y / (1 + z^2)")];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 97;
+    let args: Vec<f64> = (0..3 * N).map(|i| (i as f64 * 0.37).sin()).collect();
+    let mut simd = vec![0.0; 2 * N];
+    runner.evaluate_auto_simd(&args, &mut simd, N);
+
+    for (row, outs) in args.chunks(3).zip(simd.chunks(2)) {
+        let mut expected = [0.0; 2];
+        runner.evaluate(row, &mut expected);
+        assert!((outs[0] - expected[0]).abs() < 1e-12);
+        assert!((outs[1] - expected[1]).abs() < 1e-12);
+    }
+
+    Ok(())
+}
+
+fn test_int_params() -> Result<()> {
+    let params = vec![parse!("n"), parse!("x"), parse!("k")];
+    let f = FunctionMap::new();
+    let ev = parse!("n*x + k")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 70;
+    let ints: Vec<i32> = (0..2 * N as i32).map(|i| i - 50).collect();
+    let floats: Vec<f64> = (0..N).map(|i| i as f64 * 0.25).collect();
+    let mut outs = vec![0.0; N];
+    runner.evaluate_with_ints(&[0, 2], &ints, &floats, &mut outs, N);
+
+    for i in 0..N {
+        let expected = f64::from(ints[2 * i]) * floats[i] + f64::from(ints[2 * i + 1]);
+        assert_eq!(outs[i], expected);
+    }
+
+    Ok(())
+}
+
+fn test_accumulate() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x^2 + y^2")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut runner = ThreadedRealRunner::compile(&ev, Config::default())?;
+    runner.set_num_threads(4);
+
+    // a histogram of x^2 + y^2 over [0, 2) with 16 bins
+    let bin_fn = |outs: &[f64], bins: &mut [f64]| {
+        let k = (outs[0] * 8.0) as usize;
+        if k < bins.len() {
+            bins[k] += 1.0;
+        }
+    };
+
+    const N: usize = 50_000;
+    let args: Vec<f64> = (0..2 * N)
+        .map(|i| ((i * 7919) % 1000) as f64 * 1e-3)
+        .collect();
+    let mut bins = vec![0.0; 16];
+    runner.evaluate_matrix_accumulate(&args, N, &mut bins, bin_fn);
+    assert!(runner.last_evaluate_used_threads());
+
+    let mut serial = vec![0.0; 16];
+    let mut outs = vec![0.0; N];
+    runner.evaluate(&args, &mut outs);
+    for x in outs.iter() {
+        bin_fn(std::slice::from_ref(x), &mut serial);
+    }
+
+    assert_eq!(bins, serial);
+    assert_eq!(bins.iter().sum::<f64>(), N as f64);
+
+    Ok(())
+}
+
+fn test_assume_pure() -> Result<()> {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    let params = vec![parse!("x")];
+    let mut f = FunctionMap::new();
+    f.add_external_function(symbol!("tally"), "tally".to_string())
+        .unwrap();
+    let ev = parse!("tally(x)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let config = |pure: bool| -> Result<Config> {
+        let mut df = Defuns::new();
+        let tally: ExternalFunction<f64> =
+            Box::new(|_: &[f64]| CALLS.fetch_add(1, Ordering::SeqCst) as f64);
+        df.add_sliced_func("tally", tally)?;
+        let mut config = Config::from_defuns(df)?;
+        config.set_assume_pure(pure);
+        Ok(config)
+    };
+
+    const N: usize = 10_000;
+    let args = vec![0.0; N];
+    let mut outs = vec![0.0; N];
+
+    let mut runner = ThreadedRealRunner::compile(&ev, config(true)?)?;
+    runner.set_num_threads(4);
+    runner.evaluate(&args, &mut outs);
+    assert!(runner.last_evaluate_used_threads());
+
+    let mut runner = ThreadedRealRunner::compile(&ev, config(false)?)?;
+    runner.set_num_threads(4);
+    runner.evaluate(&args, &mut outs);
+    assert!(!runner.last_evaluate_used_threads());
+    // the calls are made in row order
+    assert!(outs.windows(2).all(|w| w[1] == w[0] + 1.0));
+
+    Ok(())
+}
+
+fn test_evaluate_checked() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![parse!("log(x) + y"), parse!("x * y")];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    // the odd rows take the log of a negative number
+    const N: usize = 37;
+    let args: Vec<f64> = (0..N)
+        .flat_map(|i| {
+            let x = (i + 1) as f64;
+            [if i % 2 == 1 { -x } else { x }, 0.5]
+        })
+        .collect();
+    let mut outs = vec![0.0; 2 * N];
+    let flags = runner.evaluate_matrix_checked(&args, &mut outs, N);
+
+    assert_eq!(flags.len(), N);
+    for (i, flag) in flags.iter().enumerate() {
+        assert_eq!(*flag, i % 2 == 0);
+        assert_eq!(*flag, outs[2 * i].is_finite());
+    }
+
+    Ok(())
+}
+
+fn test_compile_rational() -> Result<()> {
+    let params = vec![parse!("x")];
+    let runner = compile_rational(
+        &parse!("x^2 - 1"),
+        &parse!("x - 1"),
+        &params,
+        Config::default(),
+    )?;
+    assert_eq!(runner.count_params(), 1);
+
+    let args = [3.0, -2.0, 0.5, 1.0, 1.0 + 1e-6];
+    let mut outs = [0.0; 5];
+    runner.evaluate(&args, &mut outs);
+
+    assert_eq!(outs[0], 4.0);
+    assert_eq!(outs[1], -1.0);
+    assert_eq!(outs[2], 1.5);
+    // the removable singularity is not simplified away
+    assert!(outs[3].is_nan());
+    // the cancellation in x^2 - 1 costs about 6 digits of the exact 2 + 1e-6
+    assert!((outs[4] - (2.0 + 1e-6)).abs() < 1e-9);
+
+    Ok(())
+}
+
+fn test_conjugate_pairs() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![parse!("x^3 - 2*x*y + 5"), parse!("exp(x) * y^2")];
+    let f = FunctionMap::new();
+    let ev = Atom::evaluator_multiple(&exprs, &f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+
+    let runner = CompiledComplexRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 9;
+    let z: Vec<Complex<f64>> = (0..2 * N)
+        .map(|i| Complex::new(0.3 * i as f64 - 1.0, 0.7 - 0.1 * i as f64))
+        .collect();
+    let mut outs_z = vec![Complex::default(); 2 * N];
+    let mut outs_conj = vec![Complex::default(); 2 * N];
+    runner.evaluate_conjugate_pairs(&z, &mut outs_z, &mut outs_conj);
+
+    let zc: Vec<Complex<f64>> = z.iter().map(|x| x.conj()).collect();
+    let mut direct = vec![Complex::default(); 2 * N];
+    runner.evaluate(&zc, &mut direct);
+
+    for i in 0..2 * N {
+        assert_eq!(outs_conj[i], outs_z[i].conj());
+        assert!((outs_conj[i] - direct[i]).norm() <= 1e-12 * direct[i].norm().max(1.0));
+    }
+
+    Ok(())
+}
+
+fn test_compile_named() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let exprs = vec![
+        ("sum".to_string(), parse!("x + y")),
+        ("product".to_string(), parse!("x * y")),
+    ];
+    let app = compile_named(&exprs, &params, Config::default())?;
+
+    assert_eq!(app.output_name(0), Some("sum"));
+    assert_eq!(app.output_name(1), Some("product"));
+    assert_eq!(app.output_name(2), None);
+    assert_eq!(app.output_index("product"), Some(1));
+
+    let outs = app.evaluate_named(&[3.0, 4.0]);
+    assert_eq!(outs.len(), 2);
+    assert_eq!(outs["sum"], 7.0);
+    assert_eq!(outs["product"], 12.0);
+
+    let duplicate = vec![
+        ("f".to_string(), parse!("x")),
+        ("f".to_string(), parse!("y")),
+    ];
+    assert!(compile_named(&duplicate, &params, Config::default()).is_err());
+
+    Ok(())
+}
+
+fn test_pack_complex() -> Result<()> {
+    let z: Vec<Complex<f64>> = (0..4)
+        .map(|i| Complex::new(i as f64, -2.0 * i as f64))
+        .collect();
+    let packed = pack_complex_aos_to_simd(&z, 1);
+    assert_eq!(packed.len(), 1);
+    assert_eq!(packed[0].re.to_array(), [0.0, 1.0, 2.0, 3.0]);
+    assert_eq!(unpack_complex_simd_to_aos(&packed, 1, 4), z);
+
+    let params = vec![parse!("x"), parse!("y")];
+    let f = FunctionMap::new();
+    let ev = parse!("x * y + x^2")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| Complex::new(x.re.to_f64(), x.im.to_f64()));
+    let runner = CompiledComplexRunner::compile(&ev, Config::default())?;
+
+    // 7 rows of 2 parameters, so the last SIMD row is padded
+    const N: usize = 7;
+    let args: Vec<Complex<f64>> = (0..2 * N)
+        .map(|i| Complex::new(0.5 * i as f64, 1.0 - 0.25 * i as f64))
+        .collect();
+    let packed = pack_complex_aos_to_simd(&args, 2);
+    assert_eq!(packed.len(), 4);
+    assert_eq!(unpack_complex_simd_to_aos(&packed, 2, N), args);
+
+    let mut outs4 = vec![Complex::new(f64x4::default(), f64x4::default()); 2];
+    runner.evaluate(&packed, &mut outs4);
+    let outs = unpack_complex_simd_to_aos(&outs4, 1, N);
+
+    for (row, y) in args.chunks(2).zip(&outs) {
+        let expected = row[0] * row[1] + row[0] * row[0];
+        assert!((*y - expected).norm() < 1e-12);
+    }
+
+    Ok(())
+}
+
+fn test_large_kernel() -> Result<()> {
+    const TERMS: usize = 2500;
+
+    let params = vec![parse!("x"), parse!("y")];
+    let terms: Vec<String> = (1..=TERMS)
+        .map(|i| format!("sin(x + {}) * (y + {})^2", i, i))
+        .collect();
+    let f = FunctionMap::new();
+    let ev = parse!(&terms.join(" + "))
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let total: usize = num_instructions(&ev, &Config::default())?.values().sum();
+    assert!(total >= 10_000);
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    let (x, y) = (0.3, -0.7);
+    let mut outs = [0.0];
+    runner.evaluate(&[x, y], &mut outs);
+
+    let terms: Vec<f64> = (1..=TERMS)
+        .map(|i| (x + i as f64).sin() * (y + i as f64).powi(2))
+        .collect();
+    let expected: f64 = terms.iter().sum();
+    let scale: f64 = terms.iter().map(|t| t.abs()).sum();
+    assert!((outs[0] - expected).abs() <= 1e-12 * scale);
+
+    Ok(())
+}
+
+fn test_gradient_reverse() -> Result<()> {
+    const N: usize = 10;
+
+    let names: Vec<String> = (0..N).map(|i| format!("x{}", i)).collect();
+    let params: Vec<Atom> = names.iter().map(|x| parse!(x)).collect();
+    let args: Vec<f64> = (0..N).map(|i| 0.1 * i as f64 - 0.35).collect();
+
+    let squares: Vec<String> = names.iter().map(|x| format!("{}^2", x)).collect();
+    let mixed = format!(
+        "sin(x0)*exp(x1) + log(x2^2 + 1)*sqrt(x3 + 2) + cos(x4*x5)/(x6^2 + 3) + x7*x8*x9 + {}",
+        squares.join(" + ")
+    );
+
+    for expr in [squares.join(" + "), mixed] {
+        let expr = parse!(&expr);
+        let ev = expr
+            .evaluator(
+                &FunctionMap::new(),
+                &params,
+                OptimizationSettings::default(),
+            )
+            .unwrap()
+            .map_coeff(&|x| x.re.to_f64());
+
+        let reverse = compile_gradient_reverse(&ev, Config::default())?;
+
+        let mut grad = vec![0.0; N];
+        reverse.evaluate_matrix(&args, &mut grad, 1);
+
+        for (k, g) in grad.iter().enumerate() {
+            let forward = compile_derivative(&expr, &params, k, 1, Config::default())?;
+            let expected = forward.evaluate_single(&args);
+            assert!((g - expected).abs() < 1e-12 * expected.abs().max(1.0));
+        }
+    }
+
+    Ok(())
+}
+
+fn test_reduced_accuracy() -> Result<()> {
+    let params = vec![parse!("x")];
+    let ev = parse!("sin(x)")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_fp_model(FpModel::Fast);
+    let full = InterpretedSimdRealRunner::compile_with_config(&ev, &config)?;
+    config.set_transcendental_accuracy(Accuracy::Reduced);
+    let reduced = InterpretedSimdRealRunner::compile_with_config(&ev, &config)?;
+
+    let n = 100000;
+    let pi = std::f64::consts::PI;
+    let xs: Vec<f64> = (0..4 * n)
+        .map(|i| -pi + 2.0 * pi * i as f64 / (4 * n - 1) as f64)
+        .collect();
+    let args: Vec<f64x4> = xs
+        .chunks(4)
+        .map(|x| f64x4::from([x[0], x[1], x[2], x[3]]))
+        .collect();
+    let mut outs = vec![f64x4::default(); n];
+
+    reduced.evaluate(&args, &mut outs);
+
+    for (y, x) in outs.iter().flat_map(|y| y.to_array()).zip(xs.iter()) {
+        assert!((y - x.sin()).abs() <= 1e-6 * x.sin().abs() + 1e-15);
+    }
+
+    // best of a few runs, with a loose margin for noisy machines
+    let time = |runner: &InterpretedSimdRealRunner, outs: &mut [f64x4]| {
+        (0..5)
+            .map(|_| {
+                let t = Instant::now();
+                runner.evaluate(&args, outs);
+                t.elapsed()
+            })
+            .min()
+            .unwrap()
+    };
+
+    let t_full = time(&full, &mut outs);
+    let t_reduced = time(&reduced, &mut outs);
+    assert!(t_reduced.as_secs_f64() < 1.5 * t_full.as_secs_f64());
+
+    Ok(())
+}
+
+fn test_affine() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let args: Vec<f64> = (0..22).map(|i| 0.3 * i as f64 - 2.0).collect();
+    let nrows = args.len() / 2;
+
+    let ev = parse!("2*x + 3*y + 1")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+    let app = compile_affine(&ev, Config::default(), 0)?;
+    assert!(app.is_affine());
+
+    let mut outs = vec![0.0; nrows];
+    app.evaluate_matrix(&args, &mut outs, nrows);
+    let mut kernel = vec![0.0; nrows];
+    (*app).evaluate_matrix(&args, &mut kernel, nrows);
+
+    for i in 0..nrows {
+        let expected = 2.0 * args[2 * i] + 3.0 * args[2 * i + 1] + 1.0;
+        assert!((outs[i] - expected).abs() < 1e-14);
+        assert!((kernel[i] - expected).abs() < 1e-14);
+    }
+
+    let ev = parse!("x*y")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+    let app = compile_affine(&ev, Config::default(), 0)?;
+    assert!(!app.is_affine());
+
+    app.evaluate_matrix(&args, &mut outs, nrows);
+
+    for i in 0..nrows {
+        assert!((outs[i] - args[2 * i] * args[2 * i + 1]).abs() < 1e-14);
+    }
+
+    Ok(())
+}
+
+fn test_windowed() -> Result<()> {
+    let params = vec![parse!("a"), parse!("b"), parse!("c")];
+    let ev = parse!("a + b + c")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    // long enough to span several staging blocks
+    let signal: Vec<f64> = (0..500).map(|i| (i * i % 17) as f64).collect();
+
+    for stride in [1, 2, 3] {
+        let n = (signal.len() - 3) / stride + 1;
+        let mut outs = vec![0.0; n];
+        runner.evaluate_windowed(&signal, 3, stride, &mut outs);
+
+        for (i, y) in outs.iter().enumerate() {
+            let s = &signal[i * stride..];
+            assert_eq!(*y, s[0] + s[1] + s[2]);
+        }
+    }
+
+    Ok(())
+}
+
+fn test_cancellable() -> Result<()> {
+    static TOKEN: AtomicBool = AtomicBool::new(false);
+
+    let params = vec![parse!("x")];
+    let mut f = FunctionMap::new();
+    f.add_external_function(symbol!("cancel"), "cancel".to_string())
+        .unwrap();
+    let ev = parse!("cancel(x)")
+        .evaluator(&f, &params, OptimizationSettings::default())
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    // sets the token (as a cancel button would) when it sees a negative row
+    let mut df = Defuns::new();
+    let cancel: ExternalFunction<f64> = Box::new(|x: &[f64]| {
+        if x[0] < 0.0 {
+            TOKEN.store(true, Ordering::SeqCst);
+        }
+        x[0]
+    });
+    df.add_sliced_func("cancel", cancel)?;
+
+    let mut runner = ThreadedRealRunner::compile(&ev, Config::from_defuns(df)?)?;
+    runner.set_num_threads(4);
+
+    const N: usize = 100_000;
+    let mut outs = vec![0.0; N];
+
+    // the first row of every block cancels, so every worker stops early
+    let args: Vec<f64> = (0..N)
+        .map(|i| if i % 1024 == 0 { -1.0 } else { 1.0 })
+        .collect();
+    assert!(!runner.evaluate_matrix_cancellable(&args, &mut outs, N, &TOKEN));
+
+    TOKEN.store(false, Ordering::SeqCst);
+    let args = vec![2.0; N];
+    assert!(runner.evaluate_matrix_cancellable(&args, &mut outs, N, &TOKEN));
+    assert!(runner.last_evaluate_used_threads());
+    assert!(outs.iter().all(|y| *y == 2.0));
+
+    Ok(())
+}
+
+fn test_map_outputs() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let ev = parse!("x + y")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    runner.map_outputs(|o| o[0] *= 2.0);
+
+    const N: usize = 10_000;
+    let args: Vec<f64> = (0..2 * N).map(|i| i as f64).collect();
+    let mut outs = vec![0.0; N];
+    runner.evaluate(&args, &mut outs);
+
+    for (i, y) in outs.iter().enumerate() {
+        assert_eq!(*y, 2.0 * (args[2 * i] + args[2 * i + 1]));
+    }
+
+    // the lanes of SIMD rows are mapped one at a time
+    #[cfg(target_arch = "x86_64")]
+    {
+        let simd_args: Vec<f64x4> = args
+            .chunks(8)
+            .flat_map(|r| {
+                [
+                    f64x4::from([r[0], r[2], r[4], r[6]]),
+                    f64x4::from([r[1], r[3], r[5], r[7]]),
+                ]
+            })
+            .collect();
+        let mut simd_outs = vec![f64x4::default(); N / 4];
+        runner.evaluate(&simd_args, &mut simd_outs);
+        let lanes: Vec<f64> = simd_outs.iter().flat_map(|v| v.to_array()).collect();
+        assert_eq!(lanes, outs);
+    }
+
+    let mut runner = runner.into_threaded()?;
+    runner.set_num_threads(4);
+    let mut threaded = vec![0.0; N];
+    runner.evaluate(&args, &mut threaded);
+    assert!(runner.last_evaluate_used_threads());
+    assert_eq!(threaded, outs);
+
+    Ok(())
+}
+
+fn test_sigmoid_softplus() -> Result<()> {
+    let sigmoid = compile_native_real("sigmoid(x)", &["x"], &["sigmoid"])?;
+    assert_eq!(eval_real(&sigmoid, &[1000.0]), 1.0);
+    assert_eq!(eval_real(&sigmoid, &[-1000.0]), 0.0);
+    assert_eq!(eval_real(&sigmoid, &[0.0]), 0.5);
+    let y = eval_real(&sigmoid, &[-40.0]);
+    assert!((y - (-40f64).exp()).abs() < 1e-15 * y);
+
+    let softplus = compile_native_real("softplus(x)", &["x"], &["softplus"])?;
+    assert_eq!(eval_real(&softplus, &[1000.0]), 1000.0);
+    assert!(eval_real(&softplus, &[-1000.0]).abs() < 1e-300);
+    assert!((eval_real(&softplus, &[0.0]) - 2f64.ln()).abs() < 1e-15);
+    let y = eval_real(&softplus, &[-40.0]);
+    assert!((y - (-40f64).exp()).abs() < 1e-15 * y);
+
+    // the naive composites overflow or lose the small values
+    let naive = compile_native_real("exp(x) / (1 + exp(x))", &["x"], &[])?;
+    assert!(eval_real(&naive, &[1000.0]).is_nan());
+    let naive = compile_native_real("log(1 + exp(x))", &["x"], &[])?;
+    assert_eq!(eval_real(&naive, &[1000.0]), f64::INFINITY);
+    assert_eq!(eval_real(&naive, &[-40.0]), 0.0);
+
+    Ok(())
+}
+
+fn test_save_format() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let ev = parse!("x * y + 1")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    runner.save("test_format.sjb")?;
+
+    let loaded = CompiledRealRunner::load("test_format.sjb", &Config::default())?;
+    let mut outs = [0.0];
+    loaded.evaluate(&[3.0, 4.0], &mut outs);
+    assert_eq!(outs[0], 13.0);
+
+    // a real kernel is not loaded as a complex one
+    let mut config = Config::default();
+    config.set_complex(true);
+    assert!(CompiledComplexRunner::load("test_format.sjb", &config).is_err());
+
+    // the version follows the 4-byte magic number
+    let mut bytes = std::fs::read("test_format.sjb")?;
+    assert_eq!(bytes[4], SAVE_FORMAT_VERSION as u8);
+    bytes[4] = bytes[4].wrapping_add(1);
+    std::fs::write("test_format.sjb", &bytes)?;
+
+    let err = CompiledRealRunner::load("test_format.sjb", &Config::default())
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("incompatible version"));
+
+    bytes[0] = b'X';
+    std::fs::write("test_format.sjb", &bytes)?;
+    assert!(CompiledRealRunner::load("test_format.sjb", &Config::default()).is_err());
+
+    // the output names are kept
+    let exprs = vec![
+        ("sum".to_string(), parse!("x + y")),
+        ("product".to_string(), parse!("x * y")),
+    ];
+    compile_named(&exprs, &params, Config::default())?.save("test_format.sjb")?;
+    let app = NamedApplication::load("test_format.sjb", &Config::default())?;
+    assert_eq!(app.output_index("product"), Some(1));
+    assert_eq!(app.evaluate_named(&[3.0, 4.0])["sum"], 7.0);
+
+    std::fs::remove_file("test_format.sjb")?;
+    Ok(())
+}
+
+fn test_evaluate_multi() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let evs: Vec<ExpressionEvaluator<f64>> = ["x * y", "sin(x) + y^2"]
+        .iter()
+        .map(|e| {
+            parse!(*e)
+                .evaluator(
+                    &FunctionMap::new(),
+                    &params,
+                    OptimizationSettings::default(),
+                )
+                .unwrap()
+                .map_coeff(&|x| x.re.to_f64())
+        })
+        .collect();
+    let apps = compile_many(&evs, Config::default())?;
+
+    const N: usize = 1000;
+    let args: Vec<f64> = (0..2 * N).map(|i| 0.01 * i as f64).collect();
+
+    let mut a = vec![0.0; N];
+    let mut b = vec![0.0; N];
+    evaluate_multi(&[&apps[0], &apps[1]], &args, N, &mut [&mut a, &mut b])?;
+
+    for (app, outs) in apps.iter().zip([&a, &b]) {
+        let mut expected = vec![0.0; N];
+        app.evaluate_matrix(&args, &mut expected, N);
+        assert_eq!(outs, &expected);
+    }
+
+    // the kernels must have the same number of parameters
+    let other = compile_str("x", &["x"], Config::default())?;
+    assert!(evaluate_multi(&[&apps[0], &other], &args, N, &mut [&mut a, &mut b]).is_err());
+
+    Ok(())
+}
+
+fn test_optimize_for_size() -> Result<()> {
+    let mut config = Config::default();
+    config.set_optimize_for(OptLevel::Size);
+    let mut small = compile_str("x^32", &["x"], config)?;
+    let mut fast = compile_str("x^32", &["x"], Config::default())?;
+
+    assert!(code_size(&mut small) > 0);
+    assert!(code_size(&mut small) < code_size(&mut fast));
+
+    let args: Vec<f64> = (1..=20).map(|i| 0.1 * i as f64).collect();
+    let mut a = vec![0.0; args.len()];
+    let mut b = vec![0.0; args.len()];
+    small.evaluate_matrix(&args, &mut a, args.len());
+    fast.evaluate_matrix(&args, &mut b, args.len());
+
+    for (x, (a, b)) in args.iter().zip(a.iter().zip(b.iter())) {
+        let expected = x.powi(32);
+        assert!((a - expected).abs() <= 1e-14 * expected);
+        assert!((b - expected).abs() <= 1e-14 * expected);
+    }
+
+    Ok(())
+}
+
+fn test_trace_nonfinite() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let ev = parse!("sqrt(x) * y + 1")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut config = Config::default();
+    config.set_trace_nonfinite(true);
+    let runner = CompiledRealRunner::compile(&ev, config)?;
+
+    let mut outs = vec![0.0; 3];
+    let trace = runner.evaluate_matrix_traced(&[4.0, 1.0, 9.0, 2.0], &mut outs, 2)?;
+    assert_eq!(trace, None);
+
+    let trace = runner
+        .evaluate_matrix_traced(&[4.0, 1.0, 9.0, 2.0, -1.0, 3.0], &mut outs, 3)?
+        .unwrap();
+    assert_eq!(trace.row, 2);
+    assert_eq!(trace.op.as_deref(), Some("sqrt"));
+
+    let (instructions, _, _) = ev.export_instructions();
+    assert!(matches!(
+        instructions[trace.instruction.unwrap()],
+        Instruction::Fun(..)
+    ));
+
+    // the interpreted copy is only kept on request
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+    assert!(runner
+        .evaluate_matrix_traced(&[4.0, 1.0], &mut outs, 1)
+        .is_err());
+
+    Ok(())
+}
+
+fn test_columns() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y"), parse!("z")];
+    let exprs = vec![parse!("x * y - z"), parse!("x + y^2 + z^3")];
+    let ev = Atom::evaluator_multiple(
+        &exprs,
+        &FunctionMap::new(),
+        &params,
+        OptimizationSettings::default(),
+    )
+    .unwrap()
+    .map_coeff(&|x| x.re.to_f64());
+    let runner = CompiledRealRunner::compile(&ev, Config::default())?;
+
+    const N: usize = 1000;
+    let x: Vec<f64> = (0..N).map(|i| 0.01 * i as f64).collect();
+    let y: Vec<f64> = (0..N).map(|i| 1.0 - 0.002 * i as f64).collect();
+    let z: Vec<f64> = (0..N).map(|i| (i % 7) as f64).collect();
+
+    let mut outs = vec![0.0; 2 * N];
+    runner.evaluate_columns(&[&x, &y, &z], &mut outs);
+
+    let args: Vec<f64> = (0..N).flat_map(|i| [x[i], y[i], z[i]]).collect();
+    let mut expected = vec![0.0; 2 * N];
+    runner.evaluate(&args, &mut expected);
+    assert_eq!(outs, expected);
+
+    Ok(())
+}
+
+fn test_argmin() -> Result<()> {
+    let params = vec![parse!("x")];
+    let ev = parse!("x^2")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let mut runner = ThreadedRealRunner::compile(&ev, Config::default())?;
+    runner.set_num_threads(4);
+
+    // x runs from about -5 to 5, and row 50_003 is the nearest to zero
+    const N: usize = 100_000;
+    let args: Vec<f64> = (0..N).map(|i| (i as f64 - 50_003.25) * 1e-4).collect();
+
+    let (row, y) = runner.evaluate_matrix_argmin(&args, N).unwrap();
+    assert!(runner.last_evaluate_used_threads());
+    assert_eq!(row, 50_003);
+    assert_eq!(y, args[row] * args[row]);
+
+    let (row, _) = runner.evaluate_matrix_argmax(&args, N).unwrap();
+    assert_eq!(row, 0);
+
+    assert_eq!(runner.evaluate_matrix_argmin(&args, 0), None);
+
+    Ok(())
+}
+
+fn test_round_mode() -> Result<()> {
+    let constant = |expr: &str, mode: RoundMode| -> Result<f64> {
+        let mut config = Config::default();
+        config.set_round_mode(mode);
+        let app = compile_str(expr, &["x"], config)?;
+        Ok(app.evaluate_single(&[0.0]))
+    };
+
+    // the nearest f64 to 1/3 is below it, and the nearest to 1/10 is above it
+    let third = constant("x + 1/3", RoundMode::Nearest)?;
+    assert_eq!(third, 1.0 / 3.0);
+    assert_eq!(constant("x + 1/3", RoundMode::TowardZero)?, third);
+    assert_eq!(
+        constant("x + 1/3", RoundMode::Up)?.to_bits(),
+        third.to_bits() + 1
+    );
+
+    let tenth = constant("x + 1/10", RoundMode::Nearest)?;
+    assert_eq!(tenth, 0.1);
+    assert_eq!(
+        constant("x + 1/10", RoundMode::TowardZero)?.to_bits(),
+        tenth.to_bits() - 1
+    );
+    assert_eq!(
+        constant("x + 1/10", RoundMode::Down)?,
+        constant("x + 1/10", RoundMode::TowardZero)?
+    );
+    assert_eq!(
+        constant("x - 1/10", RoundMode::TowardZero)?,
+        -constant("x + 1/10", RoundMode::TowardZero)?
+    );
+
+    // exact constants are not moved
+    assert_eq!(constant("x + 1/4", RoundMode::Up)?, 0.25);
+    Ok(())
+}
+
+fn test_lazy() -> Result<()> {
+    let params = vec![parse!("x"), parse!("y")];
+    let ev = parse!("sin(x) * y + 2")
+        .evaluator(
+            &FunctionMap::new(),
+            &params,
+            OptimizationSettings::default(),
+        )
+        .unwrap()
+        .map_coeff(&|x| x.re.to_f64());
+
+    let app = compile(&ev, Config::default(), 0)?;
+    let mut lazy = compile_lazy(&ev, Config::default(), 0)?;
+    assert!(!lazy.is_compiled());
+
+    let args = [0.5, 3.0];
+    assert_eq!(lazy.evaluate_single(&args)?, app.evaluate_single(&args));
+    assert!(lazy.is_compiled());
+
+    let args = [0.1, 1.0, 0.2, 2.0, 0.3, 3.0];
+    let mut outs = [0.0; 3];
+    let mut expected = [0.0; 3];
+    lazy.evaluate_matrix(&args, &mut outs, 3)?;
+    app.evaluate_matrix(&args, &mut expected, 3);
+    assert_eq!(outs, expected);
+
+    // `evaluate` takes as many rows as `args` holds
+    let mut outs = [0.0; 3];
+    lazy.evaluate(&args, &mut outs)?;
+    assert_eq!(outs, expected);
+    assert!(lazy.evaluate(&args[..5], &mut outs).is_err());
+
+    // the code size limit is checked when the code is generated
+    let mut config = Config::default();
+    config.set_max_code_bytes(Some(1));
+    let mut lazy = compile_lazy(&ev, config, 0)?;
+    assert!(lazy.evaluate_single(&[0.5, 3.0]).is_err());
+    assert!(!lazy.is_compiled());
+
+    Ok(())
+}
+
+pub fn main() -> Result<()> {
+    test_real()?;
+    pass("real");
+
+    test_complex()?;
+    pass("complex");
+
+    test_real_runner()?;
+    pass("real runner");
+
+    test_complex_runner()?;
+    pass("complex runner");
+
+    test_scattered_simd_real_runner()?;
+    pass("Scattered simd real runner");
+
+    test_scattered_simd_complex_runner()?;
+    pass("Scattered simd complex runner");
+
+    test_interpreted_real_runner()?;
+    pass("interpreted real runner");
+
+    test_interpreted_complex_runner()?;
+    pass("interpreted complex runner");
+
+    test_external()?;
+    pass("external real runner");
+
+    test_external_save()?;
+    test_external_load()?;
+    pass("external func real runner (save/load)");
+
+    test_external_func_complex()?;
+    pass("external func complex runner");
+
+    // test_external_func_bytecode()?;
+    // pass("external func bytecode runner");
+    #[cfg(target_arch = "x86_64")]
+    test_external_simd_func()?;
+    pass("external func simd runner");
+
+    #[cfg(target_arch = "x86_64")]
+    test_external_simd_complex_func()?;
+    pass("external func simd complex runner");
+
+    test_string_real()?;
+    pass("string real runner");
+
+    test_string_complex()?;
+    pass("string complex runner");
+
+    test_threads_runner()?;
+    pass("threads");
+
+    test_threads_application()?;
+    pass("threads");
+
+    test_ifelse()?;
+    pass("ifelse");
+
+    test_fp_model_strict()?;
+    pass("strict fp model");
+
+    test_robust_runner()?;
+    test_robust_runner_fallback()?;
+    pass("robust runner");
+
+    test_erf()?;
+    pass("erf");
+
+    test_gamma()?;
+    pass("gamma");
+
+    test_warmup()?;
+    pass("warmup");
+
+    test_broadcast()?;
+    pass("broadcast");
+
+    test_is_complex()?;
+    pass("is_complex");
+
+    test_validate_instructions()?;
+    pass("validate instructions");
+
+    test_inplace()?;
+    pass("in-place evaluation");
+
+    test_log_bases()?;
+    pass("log10 and log2");
+
+    test_compile_many()?;
+    pass("compile many");
+
+    test_atan2()?;
+    pass("atan2");
+
+    test_validate()?;
+    pass("validate");
+
+    test_compile_split()?;
+    pass("compile split");
+
+    test_hypot()?;
+    pass("hypot");
+
+    #[cfg(target_os = "linux")]
+    {
+        test_write_object()?;
+        pass("write object");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        test_simd_complex_join()?;
+        pass("simd complex join");
+    }
+
+    test_scattered_complex_join()?;
+    pass("scattered complex join");
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        test_interpreted_simd()?;
+        pass("interpreted simd");
+    }
+
+    test_complex_args_len()?;
+    pass("complex args length");
+
+    test_sincos()?;
+    pass("sincos");
+
+    test_fuse_sincos()?;
+    pass("fuse sincos");
+
+    test_fast_rsqrt()?;
+    pass("fast rsqrt");
+
+    test_compile_str()?;
+    pass("compile_str");
+
+    test_bridge_error()?;
+    pass("bridge error");
+
+    test_const_fold()?;
+    pass("const fold");
+
+    test_compile_then()?;
+    pass("compile then");
+
+    test_matrix_sum()?;
+    pass("matrix sum");
+
+    test_used_threads()?;
+    pass("used threads");
+
+    test_hyperbolic()?;
+    pass("hyperbolic");
+
+    test_prepare()?;
+    pass("prepare");
+
+    test_evaluate_split()?;
+    pass("evaluate split");
+
+    test_max_code_bytes()?;
+    pass("max code bytes");
+
+    test_rounding()?;
+    pass("rounding");
+
+    test_fd_gradient()?;
+    pass("fd gradient");
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    {
+        test_flush_denormals()?;
+        pass("flush denormals");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        test_try_evaluate()?;
+        pass("try evaluate");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        test_simd_reduce()?;
+        pass("simd reduce");
+    }
+
+    test_numa_replicas()?;
+    pass("numa replicas");
+
+    test_piecewise()?;
+    pass("piecewise");
+
+    test_bool_runner()?;
+    pass("bool runner");
+
+    test_evaluate_grid()?;
+    pass("evaluate grid");
+
+    test_instrument()?;
+    pass("instrument");
+
+    test_array_params()?;
+    pass("array params");
+
+    test_complex_layout()?;
+    pass("complex layout");
+
+    test_polar_runner()?;
+    pass("polar runner");
+
+    test_vmath()?;
+    pass("vmath");
+
+    test_to_dot()?;
+    pass("to_dot");
+
+    test_input_transform()?;
+    pass("input transform");
+
+    test_accurate_sum()?;
+    pass("accurate sum");
+
+    test_entry_ptr()?;
+    pass("entry pointer");
+
+    test_evaluate_masked()?;
+    pass("evaluate masked");
+
+    test_fmod()?;
+    pass("fmod");
+
+    test_threaded_column_major()?;
+    pass("threaded column major");
+
+    test_temp_count()?;
+    pass("temp count");
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        test_complex_f64x2_layout()?;
+        pass("complex f64x2 layout");
+    }
+
+    test_dedup_constants()?;
+    pass("dedup constants");
+
+    test_compile_derivative()?;
+    pass("compile derivative");
+
+    test_value_and_gradient()?;
+    pass("value and gradient");
+
+    test_zero_params()?;
+    pass("zero params");
+
+    test_scalars()?;
+    pass("scalars");
+
+    test_cbrt()?;
+    pass("cbrt");
+
+    test_recompile()?;
+    pass("recompile");
+
+    test_auto_simd()?;
+    pass("auto simd");
+
+    test_int_params()?;
+    pass("int params");
+
+    test_accumulate()?;
+    pass("accumulate");
+
+    test_assume_pure()?;
+    pass("assume pure");
+
+    test_evaluate_checked()?;
+    pass("evaluate checked");
+
+    test_compile_rational()?;
+    pass("compile rational");
+
+    test_conjugate_pairs()?;
+    pass("conjugate pairs");
+
+    test_compile_named()?;
+    pass("compile named");
+
+    test_pack_complex()?;
+    pass("pack complex");
+
+    test_large_kernel()?;
+    pass("large kernel");
+
+    test_gradient_reverse()?;
+    pass("gradient reverse");
+
+    test_reduced_accuracy()?;
+    pass("reduced accuracy");
+
+    test_affine()?;
+    pass("affine");
+
+    test_windowed()?;
+    pass("windowed");
+
+    test_cancellable()?;
+    pass("cancellable");
+
+    test_map_outputs()?;
+    pass("map outputs");
+
+    test_sigmoid_softplus()?;
+    pass("sigmoid and softplus");
+
+    test_save_format()?;
+    pass("save format");
+
+    test_evaluate_multi()?;
+    pass("evaluate multi");
+
+    test_optimize_for_size()?;
+    pass("optimize for size");
+
+    test_trace_nonfinite()?;
+    pass("trace nonfinite");
+
+    test_columns()?;
+    pass("columns");
+
+    test_argmin()?;
+    pass("argmin");
+
+    test_round_mode()?;
+    pass("round_mode");
+
+    test_lazy()?;
+    pass("lazy");
+
+    test_phasor_runner()?;
+    pass("phasor_runner");
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        test_required_features()?;
+        pass("required features");
+    }
+
+    test_expm1_log1p()?;
+    pass("expm1 and log1p");
+
+    test_pin_threads()?;
+    pass("pin threads");
+
+    test_output_layout()?;
+    pass("output layout");
+
+    test_self_check()?;
+    pass("self check");
+
+    test_evaluate_rows()?;
+    pass("evaluate rows");
+
+    #[cfg(feature = "arbitrary-precision")]
+    {
+        test_precision()?;
+        pass("arbitrary precision");
+    }
+
+    #[cfg(feature = "cli")]
+    {
+        test_repl()?;
+        pass("repl");
+    }
+
+    #[cfg(feature = "nalgebra")]
+    {
+        test_nalgebra()?;
+        pass("nalgebra");
+    }
+
+    #[cfg(feature = "test-util")]
+    {
+        test_assert_close()?;
+        pass("assert_close");
+    }
 
     Ok(())
 }
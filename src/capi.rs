@@ -0,0 +1,102 @@
+//! `extern "C"` functions to compile and evaluate real kernels from C or
+//! Python (ctypes/cffi). Errors, including panics, are reported by returning
+//! a null pointer or a negative value; the error message is not available
+//! through the C API.
+
+use std::ffi::{c_char, CStr};
+use std::panic::catch_unwind;
+
+use crate::{compile_str, CompiledRealRunner, Config};
+
+/// Parses and compiles the real expression `expr` with the `nparams`
+/// parameters named in `params` (see `compile_str`). Returns the runner, to
+/// be released with `symjit_bridge_free`, or null on error.
+///
+/// # Safety
+///
+/// `expr` and the `nparams` elements of `params` must be valid NUL-terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn symjit_bridge_compile(
+    expr: *const c_char,
+    params: *const *const c_char,
+    nparams: usize,
+) -> *mut CompiledRealRunner {
+    if expr.is_null() || (nparams > 0 && params.is_null()) {
+        return std::ptr::null_mut();
+    }
+
+    let Ok(expr) = CStr::from_ptr(expr).to_str() else {
+        return std::ptr::null_mut();
+    };
+
+    let mut names = Vec::with_capacity(nparams);
+
+    for i in 0..nparams {
+        let p = *params.add(i);
+        match (!p.is_null()).then(|| CStr::from_ptr(p).to_str()) {
+            Some(Ok(name)) => names.push(name),
+            _ => return std::ptr::null_mut(),
+        }
+    }
+
+    match catch_unwind(|| compile_str(expr, &names, Config::default())) {
+        Ok(Ok(app)) => Box::into_raw(Box::new(CompiledRealRunner::new(app, false, false))),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Evaluates `nrows` rows: `args` holds `nrows * count_params` parameters
+/// stored row by row and `outs` receives `nrows * count_obs` outputs. Returns
+/// 0, or -1 if `runner` is null or the evaluation panicked.
+///
+/// # Safety
+///
+/// `runner` must come from `symjit_bridge_compile` and not be freed, and
+/// `args` and `outs` must point to buffers of the sizes above.
+#[no_mangle]
+pub unsafe extern "C" fn symjit_bridge_evaluate(
+    runner: *const CompiledRealRunner,
+    args: *const f64,
+    outs: *mut f64,
+    nrows: usize,
+) -> i32 {
+    let Some(runner) = runner.as_ref() else {
+        return -1;
+    };
+
+    let entry: crate::EntryFn = std::mem::transmute(runner.entry_ptr());
+    entry(runner.context_ptr(), args, outs, nrows)
+}
+
+/// The number of parameters per row of `runner`, or 0 if it is null.
+///
+/// # Safety
+///
+/// `runner` must come from `symjit_bridge_compile` and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn symjit_bridge_count_params(runner: *const CompiledRealRunner) -> usize {
+    runner.as_ref().map_or(0, |r| r.count_params())
+}
+
+/// The number of outputs per row of `runner`, or 0 if it is null.
+///
+/// # Safety
+///
+/// `runner` must come from `symjit_bridge_compile` and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn symjit_bridge_count_obs(runner: *const CompiledRealRunner) -> usize {
+    runner.as_ref().map_or(0, |r| r.count_obs())
+}
+
+/// Releases a runner returned by `symjit_bridge_compile`; null is ignored.
+///
+/// # Safety
+///
+/// `runner` must come from `symjit_bridge_compile` and not be used afterward.
+#[no_mangle]
+pub unsafe extern "C" fn symjit_bridge_free(runner: *mut CompiledRealRunner) {
+    if !runner.is_null() {
+        drop(Box::from_raw(runner));
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+use crate::Application;
+
+/// Turns a real kernel into a line filter: each line of `input` holds the
+/// whitespace-separated parameters of one row, and the corresponding outputs
+/// are written to `output` as one whitespace-separated line. Blank lines are
+/// skipped. A malformed line produces a line starting with `error:` and the
+/// loop continues with the next line.
+pub fn run_repl<R: BufRead, W: Write>(app: &Application, input: R, mut output: W) -> Result<()> {
+    let mut outs = vec![0.0; app.count_obs];
+
+    for line in input.lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let args: Result<Vec<f64>, _> = line.split_whitespace().map(|x| x.parse::<f64>()).collect();
+
+        match args {
+            Ok(args) if args.len() == app.count_params => {
+                app.evaluate_matrix(&args, &mut outs, 1);
+                let row: Vec<String> = outs.iter().map(|x| x.to_string()).collect();
+                writeln!(output, "{}", row.join(" "))?;
+            }
+            Ok(args) => {
+                writeln!(
+                    output,
+                    "error: expected {} values, found {}",
+                    app.count_params,
+                    args.len()
+                )?;
+            }
+            Err(err) => writeln!(output, "error: {}", err)?,
+        }
+    }
+
+    output.flush()?;
+    Ok(())
+}
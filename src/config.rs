@@ -0,0 +1,403 @@
+use anyhow::Result;
+use std::ops::{Deref, DerefMut};
+use symjit::{CompilerType, Defuns};
+
+/// Floating-point model used by the generated code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FpModel {
+    /// IEEE semantics: no FMA contraction. Results do not depend on the SIMD
+    /// width.
+    #[default]
+    Strict,
+    /// Allows FMA contraction in the real kernels and the `vmath`
+    /// approximations of `InterpretedSimdRealRunner::compile_with_config`.
+    Fast,
+}
+
+/// How `CompiledComplexRunner::evaluate_split` feeds split (planar) complex
+/// data to the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ComplexLayout {
+    /// Picks the faster of `Interleaved` and `Planar` for the kernel and CPU
+    /// by timing a few rows of each at compile time.
+    Auto,
+    /// Interleaves the real and imaginary parts into `Complex<f64>` rows.
+    #[default]
+    Interleaved,
+    /// Packs groups of rows into `Complex<f64x4>` vectors, with the real and
+    /// imaginary parts of four rows in separate vectors (x86-64 only; other
+    /// architectures use `Interleaved`).
+    Planar,
+}
+
+/// Accuracy of the vectorized approximations of the transcendental functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Accuracy {
+    /// Close to the correctly rounded results (a few ulps).
+    #[default]
+    Full,
+    /// Lower-degree polynomials, with about 1e-6 relative error.
+    Reduced,
+}
+
+/// Whether the generated code favors speed or size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    #[default]
+    Speed,
+    /// Generates only the scalar code, calls `pow` for the integer powers
+    /// beyond the squares, and disables FMA contraction, for smaller code at
+    /// the cost of throughput.
+    Size,
+}
+
+/// How the exact rational constants of an expression are rounded to `f64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundMode {
+    /// To the nearest `f64`.
+    #[default]
+    Nearest,
+    /// To the nearest `f64` not larger in magnitude.
+    TowardZero,
+    /// To the nearest `f64` not smaller (toward positive infinity).
+    Up,
+    /// To the nearest `f64` not larger (toward negative infinity).
+    Down,
+}
+
+/// The compiler configuration.
+///
+/// `Config` wraps `symjit::Config` (whose methods are available through `Deref`)
+/// and adds the options that are handled by the bridge itself.
+///
+/// Kernels with conditionals (`IfElse` or `Join`) are always compiled with
+/// `set_simd_branch(true)`, overriding `symjit.toml` and any earlier call, so
+/// that the lanes of a SIMD row can take different branches.
+#[derive(Clone, Default)]
+pub struct Config {
+    pub(crate) inner: symjit::Config,
+    fp_model: Option<FpModel>,
+    max_code_bytes: Option<usize>,
+    flush_denormals: bool,
+    pin_threads: bool,
+    self_check: bool,
+    fuse_sincos: bool,
+    fast_rsqrt: Option<u32>,
+    instrument: bool,
+    complex_layout: ComplexLayout,
+    const_fold: bool,
+    input_transform: Option<(Vec<f64>, Vec<f64>)>,
+    accurate_sum: bool,
+    impure_externals: bool,
+    transcendental_accuracy: Accuracy,
+    opt_level: OptLevel,
+    trace_nonfinite: bool,
+    round_mode: RoundMode,
+}
+
+impl Config {
+    pub fn new(ty: CompilerType, opt: u32) -> Result<Config> {
+        Ok(Config::from(symjit::Config::new(ty, opt)?))
+    }
+
+    pub fn from_name(ty: &str, opt: u32) -> Result<Config> {
+        Ok(Config::from(symjit::Config::from_name(ty, opt)?))
+    }
+
+    pub fn from_toml(path: &str, opt: u32) -> Result<Config> {
+        Ok(Config::from(symjit::Config::from_toml(path, opt)?))
+    }
+
+    pub fn from_defuns(df: Defuns) -> Result<Config> {
+        Ok(Config::from(symjit::Config::from_defuns(df)?))
+    }
+
+    /// Returns a bytecode (interpreter) config with the same bridge options,
+    /// external functions, and complex flag, except for the code size limit
+    /// and the self-check, which only apply to machine code.
+    pub(crate) fn bytecode(self) -> Result<Config> {
+        let mut inner = symjit::Config::from_name("bytecode", self.inner.opt)?;
+        inner.df = self.inner.df.clone();
+        inner.set_complex(self.inner.is_complex());
+
+        Ok(Config {
+            inner,
+            max_code_bytes: None,
+            self_check: false,
+            ..self
+        })
+    }
+
+    /// The floating-point model; unless `set_fp_model` was called, it follows
+    /// the `fastmath` flag of the underlying symjit config.
+    pub fn fp_model(&self) -> FpModel {
+        match self.fp_model {
+            Some(fp_model) => fp_model,
+            None if self.inner.fastmath() => FpModel::Fast,
+            None => FpModel::Strict,
+        }
+    }
+
+    pub fn set_fp_model(&mut self, fp_model: FpModel) {
+        self.fp_model = Some(fp_model);
+    }
+
+    pub fn max_code_bytes(&self) -> Option<usize> {
+        self.max_code_bytes
+    }
+
+    /// Bounds the size of the generated machine code. Compilation fails if the
+    /// code exceeds `limit` bytes; `None` (the default) means no limit.
+    pub fn set_max_code_bytes(&mut self, limit: Option<usize>) {
+        self.max_code_bytes = limit;
+    }
+
+    pub fn flush_denormals(&self) -> bool {
+        self.flush_denormals
+    }
+
+    /// Makes the compiled runners flush denormal inputs and results to zero
+    /// (FTZ/DAZ on x86-64, FZ on aarch64) during evaluation, restoring the
+    /// previous floating point state afterward. This avoids the large slowdown
+    /// of denormal arithmetic at the cost of losing values below `f64::MIN_POSITIVE`
+    /// (about 2.2e-308), which become zero. It has no effect on other architectures.
+    pub fn set_flush_denormals(&mut self, flush: bool) {
+        self.flush_denormals = flush;
+    }
+
+    pub fn pin_threads(&self) -> bool {
+        self.pin_threads
+    }
+
+    /// Makes `ThreadedRealRunner` pin each worker thread to its own core for
+    /// the duration of a call, which avoids the jitter of thread migration.
+    /// It is a no-op on platforms without a thread affinity API.
+    pub fn set_pin_threads(&mut self, pin: bool) {
+        self.pin_threads = pin;
+    }
+
+    pub fn self_check(&self) -> bool {
+        self.self_check
+    }
+
+    /// Makes `compile` evaluate a few pseudo-random inputs with both the
+    /// generated code and the bytecode interpreter and fail if they disagree.
+    /// It is meant as a safety net during development and is off by default.
+    pub fn set_self_check(&mut self, check: bool) {
+        self.self_check = check;
+    }
+
+    pub fn fuse_sincos(&self) -> bool {
+        self.fuse_sincos
+    }
+
+    /// Makes the real runners compute `sin(x)` and `cos(x)` of the same
+    /// argument with a single `sincos` call. The fused code calls the
+    /// `sincos_0` and `sincos_1` natives, so they must be registered
+    /// (`natives::add_real_natives`) in the `Defuns` of this config.
+    pub fn set_fuse_sincos(&mut self, fuse: bool) {
+        self.fuse_sincos = fuse;
+    }
+
+    pub fn fast_rsqrt(&self) -> Option<u32> {
+        self.fast_rsqrt
+    }
+
+    /// With `FpModel::Fast`, makes the real runners compute `x^-0.5` and
+    /// `1/sqrt(x)` with a single-precision estimate followed by `steps` (0 or 1)
+    /// Newton-Raphson refinement steps. The bare estimate has a relative error
+    /// of up to about 1e-7; one step brings it down to about 1e-14. The
+    /// `rsqrt` and `rsqrt_nr` natives (`natives::add_real_natives`) must be
+    /// registered in the `Defuns` of this config. `None` (the default) disables it.
+    pub fn set_fast_rsqrt(&mut self, steps: Option<u32>) {
+        self.fast_rsqrt = steps;
+    }
+
+    pub fn instrument(&self) -> bool {
+        self.instrument
+    }
+
+    /// Makes `CompiledRealRunner` estimate the entries into the generated code
+    /// and record the timestamp counter of the last call (see
+    /// `estimated_call_count`).
+    /// When disabled (the default), no counters are kept.
+    pub fn set_instrument(&mut self, instrument: bool) {
+        self.instrument = instrument;
+    }
+
+    pub fn complex_layout(&self) -> ComplexLayout {
+        self.complex_layout
+    }
+
+    pub fn set_complex_layout(&mut self, layout: ComplexLayout) {
+        self.complex_layout = layout;
+    }
+
+    pub fn const_fold(&self) -> bool {
+        self.const_fold
+    }
+
+    /// Makes the bridge evaluate the instructions whose inputs are all
+    /// constants (e.g., `exp(1)`) at compile time and replace them with a
+    /// single constant.
+    pub fn set_const_fold(&mut self, fold: bool) {
+        self.const_fold = fold;
+    }
+
+    pub fn input_transform(&self) -> Option<(&[f64], &[f64])> {
+        self.input_transform
+            .as_ref()
+            .map(|(scales, offsets)| (scales.as_slice(), offsets.as_slice()))
+    }
+
+    /// Makes the generated code replace each parameter `x_i` with
+    /// `(x_i - offsets[i]) * scales[i]` before evaluating the kernel, e.g., to
+    /// normalize the inputs without a separate pass over the data. Both vectors
+    /// must have one entry per parameter (`count_params`; one per complex
+    /// parameter for complex kernels), or compilation fails.
+    pub fn set_input_transform(&mut self, scales: Vec<f64>, offsets: Vec<f64>) {
+        self.input_transform = Some((scales, offsets));
+    }
+
+    pub fn accurate_sum(&self) -> bool {
+        self.accurate_sum
+    }
+
+    /// Makes the bridge evaluate the sums of more than 8 terms as a balanced
+    /// tree of pairwise additions instead of a left-to-right chain, which
+    /// reduces the rounding error of long sums with cancellation. It takes the
+    /// same number of additions and a few more temporaries.
+    pub fn set_accurate_sum(&mut self, accurate: bool) {
+        self.accurate_sum = accurate;
+    }
+
+    pub fn assume_pure(&self) -> bool {
+        !self.impure_externals
+    }
+
+    /// Sets whether the external functions may be treated as pure, i.e., free
+    /// of side effects, so that their calls can be reordered and split across
+    /// threads (the default). With `false`, a kernel that calls an external
+    /// function is compiled without reassociation (as with `FpModel::Strict`)
+    /// and `ThreadedRealRunner` evaluates it on the calling thread, so the
+    /// calls are made in row order.
+    pub fn set_assume_pure(&mut self, pure: bool) {
+        self.impure_externals = !pure;
+    }
+
+    pub fn transcendental_accuracy(&self) -> Accuracy {
+        self.transcendental_accuracy
+    }
+
+    /// Selects the polynomial degree of the vectorized approximations of
+    /// `exp`, `log`, `sin`, and `cos` used by `InterpretedSimdRealRunner`.
+    /// `Accuracy::Reduced` trades accuracy (about 1e-6 relative error) for
+    /// speed and enables the approximations even with `FpModel::Strict`.
+    pub fn set_transcendental_accuracy(&mut self, accuracy: Accuracy) {
+        self.transcendental_accuracy = accuracy;
+    }
+
+    pub fn optimize_for(&self) -> OptLevel {
+        self.opt_level
+    }
+
+    /// Selects whether the generated code favors speed (the default) or size.
+    /// With `OptLevel::Size`, no SIMD code is generated (the rows are evaluated
+    /// by looping over the scalar code, so only `f64` rows are accepted),
+    /// integer powers other than `x^-2` to `x^2` are computed by calls to `pow`
+    /// instead of being expanded into sequences of multiplications, and FMA
+    /// contraction is disabled even with `FpModel::Fast`. This is meant for
+    /// deploying many kernels on memory-constrained devices; `code_size(app)`
+    /// reports the result.
+    pub fn set_optimize_for(&mut self, level: OptLevel) {
+        self.opt_level = level;
+    }
+
+    pub fn trace_nonfinite(&self) -> bool {
+        self.trace_nonfinite
+    }
+
+    /// Makes `CompiledRealRunner` keep an interpreted copy of the kernel, so
+    /// that `evaluate_matrix_traced` can find the first instruction that
+    /// produced a non-finite value. The kernel must not call external
+    /// functions or use control flow.
+    pub fn set_trace_nonfinite(&mut self, trace: bool) {
+        self.trace_nonfinite = trace;
+    }
+
+    pub fn round_mode(&self) -> RoundMode {
+        self.round_mode
+    }
+
+    /// Selects how the rational constants are rounded to `f64` when the bridge
+    /// builds the evaluator itself (`compile_str`, `compile_named`,
+    /// `compile_derivative`, `compile_value_and_gradient`, and
+    /// `compile_rational`). The default is
+    /// `RoundMode::Nearest`. Directed rounding brackets the exact constants,
+    /// e.g., to bound the result of an interval computation.
+    pub fn set_round_mode(&mut self, mode: RoundMode) {
+        self.round_mode = mode;
+    }
+
+    /// Lowers the bridge options into the underlying symjit config.
+    /// `branching` tells whether the instruction stream has conditionals.
+    pub(crate) fn into_symjit(mut self, branching: bool) -> symjit::Config {
+        if let Some(fp_model) = self.fp_model {
+            self.inner.set_fastmath(fp_model == FpModel::Fast);
+        }
+
+        if self.opt_level == OptLevel::Size {
+            self.inner.set_fastmath(false);
+            self.inner.set_simd(false);
+        }
+
+        // without it, a SIMD row whose lanes take different branches falls
+        // back to the scalar code, which misreads the lanes of `f64x4` and
+        // `Complex<f64x4>` arguments; with it, both arms run and `Join`
+        // selects per lane
+        if branching {
+            self.inner.set_simd_branch(true);
+        }
+
+        self.inner
+    }
+}
+
+impl From<symjit::Config> for Config {
+    fn from(inner: symjit::Config) -> Self {
+        Config {
+            inner,
+            fp_model: None,
+            max_code_bytes: None,
+            flush_denormals: false,
+            pin_threads: false,
+            self_check: false,
+            fuse_sincos: false,
+            fast_rsqrt: None,
+            instrument: false,
+            complex_layout: ComplexLayout::default(),
+            const_fold: false,
+            input_transform: None,
+            accurate_sum: false,
+            impure_externals: false,
+            transcendental_accuracy: Accuracy::default(),
+            opt_level: OptLevel::default(),
+            trace_nonfinite: false,
+            round_mode: RoundMode::default(),
+        }
+    }
+}
+
+impl Deref for Config {
+    type Target = symjit::Config;
+
+    fn deref(&self) -> &symjit::Config {
+        &self.inner
+    }
+}
+
+impl DerefMut for Config {
+    fn deref_mut(&mut self) -> &mut symjit::Config {
+        &mut self.inner
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{anyhow, Result};
+#[cfg(feature = "test-util")]
+use std::cell::RefCell;
+
+use crate::runners::has_simd;
+use crate::Application;
+
+/// An instruction-set extension that generated code may depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpuFeature {
+    /// The 256-bit AVX instructions used by the x86-64 SIMD code.
+    Avx,
+    /// The Advanced SIMD instructions used by the aarch64 SIMD code.
+    Neon,
+}
+
+#[cfg(feature = "test-util")]
+thread_local! {
+    static OVERRIDE: RefCell<Option<Vec<CpuFeature>>> = const { RefCell::new(None) };
+}
+
+/// Makes the current thread behave as if the CPU supported exactly
+/// `features`, or restores the actual detection if `None`. This is meant for
+/// testing the feature checks of `load`.
+#[cfg(feature = "test-util")]
+pub fn override_cpu_features(features: Option<Vec<CpuFeature>>) {
+    OVERRIDE.with(|o| *o.borrow_mut() = features);
+}
+
+impl CpuFeature {
+    /// Whether the current CPU (or the override) supports this feature.
+    pub fn is_available(self) -> bool {
+        #[cfg(feature = "test-util")]
+        if let Some(available) = OVERRIDE.with(|o| o.borrow().clone()) {
+            return available.contains(&self);
+        }
+
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            CpuFeature::Avx => std::arch::is_x86_feature_detected!("avx"),
+            #[cfg(target_arch = "aarch64")]
+            CpuFeature::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            _ => false,
+        }
+    }
+}
+
+/// The features used by the machine code of `app`. The scalar code only uses
+/// the baseline instructions of the architecture; the SIMD code, if present,
+/// needs the vector extension.
+pub(crate) fn required_features(app: &Application) -> Vec<CpuFeature> {
+    if !has_simd(app) {
+        return Vec::new();
+    }
+
+    if cfg!(target_arch = "aarch64") {
+        vec![CpuFeature::Neon]
+    } else {
+        vec![CpuFeature::Avx]
+    }
+}
+
+/// Returns an error naming the first feature of `required` that is not available.
+pub(crate) fn check_features(required: &[CpuFeature]) -> Result<()> {
+    match required.iter().find(|f| !f.is_available()) {
+        Some(f) => Err(anyhow!(
+            "the compiled code requires {:?}, which this CPU does not support",
+            f
+        )),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use symbolica::evaluate::{Instruction, Slot};
+
+use crate::passes::{self, op_name};
+use crate::Complex;
+
+/// Renders the instructions as a Graphviz digraph. Parameters and constants
+/// are leaf nodes, each instruction is a node labeled with its operation (see
+/// `op_name`), and each output is a sink node. An edge runs from the node that
+/// last wrote a slot to every instruction that reads it.
+pub(crate) fn render(instructions: &[Instruction], constants: &[Complex<f64>]) -> String {
+    let mut dot = String::from("digraph kernel {\n");
+    let mut leaves: HashMap<Slot, String> = HashMap::new();
+    let mut defs: HashMap<Slot, String> = HashMap::new();
+
+    // writing to a String cannot fail, so the results of writeln! are ignored
+    let mut node = |dot: &mut String, slot: Slot, defs: &HashMap<Slot, String>| -> String {
+        if let Some(id) = defs.get(&slot) {
+            return id.clone();
+        }
+
+        if let Some(id) = leaves.get(&slot) {
+            return id.clone();
+        }
+
+        let (id, label, shape) = match slot {
+            Slot::Param(i) => (format!("p{}", i), format!("param {}", i), "box"),
+            Slot::Const(i) => {
+                let c = constants[i];
+                let label = if c.im == 0.0 {
+                    format!("{}", c.re)
+                } else {
+                    format!("{}{:+}i", c.re, c.im)
+                };
+                (format!("c{}", i), label, "plaintext")
+            }
+            // read before written, e.g., an output used as an accumulator
+            Slot::Temp(i) => (format!("t{}", i), format!("temp {}", i), "box"),
+            Slot::Out(i) => (format!("o{}", i), format!("out {}", i), "box"),
+        };
+
+        let _ = writeln!(dot, "    {} [label=\"{}\", shape={}];", id, label, shape);
+        leaves.insert(slot, id.clone());
+        id
+    };
+
+    for (k, q) in instructions.iter().enumerate() {
+        let id = format!("n{}", k);
+        let shape = match q {
+            Instruction::Label(_) | Instruction::IfElse(..) | Instruction::Goto(_) => "diamond",
+            _ => "ellipse",
+        };
+        let _ = writeln!(
+            dot,
+            "    {} [label=\"{}\", shape={}];",
+            id,
+            op_name(q),
+            shape
+        );
+
+        for arg in passes::args(q) {
+            let src = node(&mut dot, arg, &defs);
+            let _ = writeln!(dot, "    {} -> {};", src, id);
+        }
+
+        if let Some(lhs) = passes::lhs(q) {
+            defs.insert(lhs, id);
+        }
+    }
+
+    let mut outs: Vec<(usize, &String)> = defs
+        .iter()
+        .filter_map(|(slot, id)| match slot {
+            Slot::Out(i) => Some((*i, id)),
+            _ => None,
+        })
+        .collect();
+    outs.sort();
+
+    for (i, src) in outs {
+        let _ = writeln!(
+            dot,
+            "    out{} [label=\"out {}\", shape=doublecircle];",
+            i, i
+        );
+        let _ = writeln!(dot, "    {} -> out{};", src, i);
+    }
+
+    dot.push_str("}\n");
+    dot
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+
+/// The stage of the pipeline at which compilation failed.
+///
+/// The public functions return `anyhow::Result`, and the errors raised by the
+/// pipeline carry a `BridgeError`, which can be recovered with
+/// `err.downcast_ref::<BridgeError>()`.
+#[derive(Debug)]
+pub enum BridgeError {
+    /// The expression or a parameter could not be parsed.
+    Parse(String),
+    /// Symbolica could not build the evaluator.
+    EvaluatorBuild(String),
+    /// The instruction stream could not be translated, e.g., because it is
+    /// malformed or calls an unknown function.
+    Translate(anyhow::Error),
+    /// Machine code generation failed or exceeded the code size limit.
+    Codegen(anyhow::Error),
+}
+
+impl BridgeError {
+    pub(crate) fn translate(e: anyhow::Error) -> anyhow::Error {
+        BridgeError::Translate(e).into()
+    }
+
+    pub(crate) fn codegen(e: anyhow::Error) -> anyhow::Error {
+        BridgeError::Codegen(e).into()
+    }
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BridgeError::Parse(e) => write!(f, "parse error: {}", e),
+            BridgeError::EvaluatorBuild(e) => write!(f, "cannot build the evaluator: {}", e),
+            BridgeError::Translate(e) => write!(f, "translation error: {}", e),
+            BridgeError::Codegen(e) => write!(f, "code generation error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BridgeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BridgeError::Translate(e) | BridgeError::Codegen(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Control of the floating point environment around kernel calls.
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use std::arch::asm;
+
+/// Sets flush-to-zero and denormals-are-zero (FTZ/DAZ in MXCSR on x86-64,
+/// FZ in FPCR on aarch64) while alive and restores the previous state when
+/// dropped. On other architectures, it does nothing.
+pub(crate) struct FlushDenormals {
+    saved: Option<u64>,
+}
+
+#[cfg(target_arch = "x86_64")]
+const FLUSH_BITS: u64 = 0x8040; // FTZ (bit 15) | DAZ (bit 6)
+
+#[cfg(target_arch = "aarch64")]
+const FLUSH_BITS: u64 = 1 << 24; // FZ
+
+#[cfg(target_arch = "x86_64")]
+fn read_control() -> u64 {
+    let mut csr: u32 = 0;
+    unsafe { asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack)) };
+    csr as u64
+}
+
+#[cfg(target_arch = "x86_64")]
+fn write_control(value: u64) {
+    let csr = value as u32;
+    unsafe { asm!("ldmxcsr [{}]", in(reg) &csr, options(nostack)) };
+}
+
+#[cfg(target_arch = "aarch64")]
+fn read_control() -> u64 {
+    let fpcr: u64;
+    unsafe { asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack)) };
+    fpcr
+}
+
+#[cfg(target_arch = "aarch64")]
+fn write_control(value: u64) {
+    unsafe { asm!("msr fpcr, {}", in(reg) value, options(nomem, nostack)) };
+}
+
+impl FlushDenormals {
+    /// Enables flushing if `enable` is true; otherwise, the guard is inert.
+    pub fn new(enable: bool) -> Self {
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        if enable {
+            let saved = read_control();
+            write_control(saved | FLUSH_BITS);
+            return FlushDenormals { saved: Some(saved) };
+        }
+
+        let _ = enable;
+        FlushDenormals { saved: None }
+    }
+}
+
+impl Drop for FlushDenormals {
+    fn drop(&mut self) {
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        if let Some(saved) = self.saved {
+            write_control(saved);
+        }
+    }
+}
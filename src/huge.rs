@@ -6,8 +6,6 @@ use symbolica::{
     evaluate::ExpressionEvaluator,
 };
 
-use num_complex;
-use pyo3;
 use rand::prelude::*;
 use symjit_bridge::{CompiledComplexRunner, Config};
 
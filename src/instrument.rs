@@ -0,0 +1,71 @@
+//! Optional call counters for the compiled runners.
+
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::asm;
+
+/// Reads the processor timestamp counter (`rdtsc` on x86-64 and `cntvct_el0`
+/// on aarch64), or returns 0 on other architectures.
+pub(crate) fn timestamp() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        unsafe { std::arch::x86_64::_rdtsc() }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let t: u64;
+        unsafe { asm!("mrs {}, cntvct_el0", out(reg) t, options(nomem, nostack)) };
+        t
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        0
+    }
+}
+
+/// Estimates the number of entries into the generated code of a kernel from
+/// the number of rows evaluated, and records the timestamp of the last call.
+pub(crate) struct Counters {
+    calls: AtomicU64,
+    last_timestamp: AtomicU64,
+    /// The number of lanes of the SIMD code, or 1 if there is none.
+    lanes: usize,
+}
+
+impl Counters {
+    pub fn new(lanes: usize) -> Counters {
+        Counters {
+            calls: AtomicU64::new(0),
+            last_timestamp: AtomicU64::new(0),
+            lanes,
+        }
+    }
+
+    /// Records an `evaluate_matrix` call on `n` rows of `T`. The rows of f64
+    /// are assumed to run in SIMD groups of `lanes` rows plus one scalar entry
+    /// for each remaining row; vector elements enter the code once per row.
+    /// The scalar reruns of SIMD groups whose lanes take different branches
+    /// are not seen, so the count is an estimate.
+    pub fn record<T>(&self, n: usize) {
+        let entries = if size_of::<T>() == size_of::<f64>() {
+            n / self.lanes + n % self.lanes
+        } else {
+            n
+        };
+
+        self.calls.fetch_add(entries as u64, Ordering::Relaxed);
+        self.last_timestamp.store(timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn estimated_calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    pub fn last_timestamp(&self) -> u64 {
+        self.last_timestamp.load(Ordering::Relaxed)
+    }
+}
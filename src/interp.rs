@@ -0,0 +1,409 @@
+use anyhow::{anyhow, Result};
+use std::ops::{Add, Div, Mul, Sub};
+use wide::{f64x2, f64x4};
+
+use symbolica::atom::Symbol;
+use symbolica::evaluate::{ExpressionEvaluator, Instruction, Slot};
+
+use crate::{passes, vmath};
+
+#[cfg(feature = "arbitrary-precision")]
+use rug::{ops::Pow, Float};
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for f64 {}
+    impl Sealed for wide::f64x2 {}
+    impl Sealed for wide::f64x4 {}
+}
+
+/// An element type that packs `LANES` independent f64 values. Implemented
+/// for `f64`, `f64x2`, and `f64x4` only.
+pub trait SimdElem:
+    Copy
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + sealed::Sealed
+{
+    const LANES: usize;
+
+    fn splat(x: f64) -> Self;
+    fn map(self, f: impl Fn(f64) -> f64) -> Self;
+    fn zip(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self;
+    /// Lane-wise `if cond != 0 { t } else { f }`.
+    fn select(cond: Self, t: Self, f: Self) -> Self;
+}
+
+impl SimdElem for f64 {
+    const LANES: usize = 1;
+
+    fn splat(x: f64) -> Self {
+        x
+    }
+
+    fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        f(self)
+    }
+
+    fn zip(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self {
+        f(self, other)
+    }
+
+    fn select(cond: Self, t: Self, f: Self) -> Self {
+        if cond != 0.0 {
+            t
+        } else {
+            f
+        }
+    }
+}
+
+macro_rules! impl_simd_elem {
+    ($t:ident, $n:literal) => {
+        impl SimdElem for $t {
+            const LANES: usize = $n;
+
+            fn splat(x: f64) -> Self {
+                $t::splat(x)
+            }
+
+            fn map(self, f: impl Fn(f64) -> f64) -> Self {
+                $t::from(self.to_array().map(f))
+            }
+
+            fn zip(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self {
+                let a = self.to_array();
+                let b = other.to_array();
+                $t::from(std::array::from_fn::<f64, $n, _>(|i| f(a[i], b[i])))
+            }
+
+            fn select(cond: Self, t: Self, f: Self) -> Self {
+                let c = cond.to_array();
+                let t = t.to_array();
+                let f = f.to_array();
+                $t::from(std::array::from_fn::<f64, $n, _>(|i| {
+                    if c[i] != 0.0 {
+                        t[i]
+                    } else {
+                        f[i]
+                    }
+                }))
+            }
+        }
+    };
+}
+
+impl_simd_elem!(f64x2, 2);
+impl_simd_elem!(f64x4, 4);
+
+#[derive(Clone, Copy)]
+enum Builtin {
+    Exp,
+    Log,
+    Sin,
+    Cos,
+    Sqrt,
+}
+
+impl Builtin {
+    fn from_symbol(fun: Symbol) -> Option<Builtin> {
+        let table = [
+            (Symbol::EXP, Builtin::Exp),
+            (Symbol::LOG, Builtin::Log),
+            (Symbol::SIN, Builtin::Sin),
+            (Symbol::COS, Builtin::Cos),
+            (Symbol::SQRT, Builtin::Sqrt),
+        ];
+
+        table.iter().find(|(s, _)| *s == fun).map(|(_, f)| *f)
+    }
+
+    fn eval(self, x: f64) -> f64 {
+        match self {
+            Builtin::Exp => x.exp(),
+            Builtin::Log => x.ln(),
+            Builtin::Sin => x.sin(),
+            Builtin::Cos => x.cos(),
+            Builtin::Sqrt => x.sqrt(),
+        }
+    }
+
+    /// The vectorized polynomial versions of `vmath`.
+    fn eval_fast<T: SimdElem>(self, x: T, reduced: bool) -> T {
+        if reduced {
+            return match self {
+                Builtin::Exp => vmath::exp_reduced(x),
+                Builtin::Log => vmath::log_reduced(x),
+                Builtin::Sin => vmath::sin_reduced(x),
+                Builtin::Cos => vmath::cos_reduced(x),
+                Builtin::Sqrt => x.map(f64::sqrt),
+            };
+        }
+
+        match self {
+            Builtin::Exp => vmath::exp(x),
+            Builtin::Log => vmath::log(x),
+            Builtin::Sin => vmath::sin(x),
+            Builtin::Cos => vmath::cos(x),
+            Builtin::Sqrt => x.map(f64::sqrt),
+        }
+    }
+
+    #[cfg(feature = "arbitrary-precision")]
+    fn eval_float(self, x: Float) -> Float {
+        match self {
+            Builtin::Exp => x.exp(),
+            Builtin::Log => x.ln(),
+            Builtin::Sin => x.sin(),
+            Builtin::Cos => x.cos(),
+            Builtin::Sqrt => x.sqrt(),
+        }
+    }
+}
+
+enum Step {
+    Add(Slot, Vec<Slot>),
+    Mul(Slot, Vec<Slot>),
+    Pow(Slot, Slot, i32),
+    Powf(Slot, Slot, Slot),
+    Assign(Slot, Slot),
+    Fun(Slot, Builtin, Slot),
+    Join(Slot, Slot, Slot, Slot),
+}
+
+impl Step {
+    /// The name of the operation, as returned by `passes::op_name`.
+    fn op_name(&self) -> &'static str {
+        match self {
+            Step::Add(..) => "add",
+            Step::Mul(..) => "mul",
+            Step::Pow(..) => "pow",
+            Step::Powf(..) => "powf",
+            Step::Assign(..) => "assign",
+            Step::Fun(_, Builtin::Exp, _) => "exp",
+            Step::Fun(_, Builtin::Log, _) => "log",
+            Step::Fun(_, Builtin::Sin, _) => "sin",
+            Step::Fun(_, Builtin::Cos, _) => "cos",
+            Step::Fun(_, Builtin::Sqrt, _) => "sqrt",
+            Step::Join(..) => "join",
+        }
+    }
+}
+
+/// A straight-line real program interpreted in the bridge, independent of
+/// symjit's code generators.
+pub(crate) struct Program {
+    steps: Vec<Step>,
+    constants: Vec<f64>,
+    num_temps: usize,
+    pub count_params: usize,
+    pub count_obs: usize,
+    /// Use the vectorized approximations of `vmath` for the builtins.
+    pub fast: bool,
+    /// Use the lower-degree (`Accuracy::Reduced`) approximations of `vmath`.
+    pub reduced: bool,
+}
+
+impl Program {
+    pub fn new(ev: &ExpressionEvaluator<f64>) -> Result<Program> {
+        let (instructions, num_temps, constants) = ev.export_instructions();
+        passes::validate_instructions(&instructions, num_temps, constants.len())?;
+
+        if passes::has_control_flow(&instructions) {
+            return Err(anyhow!(
+                "control flow is not supported by the bridge interpreter"
+            ));
+        }
+
+        let count_params = passes::count_params(&instructions);
+        let count_obs = passes::count_outputs(&instructions);
+        let mut steps = Vec::with_capacity(instructions.len());
+
+        for q in instructions {
+            let step = match q {
+                Instruction::Add(lhs, args, _) => Step::Add(lhs, args),
+                Instruction::Mul(lhs, args, _) => Step::Mul(lhs, args),
+                Instruction::Pow(lhs, arg, p, _) => Step::Pow(lhs, arg, p as i32),
+                Instruction::Powf(lhs, arg, p, _) => Step::Powf(lhs, arg, p),
+                Instruction::Assign(lhs, rhs) => Step::Assign(lhs, rhs),
+                Instruction::Fun(lhs, fun, arg, _) => {
+                    let f = Builtin::from_symbol(fun.get_symbol())
+                        .ok_or_else(|| anyhow!("unsupported builtin function {:?}", fun))?;
+                    Step::Fun(lhs, f, arg)
+                }
+                Instruction::Join(lhs, cond, t, f) => Step::Join(lhs, cond, t, f),
+                Instruction::ExternalFun(_, op, _) => {
+                    return Err(anyhow!(
+                        "external function {} is not supported by the bridge interpreter",
+                        op
+                    ))
+                }
+                Instruction::Label(_) | Instruction::IfElse(_, _) | Instruction::Goto(_) => {
+                    unreachable!()
+                }
+            };
+            steps.push(step);
+        }
+
+        Ok(Program {
+            steps,
+            constants,
+            num_temps,
+            count_params,
+            count_obs,
+            fast: false,
+            reduced: false,
+        })
+    }
+
+    /// Evaluates one row: `args` holds `count_params` elements and `outs`
+    /// receives `count_obs` elements. `temps` is scratch space reused
+    /// across calls.
+    pub fn run<T: SimdElem>(&self, args: &[T], outs: &mut [T], temps: &mut Vec<T>) {
+        self.run_until(args, outs, temps, |_| false);
+    }
+
+    /// Evaluates one row step by step and returns the index of the first
+    /// step (which is also the index of its instruction in the evaluator) whose
+    /// result is not finite, with the name of its operation.
+    pub fn trace_nonfinite(&self, args: &[f64]) -> Option<(usize, &'static str)> {
+        let mut outs = vec![0.0; self.count_obs];
+        let mut temps = Vec::new();
+        self.run_until(args, &mut outs, &mut temps, |x: f64| !x.is_finite())
+            .map(|i| (i, self.steps[i].op_name()))
+    }
+
+    /// Like `run`, but stops after the first step whose result satisfies
+    /// `stop` and returns its index.
+    fn run_until<T: SimdElem>(
+        &self,
+        args: &[T],
+        outs: &mut [T],
+        temps: &mut Vec<T>,
+        stop: impl Fn(T) -> bool,
+    ) -> Option<usize> {
+        temps.resize(self.num_temps, T::splat(0.0));
+
+        let get = |s: &Slot, outs: &[T], temps: &[T]| -> T {
+            match *s {
+                Slot::Param(id) => args[id],
+                Slot::Out(id) => outs[id],
+                Slot::Const(id) => T::splat(self.constants[id]),
+                Slot::Temp(id) => temps[id],
+            }
+        };
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let (lhs, val) = match step {
+                Step::Add(lhs, args) => {
+                    let mut v = get(&args[0], outs, temps);
+                    for s in args[1..].iter() {
+                        v = v + get(s, outs, temps);
+                    }
+                    (lhs, v)
+                }
+                Step::Mul(lhs, args) => {
+                    let mut v = get(&args[0], outs, temps);
+                    for s in args[1..].iter() {
+                        v = v * get(s, outs, temps);
+                    }
+                    (lhs, v)
+                }
+                Step::Pow(lhs, arg, p) => (lhs, get(arg, outs, temps).map(|x| x.powi(*p))),
+                Step::Powf(lhs, arg, p) => (
+                    lhs,
+                    get(arg, outs, temps).zip(get(p, outs, temps), f64::powf),
+                ),
+                Step::Assign(lhs, rhs) => (lhs, get(rhs, outs, temps)),
+                Step::Fun(lhs, f, arg) if self.fast || self.reduced => {
+                    (lhs, f.eval_fast(get(arg, outs, temps), self.reduced))
+                }
+                Step::Fun(lhs, f, arg) => (lhs, get(arg, outs, temps).map(|x| f.eval(x))),
+                Step::Join(lhs, cond, t, f) => (
+                    lhs,
+                    T::select(
+                        get(cond, outs, temps),
+                        get(t, outs, temps),
+                        get(f, outs, temps),
+                    ),
+                ),
+            };
+
+            match *lhs {
+                Slot::Out(id) => outs[id] = val,
+                Slot::Temp(id) => temps[id] = val,
+                _ => unreachable!(),
+            }
+
+            if stop(val) {
+                return Some(i);
+            }
+        }
+
+        None
+    }
+
+    /// Evaluates one row like `run`, but in `bits`-bit floating point, and
+    /// rounds the outputs to f64.
+    #[cfg(feature = "arbitrary-precision")]
+    pub fn run_precise(&self, args: &[f64], outs: &mut [f64], bits: u32) {
+        let zero = Float::new(bits);
+        let args: Vec<Float> = args.iter().map(|x| Float::with_val(bits, *x)).collect();
+        let constants: Vec<Float> = self
+            .constants
+            .iter()
+            .map(|x| Float::with_val(bits, *x))
+            .collect();
+        let mut vals = vec![zero.clone(); self.count_obs];
+        let mut temps = vec![zero; self.num_temps];
+
+        for step in self.steps.iter() {
+            let get = |s: &Slot| -> &Float {
+                match *s {
+                    Slot::Param(id) => &args[id],
+                    Slot::Out(id) => &vals[id],
+                    Slot::Const(id) => &constants[id],
+                    Slot::Temp(id) => &temps[id],
+                }
+            };
+
+            let (lhs, val) = match step {
+                Step::Add(lhs, args) => {
+                    let mut v = get(&args[0]).clone();
+                    for s in args[1..].iter() {
+                        v += get(s);
+                    }
+                    (lhs, v)
+                }
+                Step::Mul(lhs, args) => {
+                    let mut v = get(&args[0]).clone();
+                    for s in args[1..].iter() {
+                        v *= get(s);
+                    }
+                    (lhs, v)
+                }
+                Step::Pow(lhs, arg, p) => (lhs, Float::with_val(bits, get(arg).pow(*p))),
+                Step::Powf(lhs, arg, p) => (lhs, Float::with_val(bits, get(arg).pow(get(p)))),
+                Step::Assign(lhs, rhs) => (lhs, get(rhs).clone()),
+                Step::Fun(lhs, f, arg) => (lhs, f.eval_float(get(arg).clone())),
+                Step::Join(lhs, cond, t, f) => {
+                    let v = if get(cond).is_zero() { get(f) } else { get(t) };
+                    (lhs, v.clone())
+                }
+            };
+
+            match *lhs {
+                Slot::Out(id) => vals[id] = val,
+                Slot::Temp(id) => temps[id] = val,
+                _ => unreachable!(),
+            }
+        }
+
+        for (y, v) in outs.iter_mut().zip(vals.iter()) {
+            *y = v.to_f64();
+        }
+    }
+}
@@ -27,29 +27,232 @@
 //! * `CompiledComplexRunner`, corresponding to `CompiledComplexEvaluator`.
 //! * `InterpretedRealRunner`, bytecode interpreter, generally similar to `ExpressionEvaluator`.
 //! * `InterpretedComplexRunner`, bytecode interpreter, generally similar to `ExpressionEvaluator`.
+//! * `InterpretedSimdRealRunner`, interprets the instructions over `f64x4` or `f64x2` lanes
+//!   without generating machine code (no external functions or control flow).
+//! * `ThreadedRealRunner`, a sealed `CompiledRealRunner` (see `into_threaded()`) that splits large
+//!   batches across threads.
+//! * `CompiledBoolRunner`, a real kernel whose outputs (e.g., of the comparison builtins) are read as
+//!   booleans: `evaluate` writes `bool`s, `evaluate_bitmask` packs each output column into `u64`
+//!   words, and `evaluate_lanes` returns one lane mask per `f64x4` output.
+//! * `CompiledPolarRunner`, a `CompiledComplexRunner` that writes each output `z` as `[|z|, arg(z)]`,
+//!   with the phase in `(-pi, pi]` (0 for `z == 0`).
+//! * `CompiledPhasorRunner`, a `CompiledComplexRunner` that normalizes each output `z` to the unit
+//!   phasor `z / |z|` (`1 + 0i` for `z == 0`), e.g., for `exp(i*phi)` in wave optics.
+//! * `RationalRunner`, a two-output kernel (numerator `P` and denominator `Q`, built by
+//!   `compile_rational(num, den, params, config)`) that returns `P / Q` per row, or NaN where `Q == 0`.
+//!   The ratio is not simplified algebraically, so it loses precision near removable singularities.
+//! * `RobustRealRunner`, uses `CompiledRealRunner` if compilation succeeds and falls back to
+//!   `InterpretedRealRunner` otherwise (`is_compiled()` reports which one is in use).
 //!
 //! Each runner has four main methods:
 //!
 //! * `compile(ev: &ExpressionEvaluator<T>, config: Config)`: the main constructor. `T` is either `f64`
-//!     or `Complex<f64>`, and `config` is an object of type `Config`. For most applications, the
-//!     default config suffices. However, `Config.use_threads(bool)` is useful to enable multi-threading.
+//!   or `Complex<f64>`, and `config` is an object of type `Config`. For most applications, the
+//!   default config suffices. However, `Config.use_threads(bool)` is useful to enable multi-threading.
 //! * `compile_with_funcs(ev: &ExpressionEvaluator<T>, config: Config, df: &Defuns, num_params: usize)`: Same as
-//!     `compile` but with the additional of external functions defined in a `Defuns` structure and `num_prams`.
+//!   `compile` but with the additional of external functions defined in a `Defuns` structure and `num_prams`.
 //! * `compile_string(model: String, config: Config)`: `model` is a string generated using `get_instruction` method
-//!     in Python, and `config` is an object of type `Config`.
+//!   in Python, and `config` is an object of type `Config`.
 //! * `compile_string_with_funcs(model: String, config: Config, df: &Defuns, num_params: usize)`: Same as
-//!     `compile_string` but with the additional of external functions defined in a `Defuns` structure
-//!     and `num_params`.
+//!   `compile_string` but with the additional of external functions defined in a `Defuns` structure
+//!   and `num_params`.
 //! * `evaluate(args, outs)`: similar to the corresponding method of the `Evaluator`s.
-//! * `save(filename)`.
-//! * `load(filename)`.
+//! * `try_evaluate(args, outs)` (`CompiledRealRunner`): checks that the element type (`f64`, `f64x2`,
+//!   or `f64x4`; see the sealed `SimdElem` trait) matches the SIMD width of the runner and that the
+//!   buffers hold whole rows before evaluating, and returns an error otherwise.
+//! * `evaluate_matrix_layout(args, outs, nrows, layout)` (`CompiledRealRunner` and `ThreadedRealRunner`):
+//!   writes the outputs in `OutputLayout::RowMajor` or `OutputLayout::ColumnMajor` (`outs[j*nrows + i]`)
+//!   order. With `ColumnMajor`, each thread of `ThreadedRealRunner` writes its own contiguous segment
+//!   of each column.
+//! * `evaluate_simd_reduce(args, op)` (`CompiledRealRunner`): evaluates `f64x4` rows of a single-output
+//!   kernel and combines all the lanes with `ReduceOp::Sum` or `ReduceOp::Product`.
+//! * `evaluate_grid(axes)` (`CompiledRealRunner`): evaluates the Cartesian grid of one axis of values
+//!   per parameter and returns the outputs in row-major grid order (e.g., a heatmap for two axes).
+//! * `evaluate_rows(rows, out)` (`CompiledRealRunner`): evaluates an iterator of parameter rows
+//!   (e.g., from a `Vec<Vec<f64>>`) and appends the outputs to `out`; fails on the first row of the wrong length.
+//! * `evaluate_masked(args, outs, mask, nrows)` (`CompiledRealRunner`): evaluates only the rows whose
+//!   `mask` entry is true, gathering them so that the SIMD code runs on active rows only, and leaves
+//!   the outputs of the other rows untouched.
+//! * `evaluate_auto_simd(args, outs, nrows)` (`CompiledRealRunner`): an alias of `evaluate` on the first
+//!   `nrows` rows. `evaluate` on `f64` rows already runs the SIMD code on groups of rows, with a scalar
+//!   tail.
+//! * `evaluate_with_ints(int_slots, ints, floats, outs, nrows)` (`CompiledRealRunner`): takes the
+//!   parameters listed in `int_slots` from a row-major `i32` buffer, cast to f64, and the others from
+//!   `floats`, so that integer columns need not be converted beforehand.
+//! * `evaluate_columns(columns, outs)` (`CompiledRealRunner`): evaluates the rows of a columnar batch
+//!   (e.g., the `f64` columns of an Arrow record batch), given as one slice per parameter, without
+//!   building a row-major copy of the batch.
+//! * `evaluate_matrix_checked(args, outs, nrows)` (`CompiledRealRunner`): evaluates the rows and returns
+//!   a flag per row that is true if all its outputs are finite, to filter out invalid parameter sets.
+//! * `evaluate_matrix_traced(args, outs, nrows)` (`CompiledRealRunner`, with
+//!   `Config.set_trace_nonfinite(true)`): if an output is not finite, re-runs the first such row through
+//!   the interpreter and returns a `NonFiniteTrace` with the row and the index and operation (e.g.,
+//!   "sqrt") of the first instruction whose result is not finite.
+//! * `evaluate_windowed(signal, window, stride, outs)` (`CompiledRealRunner`): evaluates the kernel over
+//!   sliding windows of a 1D signal, one output row per window position, without building the
+//!   overlapping args matrix.
+//! * `map_outputs(f)` (`CompiledRealRunner`): sets a closure that is applied to the outputs of each row
+//!   right after the kernel (e.g., a table lookup); `into_threaded()` keeps it, and each thread applies
+//!   it to its own rows.
+//! * `entry_ptr()` and `context_ptr()` (`CompiledRealRunner`): a C ABI entry point (`EntryFn`), called as
+//!   `entry(context, args, outs, nrows)` with the parameters and outputs stored row by row, to call the
+//!   kernel from C or Python (ctypes/cffi) without going through Rust. `entry_ptr()` is a Rust trampoline
+//!   that takes the runner as its context pointer, not the raw function pointer of the generated code; it
+//!   returns 0, or -1 if the evaluation panicked.
+//! * `warmup()`: runs the kernel once on zeroed inputs to avoid a latency spike on the first call.
+//! * `evaluate_split(re, im, out_re, out_im)` (complex runners): evaluates complex data stored as
+//!   separate arrays of real and imaginary parts.
+//! * `evaluate_conjugate_pairs(z, outs_z, outs_conj)` (`CompiledComplexRunner`): for kernels with real
+//!   coefficients, evaluates the rows once and writes the outputs at `z` and at `conj(z)`, using
+//!   `f(conj(z)) == conj(f(z))`.
+//! * `evaluate_f64x2_layout(args, outs)` (`CompiledComplexRunner`, x86-64): a layout adapter that
+//!   evaluates `Complex<f64x2>` rows (e.g., to match a 128-bit data layout) by packing pairs of them
+//!   into the 4-lane kernel; the generated code is unchanged.
+//! * `save(filename)`: writes the code after a header with a magic number, the format version
+//!   (`SAVE_FORMAT_VERSION`), the complex and SIMD flags, and the output names, if any (see
+//!   `NamedApplication::save`).
+//! * `load(filename)`: files of another format version, or of a complex kernel for a real runner (and
+//!   vice versa), are rejected with an error. The compiled runners also check that the CPU supports
+//!   the instruction-set extensions the code uses (see `required_features()`).
 //!
 //! Both `CompiledRealRunner` and `CompiledComplexRunner` may use SIMD instructions if it is available
 //!     and the number of input rows is equal or more than the number of SIMD lanes (4 in AVX, 2 in aarch64).
 //!
+//! `ThreadedRealRunner::evaluate_matrix_sum(args, nrows)` returns the per-output sums over the rows in
+//! a fixed reduction order, independent of the number of threads, and `last_evaluate_used_threads()`
+//! reports whether the last call actually ran in parallel. `prepare(nrows)` pre-allocates the
+//! per-thread buffers for a given batch size. `evaluate_matrix_accumulate(args, nrows, bins, bin_fn)`
+//! calls `bin_fn(outs, bins)` on each row's outputs to accumulate them (e.g., into a histogram), with
+//! one local copy of `bins` per thread. `evaluate_matrix_argmin(args, nrows)` and
+//! `evaluate_matrix_argmax(args, nrows)` return the row with the smallest (largest) first output and
+//! its value, reduced in parallel in one pass. `evaluate_matrix_cancellable(args, outs, nrows, token)`
+//! stops early once the `AtomicBool` `token` is set and returns `false` (the outputs are then
+//! undefined), or `true` if all rows were evaluated. With `Config.set_pin_threads(true)`, the worker
+//! threads are pinned to cores (a no-op on platforms without a thread affinity API).
+//!
+//! `InterpretedSimdRealRunner::compile_with_config(ev, &config)` with `FpModel::Fast` computes `exp`,
+//! `log`, `sin`, and `cos` with the polynomial approximations of `vmath`, which keep all the lanes in
+//! vector registers. `exp` is within 2 ULP; `log`, `sin`, and `cos` are within a few ULP (`sin` and
+//! `cos` for `|x| < 1e6`). `Config.set_transcendental_accuracy(Accuracy::Reduced)` switches to
+//! lower-degree polynomials that are faster but only accurate to about 1e-6 relative error.
+//!
+//! `Config.set_complex_layout` selects how `CompiledComplexRunner::evaluate_split` feeds the split
+//! data to the kernel: `ComplexLayout::Interleaved` (`Complex<f64>` rows, the default), `Planar`
+//! (`Complex<f64x4>` groups of rows, x86-64 only), or `Auto`, which times both at compile time and
+//! keeps the faster one (`complex_layout()`).
+//!
+//! The runners return an error for a kernel without outputs or without parameters. A constant
+//! expression (e.g., `5`) does not use its parameters; pass their number as `num_params` to
+//! `compile_with_funcs` to compile it as a kernel that ignores its arguments.
+//!
+//! `Scalars` holds either real (`Scalars::real(n)`) or complex (`Scalars::complex(n)`) buffers, so
+//! code that handles both kinds of runners can manage its buffers uniformly: `as_flat_mut()` views
+//! either as a flat `&mut [f64]` (interleaved re/im pairs for complex), and `as_real()`/`as_complex()`
+//! return the typed slices to pass to a runner.
+//!
+//! `pack_complex_aos_to_simd(values, row_len)` transposes complex rows (`row_len` values each, one row
+//! after another) into the `Complex<f64x4>` rows of the SIMD complex runners, padding the last group
+//! of 4 rows with zeros, and `unpack_complex_simd_to_aos(packed, row_len, nrows)` reverses it.
+//!
+//! `compile_split(ev, config)` compiles every output of a multi-output evaluator into its own
+//! `Application`, so that the outputs can be evaluated independently.
+//!
+//! `validate(ev, &config)` runs the translation and validation passes and builds the bytecode of the
+//! kernel, which checks every function against the symjit builtins and the registered `Defuns`,
+//! without generating machine code (no executable memory is allocated), which is useful to check an
+//! expression in CI.
+//!
+//! `compile_many(evs, config)` compiles a list of real evaluators, lowering them in parallel, and returns the
+//! resulting `Application`s in the same order. `evaluate_multi(apps, args, nrows, outs)` evaluates
+//! several kernels with the same parameters over one input matrix, visiting the rows once in small
+//! blocks that stay in cache while every kernel is applied, with the outputs of `apps[i]` in `outs[i]`.
+//!
+//! `compile_retained(ev, config, num_params)` compiles like `compile`, but returns a
+//! `RetainedApplication` that keeps the instruction stream (an `Application` does not), so that
+//! `recompile(config)` can build the kernel again under another config, e.g., as complex after compiling
+//! it as real, without the evaluator. The bridge passes of the original config are kept.
+//!
+//! `compile_lazy(ev, config, num_params)` returns a `LazyApplication`, which generates the machine code
+//! on its first evaluation (`evaluate`, `evaluate_single`, `evaluate_matrix`, or `app`), so that
+//! constructing many kernels at startup does not pay the JIT cost of the ones that are never used.
+//! `is_compiled()` reports whether the code has been generated.
+//!
+//! `Config.set_fp_model(FpModel::Fast)` allows FMA contraction in the real kernels; the default,
+//! `FpModel::Strict`, gives results that do not depend on the SIMD width. Without a call to
+//! `set_fp_model`, the `fastmath` flag of the underlying symjit config is kept. With `FpModel::Fast`
+//! and the real natives registered, `Config.set_fast_rsqrt(Some(steps))` makes `x^-0.5` and
+//! `1/sqrt(x)` call the `rsqrt` natives: a single-precision estimate (relative error up to about
+//! 1e-7) refined by `steps` (0 or 1) Newton-Raphson steps (about 1e-14 after one step).
+//!
+//! `Config.set_max_code_bytes(Some(limit))` makes compilation fail if the generated machine code
+//! exceeds `limit` bytes, e.g., to catch machine-generated expressions that would thrash the
+//! instruction cache. The interpreted runners are not affected.
+//!
+//! `Config.set_optimize_for(OptLevel::Size)` trades throughput for smaller code: only the scalar code
+//! is generated (so the compiled runners take `f64` rows only), integer powers beyond the squares call
+//! `pow` instead of being expanded into multiplications, and FMA contraction is disabled.
+//! `code_size(app)` returns the size of the machine code in bytes.
+//!
+//! `Config.set_round_mode(mode)` selects how the rational constants are rounded to `f64` when the
+//! bridge builds the evaluator (`compile_str`, `compile_named`, `compile_derivative`,
+//! `compile_value_and_gradient`, and `compile_rational`): `RoundMode::Nearest` (the default),
+//! `TowardZero`, `Up`, or `Down`. `round_rational(x, mode)` does the same for the evaluators built
+//! by hand.
+//!
+//! `Config.set_flush_denormals(true)` makes the compiled runners flush denormal numbers to zero
+//! (FTZ/DAZ) during evaluation, which avoids the slowdown of denormal arithmetic but turns values
+//! below `f64::MIN_POSITIVE` into zero.
+//!
+//! On multi-socket servers, `replicate_for_numa(app, config)` loads one copy of the code of an
+//! `Application` per NUMA node (`numa_nodes()` lists their CPUs), each on a thread of that node, and
+//! returns them as `Applet`s, so that the workers on each node execute node-local code. On other
+//! systems, it returns a single copy.
+//!
+//! `Config.set_instrument(true)` makes `CompiledRealRunner` estimate the entries into the generated
+//! code from the rows evaluated (`estimated_call_count()`: once per row for scalar code, once per group
+//! of lanes for SIMD code, without the scalar reruns of SIMD groups whose lanes branch differently) and
+//! record the timestamp counter of the last call (`last_call_timestamp()`). `into_threaded()` keeps
+//! the counters. It is off by default.
+//!
+//! `Config.set_self_check(true)` makes compilation cross-check the generated code against the
+//! bytecode interpreter on a few pseudo-random inputs and fail if they disagree (off by default).
+//!
+//! `compile_then(first, next, config)` composes two evaluators, feeding the outputs of `first`
+//! into the parameters of `next`, into a single `Application`.
+//!
+//! `compile_str(expr, params, config)` parses a string expression and compiles it as a real
+//! kernel in one call, e.g., `compile_str("x + y^2", &["x", "y"], Config::default())?`.
+//! `compile_derivative(expr, params, wrt, order, config)` differentiates an `Atom` `order` times
+//! (up to `MAX_DERIVATIVE_ORDER`) with respect to `params[wrt]` and compiles the result the same way.
+//! `compile_value_and_gradient(expr, params, wrt, config)` compiles a single kernel that returns the
+//! value followed by the partial derivatives with respect to the parameters in `wrt`, sharing their
+//! common subexpressions (`value_and_gradient_evaluator` returns the underlying evaluator).
+//! `compile_gradient_reverse(ev, config)` builds the gradient of a single-output evaluator by reverse-mode
+//! (adjoint) differentiation of its instruction stream, so all the partials cost a small multiple of one
+//! evaluation however many parameters there are; it supports straight-line code with `sin`, `cos`, `exp`,
+//! `log`, `sqrt`, and constant exponents.
+//!
+//! `compile_named(exprs, params, config)` compiles a list of `(name, Atom)` pairs into one multi-output
+//! kernel that remembers the output names: `output_name(idx)` and `output_index(name)` map between
+//! indices and names, and `evaluate_named(args)` returns the outputs of a row as a `HashMap`.
+//!
+//! `compile_affine(ev, config, num_params)` checks whether every output is affine in the parameters
+//! (`w . params + b`). If so, `is_affine()` is true, `affine_coeffs()` returns the weights and biases,
+//! the kernel is a dot product per output, and `evaluate_matrix` on `f64` rows is computed as a blocked
+//! matrix product; otherwise, the kernel is compiled as usual.
+//!
+//! Array parameters (e.g., the control points of a spline) are described by `ArrayParam::array(symbol, len)`
+//! and appear in the expression as the indexed atoms `symbol(0)`, ..., `symbol(len-1)`. `array_params(specs)`
+//! returns the parameter atoms in slot order, `compile_with_array_params(ev, specs, config)` compiles
+//! with each array in a contiguous range of slots, and `param_range(specs, symbol)` returns that range.
+//!
+//! The errors of the compilation pipeline carry a `BridgeError` (`Parse`, `EvaluatorBuild`,
+//! `Translate`, or `Codegen`) that tells where the failure occurred; recover it with
+//! `err.downcast_ref::<BridgeError>()`.
+//!
 //! ```rust
 //! use anyhow::Result;
-//! use symjit_bridge::{compile, Config};
+//! use symjit_bridge::{CompiledRealRunner, Config};
 
 //! use symbolica::{
 //!     atom::AtomCore,
@@ -80,6 +283,9 @@
 //! these functions to Symbolica by using `add_external_function`:
 
 //! ```rust
+//! # use anyhow::Result;
+//! # use symjit_bridge::{CompiledComplexRunner, Complex, ComplexFloat, Config};
+//! # use symbolica::{atom::AtomCore, evaluate::{FunctionMap, OptimizationSettings}, parse, symbol};
 //! fn test_external() -> Result<()> {
 //!     let params = vec![parse!("x"), parse!("y")];
 //!     let mut f = FunctionMap::new();
@@ -100,18 +306,124 @@
 //! }
 //! ```
 //!
+//! Special functions that are not among the symjit builtins (e.g., the complex `cerf`, `cerfc`, `cexpm1`, and `clog1p`, `tgamma`,
+//! `lgamma`, the inverse hyperbolic functions, and `floor`/`ceil`/`round_away`/`trunc`; see `natives::REAL_NATIVES` and
+//! `natives::COMPLEX_NATIVES`) are provided by the `natives` module. They are registered with
+//! `natives::add_real_natives` or `natives::add_complex_natives` into a `Defuns` and exposed to Symbolica as external
+//! functions of the same name. The real natives include `fmod(x, y)`, whose result has the sign of
+//! the dividend (`fmod(-7, 3) == -1`), and the IEEE `remainder(x, y)`, which rounds the quotient to
+//! the nearest even integer (`remainder(8, 3) == -1`). `round_away(x)` rounds half-way cases away from zero (`round_away(2.5) == 3`),
+//! while the symjit builtin `round(x)` rounds them to even (`round(2.5) == 2`). The symjit builtin `cbrt(x)` is the real cube root
+//! (`cbrt(-8) == -2`, unlike `x^(1/3)`) in the real runners and the principal root in the complex
+//! ones. The real natives `sigmoid(x)` and `softplus(x)` are numerically stable versions of
+//! `1/(1 + exp(-x))` and `log(1 + exp(x))`, which do not overflow for large `|x|`. Functions with
+//! several outputs, such as `sincos`, are registered
+//! with `natives::add_vector_func` as one external function per component (`sincos_0` and
+//! `sincos_1`), which share a cached call on the same arguments. With `Config.set_fuse_sincos(true)` and
+//! the real natives registered, `sin(x)` and `cos(x)` of the same argument are fused into one `sincos`
+//! call; `num_instructions(ev, config)` reports the instructions passed to the code generator,
+//! and `temp_count(ev, config)` the number of temporary slots they use (a proxy for register pressure).
+//! Identical constants are merged into a single entry of the constant pool, which `constants(ev, config)`
+//! returns.
+//! `to_dot(ev, config)` renders the same instructions as a Graphviz DAG (e.g., for `dot -Tpng`),
+//! where a node with several consumers is a shared subexpression.
+//! `Config.set_const_fold(true)` evaluates the instructions whose inputs are all constants
+//! (e.g., `exp(1)`) at compile time.
+//! `Config.set_input_transform(scales, offsets)` makes the kernel replace each parameter `x_i` with
+//! `(x_i - offsets[i]) * scales[i]` (e.g., to normalize the inputs) without a separate pass over the
+//! data; both vectors must have one entry per parameter.
+//! `Config.set_accurate_sum(true)` evaluates the sums of more than 8 terms as a balanced tree of
+//! pairwise additions, which reduces the rounding error of long sums with cancellation.
+//!
+//! External functions are assumed to be pure (free of side effects) by default. With
+//! `Config.set_assume_pure(false)`, a kernel that calls an external function is compiled without
+//! reassociation, and `ThreadedRealRunner` evaluates it on the calling thread, so the calls are made
+//! in row order.
+//!
+//! `Piecewise` builds piecewise-defined expressions from a list of `(condition, value)` atoms,
+//! lowered into nested `if` calls that are selected per row and per SIMD lane. The comparison
+//! builtins `lt`, `leq`, `gt`, and `geq` (returning a nonzero mask or 0) are convenient as conditions.
+//!
+//! ## Optional Features
+//!
+//! * `nalgebra`: adds `CompiledRealRunner::evaluate_nalgebra`, which evaluates the kernel over the
+//!   columns of a `DMatrix<f64>` (one evaluation point per column) and returns the outputs as a
+//!   `DMatrix<f64>`.
+//! * `test-util`: adds the `test_util` module with `is_close`, `all_close`, and `assert_close`
+//!   to compare real or complex outputs within an absolute plus relative tolerance, and
+//!   `override_cpu_features(features)`, which makes the current thread report the given CPU
+//!   features to test the feature checks of `load`.
+//! * `cli`: adds `run_repl(app, input, output)`, which reads rows of whitespace-separated
+//!   parameters line by line and writes one line of outputs per row, turning a real kernel
+//!   into a Unix filter.
+//! * `capi`: adds the `capi` module with the `extern "C"` functions `symjit_bridge_compile`,
+//!   `symjit_bridge_evaluate`, `symjit_bridge_count_params`, `symjit_bridge_count_obs`, and
+//!   `symjit_bridge_free` to compile and evaluate real kernels from C. The crate is also built as a
+//!   `staticlib`, so that C programs can link to these functions.
+//! * `arbitrary-precision`: adds `InterpretedRealRunner::compile_with_precision(ev, config, bits)`,
+//!   a reference interpreter that evaluates in `bits`-bit floating point (using `rug`) and rounds
+//!   the outputs to f64, to tell the rounding error of f64 from the error of an algorithm.
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::thread;
 
+pub use config::{Accuracy, ComplexLayout, Config, FpModel, OptLevel, RoundMode};
+#[cfg(feature = "test-util")]
+pub use cpu::override_cpu_features;
+pub use cpu::CpuFeature;
+pub use error::BridgeError;
+pub use interp::SimdElem;
+pub use numa::{numa_nodes, replicate_for_numa};
+pub use params::{array_params, param_range, ArrayParam};
+pub use passes::validate_instructions;
+pub use piecewise::Piecewise;
 pub use runners::{
-    CompiledComplexRunner, CompiledRealRunner, InterpretedComplexRunner, InterpretedRealRunner,
+    pack_complex_aos_to_simd, unpack_complex_simd_to_aos, CompiledBoolRunner,
+    CompiledComplexRunner, CompiledPhasorRunner, CompiledPolarRunner, CompiledRealRunner, EntryFn,
+    InterpretedComplexRunner, InterpretedRealRunner, InterpretedSimdRealRunner, NonFiniteTrace,
+    OutputLayout, RationalRunner, ReduceOp, RobustRealRunner, Scalars, ThreadedRealRunner,
 };
+pub use savefile::SAVE_FORMAT_VERSION;
 use symjit::{instruction, Compiler, Composer, Translator};
-pub use symjit::{Application, Complex, ComplexFloat, Config, Defuns};
+pub use symjit::{Application, Complex, ComplexFloat, Defuns};
 
-use symbolica::evaluate::{BuiltinSymbol, ExpressionEvaluator, Instruction, Slot};
+use symbolica::atom::{Atom, AtomCore, Symbol};
+use symbolica::domains::rational::Rational;
+use symbolica::evaluate::{
+    BuiltinSymbol, ExpressionEvaluator, FunctionMap, Instruction, OptimizationSettings, Slot,
+};
+use symbolica::{parse, try_parse};
 
+mod config;
+mod cpu;
+mod dot;
+mod error;
+mod fpenv;
+mod instrument;
+mod interp;
+pub mod natives;
+mod numa;
+mod object;
+mod params;
+mod passes;
+mod piecewise;
 mod runners;
+mod savefile;
+mod threads;
+pub mod vmath;
+
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+#[cfg(feature = "capi")]
+pub mod capi;
+
+#[cfg(feature = "cli")]
+mod cli;
+#[cfg(feature = "cli")]
+pub use cli::run_repl;
 
 fn slot(s: Slot) -> instruction::Slot {
     match s {
@@ -122,10 +434,12 @@ fn slot(s: Slot) -> instruction::Slot {
     }
 }
 
-fn slot_list(v: &[Slot]) -> Vec<instruction::Slot> {
-    v.iter()
-        .map(|s| slot(*s))
-        .collect::<Vec<instruction::Slot>>()
+/// Converts `v` into `buf`, which is reused across instructions so that the
+/// argument lists of a large stream do not allocate one vector each.
+fn slot_list<'a>(buf: &'a mut Vec<instruction::Slot>, v: &[Slot]) -> &'a [instruction::Slot] {
+    buf.clear();
+    buf.extend(v.iter().map(|s| slot(*s)));
+    buf
 }
 
 fn builtin_symbol(s: BuiltinSymbol) -> instruction::BuiltinSymbol {
@@ -135,23 +449,37 @@ fn builtin_symbol(s: BuiltinSymbol) -> instruction::BuiltinSymbol {
 fn translate(
     instructions: Vec<Instruction>,
     constants: Vec<Complex<f64>>,
-    mut config: Config,
+    config: Config,
     direct: bool,
 ) -> Result<Translator> {
+    let (instructions, constants) = passes::dedup_constants(instructions, constants);
+    let branching = passes::has_conditionals(&instructions);
+    let mut config = config.into_symjit(branching);
     config.set_dicect(direct);
     let mut translator = Translator::new(config);
+    append_instructions(&mut translator, instructions, constants)
+        .map_err(BridgeError::translate)?;
+    Ok(translator)
+}
 
+fn append_instructions(
+    translator: &mut Translator,
+    instructions: Vec<Instruction>,
+    constants: Vec<Complex<f64>>,
+) -> Result<()> {
     for z in constants {
         translator.append_constant(z)?;
     }
 
+    let mut buf = Vec::new();
+
     for q in instructions {
         match q {
             Instruction::Add(lhs, args, num_reals) => {
-                translator.append_add(&slot(lhs), &slot_list(&args), num_reals)?
+                translator.append_add(&slot(lhs), slot_list(&mut buf, &args), num_reals)?
             }
             Instruction::Mul(lhs, args, num_reals) => {
-                translator.append_mul(&slot(lhs), &slot_list(&args), num_reals)?
+                translator.append_mul(&slot(lhs), slot_list(&mut buf, &args), num_reals)?
             }
             Instruction::Pow(lhs, arg, p, is_real) => {
                 translator.append_pow(&slot(lhs), &slot(arg), p, is_real)?
@@ -173,19 +501,23 @@ fn translate(
             Instruction::IfElse(cond, id) => translator.append_if_else(&slot(cond), id)?,
             Instruction::Goto(id) => translator.append_goto(id)?,
             Instruction::ExternalFun(lhs, op, args) => {
-                translator.append_external_fun(&slot(lhs), &op, &slot_list(&args))?
+                translator.append_external_fun(&slot(lhs), &op, slot_list(&mut buf, &args))?
             }
         }
     }
 
-    Ok(translator)
+    Ok(())
 }
 
 pub trait Number {
+    const IS_COMPLEX: bool = false;
+
     fn as_complex(&self) -> Complex<f64>;
 }
 
 impl Number for Complex<f64> {
+    const IS_COMPLEX: bool = true;
+
     fn as_complex(&self) -> Complex<f64> {
         *self
     }
@@ -197,19 +529,1143 @@ impl Number for f64 {
     }
 }
 
+/// Applies the optional bridge passes selected by `config` to the
+/// instructions of `ev`.
+fn lower<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: &Config,
+) -> Result<(Vec<Instruction>, Vec<Complex<f64>>)> {
+    let (instructions, num_temps, constants) = ev.export_instructions();
+    validate_instructions(&instructions, num_temps, constants.len())
+        .map_err(BridgeError::translate)?;
+    let constants: Vec<Complex<f64>> = constants.iter().map(|x| x.as_complex()).collect();
+    lower_instructions(instructions, num_temps, constants, config, T::IS_COMPLEX)
+}
+
+/// Applies the optional bridge passes selected by `config` to validated
+/// `instructions` that use `num_temps` temporaries.
+fn lower_instructions(
+    instructions: Vec<Instruction>,
+    num_temps: usize,
+    constants: Vec<Complex<f64>>,
+    config: &Config,
+    is_complex: bool,
+) -> Result<(Vec<Instruction>, Vec<Complex<f64>>)> {
+    let mut constants = constants;
+
+    let (instructions, num_temps) = match config.input_transform() {
+        Some((scales, offsets)) => {
+            let n = passes::count_params(&instructions);
+            if scales.len() != n || offsets.len() != n {
+                return Err(BridgeError::translate(anyhow!(
+                    "the input transform has {} scales and {} offsets, but the kernel has {} parameters",
+                    scales.len(),
+                    offsets.len(),
+                    n
+                )));
+            }
+            passes::transform_inputs(instructions, num_temps, &mut constants, scales, offsets)
+        }
+        None => (instructions, num_temps),
+    };
+
+    let instructions = if config.const_fold() {
+        passes::fold_constants(instructions, &mut constants)
+    } else {
+        instructions
+    };
+
+    let (instructions, num_temps) = if config.fuse_sincos() && !is_complex {
+        passes::fuse_sincos(instructions, num_temps)
+    } else {
+        (instructions, num_temps)
+    };
+
+    let instructions = if config.accurate_sum() {
+        passes::pairwise_sums(instructions, num_temps).0
+    } else {
+        instructions
+    };
+
+    let instructions = match config.fast_rsqrt() {
+        Some(steps) if config.fp_model() == FpModel::Fast && !is_complex => {
+            let name = match steps {
+                0 => "rsqrt",
+                1 => "rsqrt_nr",
+                _ => {
+                    return Err(BridgeError::translate(anyhow!(
+                        "fast rsqrt supports 0 or 1 refinement steps"
+                    )))
+                }
+            };
+            let is_minus_half = |id: usize| constants[id] == Complex::new(-0.5, 0.0);
+            passes::fuse_rsqrt(instructions, is_minus_half, name)
+        }
+        _ => instructions,
+    };
+
+    let instructions = if config.optimize_for() == OptLevel::Size {
+        passes::powers_to_calls(instructions, &mut constants)
+    } else {
+        instructions
+    };
+
+    Ok((instructions, constants))
+}
+
+/// Returns the number of instructions of each kind that the bridge passes to
+/// the code generator for `ev` under `config`, keyed by the operation ("add",
+/// "mul", "pow", "powf", "assign", "join", the builtin function name, or the
+/// name of an external function). Useful to check the effect of the passes,
+/// e.g., `Config::set_fuse_sincos`.
+pub fn num_instructions<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: &Config,
+) -> Result<BTreeMap<String, usize>> {
+    let (instructions, _) = lower(ev, config)?;
+    let mut counts = BTreeMap::new();
+
+    for q in instructions.iter() {
+        let op = passes::op_name(q);
+        *counts.entry(op).or_insert(0) += 1;
+    }
+
+    Ok(counts)
+}
+
+/// Returns the number of temporary slots (one more than the highest
+/// `Slot::Temp` index) that the instructions passed to the code generator for
+/// `ev` under `config` use. A kernel that needs many temporaries is more
+/// likely to spill registers.
+pub fn temp_count<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: &Config,
+) -> Result<usize> {
+    let (instructions, _) = lower(ev, config)?;
+    Ok(passes::count_temps(&instructions))
+}
+
+/// Returns the constant pool of the kernel compiled from `ev` under `config`,
+/// after the passes and the merging of duplicate constants.
+pub fn constants<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: &Config,
+) -> Result<Vec<Complex<f64>>> {
+    let (instructions, constants) = lower(ev, config)?;
+    Ok(passes::dedup_constants(instructions, constants).1)
+}
+
+/// Renders the instructions that the bridge passes to the code generator for
+/// `ev` under `config` as a Graphviz DAG (e.g., for `dot -Tpng`), with a node
+/// for each parameter, constant, instruction, and output, and an edge for
+/// each dependency. A node with several outgoing edges is a shared
+/// subexpression.
+pub fn to_dot<T: Clone + Number>(ev: &ExpressionEvaluator<T>, config: &Config) -> Result<String> {
+    let (instructions, constants) = lower(ev, config)?;
+    Ok(dot::render(&instructions, &constants))
+}
+
+/// Whether the instructions call external functions that `config` does not
+/// assume to be pure (see `Config::set_assume_pure`).
+pub(crate) fn is_impure(instructions: &[Instruction], config: &Config) -> bool {
+    !config.assume_pure() && passes::has_external_calls(instructions)
+}
+
+fn prepare<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: Config,
+    num_params: usize,
+) -> Result<Translator> {
+    let (instructions, constants) = lower(ev, &config)?;
+    prepare_lowered(instructions, constants, config, num_params)
+}
+
+/// Translates the instructions returned by `lower`.
+fn prepare_lowered(
+    instructions: Vec<Instruction>,
+    constants: Vec<Complex<f64>>,
+    config: Config,
+    num_params: usize,
+) -> Result<Translator> {
+    check_external_functions(&instructions, &config)?;
+    let mut config = config;
+
+    if is_impure(&instructions, &config) {
+        config.set_fp_model(FpModel::Strict);
+    }
+
+    let mut translator = translate(instructions, constants, config, false)?;
+    translator.set_num_params(num_params);
+    Ok(translator)
+}
+
+/// Returns a translation error if an external function called by
+/// `instructions` is neither a symjit builtin nor registered in the `Defuns`
+/// of `config`. symjit only resolves the names when it generates code, so each
+/// distinct function is probed by building the bytecode of a single call.
+fn check_external_functions(instructions: &[Instruction], config: &Config) -> Result<()> {
+    let mut seen = HashSet::new();
+
+    for q in instructions {
+        let Instruction::ExternalFun(_, op, args) = q else {
+            continue;
+        };
+
+        if !seen.insert(op.as_str()) {
+            continue;
+        }
+
+        let probe = Instruction::ExternalFun(
+            Slot::Out(0),
+            op.clone(),
+            (0..args.len()).map(Slot::Param).collect(),
+        );
+
+        translate(vec![probe], Vec::new(), config.clone().bytecode()?, false)?
+            .compile()
+            .map_err(|e| BridgeError::translate(anyhow!("{}: {}", op, e)))?;
+    }
+
+    Ok(())
+}
+
 pub fn compile<T: Clone + Number>(
     ev: &ExpressionEvaluator<T>,
     config: Config,
     num_params: usize,
 ) -> Result<Application> {
-    let (instructions, _, constants) = ev.export_instructions();
-    let constants: Vec<Complex<f64>> = constants.iter().map(|x| x.as_complex()).collect();
-    let mut translator = translate(instructions, constants, config, false).unwrap();
+    let (instructions, constants) = lower(ev, &config)?;
+    compile_lowered(instructions, constants, config, num_params, T::IS_COMPLEX)
+}
+
+/// Translates the instructions returned by `lower`, generates their code, and
+/// runs the self-check if `config` asks for it.
+fn compile_lowered(
+    instructions: Vec<Instruction>,
+    constants: Vec<Complex<f64>>,
+    config: Config,
+    num_params: usize,
+    is_complex: bool,
+) -> Result<Application> {
+    let limit = config.max_code_bytes();
+    let checked = config
+        .self_check()
+        .then(|| (instructions.clone(), constants.clone(), config.clone()));
+    let mut translator = prepare_lowered(instructions, constants, config, num_params)?;
+    let app = generate_code(&mut translator, limit)?;
+
+    if let Some((instructions, constants, config)) = checked {
+        self_check(
+            instructions,
+            constants,
+            config,
+            num_params,
+            is_complex,
+            &app,
+        )
+        .map_err(BridgeError::codegen)?;
+    }
+
+    Ok(app)
+}
+
+/// An `Application` that keeps the instruction stream it was compiled from,
+/// so that it can be recompiled under another config (e.g., flipping
+/// `set_complex`) after the evaluator is gone. The `Application` itself does
+/// not retain its instructions; use `compile_retained` instead of `compile`
+/// to keep them. The methods of `Application` are available through `Deref`.
+pub struct RetainedApplication {
+    app: Application,
+    instructions: Vec<Instruction>,
+    constants: Vec<Complex<f64>>,
+    num_params: usize,
+}
+
+impl RetainedApplication {
+    /// Translates the retained instructions under `config` and generates new
+    /// code. The bridge passes (`const_fold`, `fuse_sincos`, etc.) already
+    /// applied under the original config are kept, and the self-check is not
+    /// run, since it needs the evaluator.
+    pub fn recompile(&self, config: Config) -> Result<Application> {
+        let limit = config.max_code_bytes();
+        let mut translator = translate(
+            self.instructions.clone(),
+            self.constants.clone(),
+            config,
+            false,
+        )?;
+        translator.set_num_params(self.num_params);
+        generate_code(&mut translator, limit)
+    }
+
+    pub fn into_app(self) -> Application {
+        self.app
+    }
+}
+
+impl Deref for RetainedApplication {
+    type Target = Application;
+
+    fn deref(&self) -> &Application {
+        &self.app
+    }
+}
+
+impl DerefMut for RetainedApplication {
+    fn deref_mut(&mut self) -> &mut Application {
+        &mut self.app
+    }
+}
+
+/// Like `compile`, but keeps the lowered instructions for
+/// `RetainedApplication::recompile`.
+pub fn compile_retained<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: Config,
+    num_params: usize,
+) -> Result<RetainedApplication> {
+    let (instructions, constants) = lower(ev, &config)?;
+    let app = compile_lowered(
+        instructions.clone(),
+        constants.clone(),
+        config,
+        num_params,
+        T::IS_COMPLEX,
+    )?;
+
+    Ok(RetainedApplication {
+        app,
+        instructions,
+        constants,
+        num_params,
+    })
+}
+
+/// A kernel whose machine code is generated on the first evaluation, so that
+/// constructing many kernels up front does not pay the JIT cost of the ones
+/// that are never used. It holds the `Translator` until then.
+pub struct LazyApplication {
+    translator: Option<Translator>,
+    app: Option<Application>,
+    limit: Option<usize>,
+}
+
+impl LazyApplication {
+    /// Whether the machine code has been generated.
+    pub fn is_compiled(&self) -> bool {
+        self.app.is_some()
+    }
+
+    /// Returns the application, generating its code on the first call.
+    pub fn app(&mut self) -> Result<&mut Application> {
+        if let Some(translator) = self.translator.as_mut() {
+            self.app = Some(generate_code(translator, self.limit)?);
+            self.translator = None;
+        }
+
+        Ok(self.app.as_mut().unwrap())
+    }
+
+    /// Evaluates the rows of `args`, which must hold a whole number of rows.
+    pub fn evaluate(&mut self, args: &[f64], outs: &mut [f64]) -> Result<()> {
+        let app = self.app()?;
+        let count = app.count_params;
+
+        let n = match args.len().checked_div(count) {
+            None => 1,
+            Some(n) if n * count == args.len() => n,
+            Some(_) => {
+                return Err(anyhow!(
+                    "input length ({}) is not a multiple of the {} parameters per row",
+                    args.len(),
+                    count
+                ))
+            }
+        };
+        app.evaluate_matrix(args, outs, n);
+        Ok(())
+    }
+
+    pub fn evaluate_single(&mut self, args: &[f64]) -> Result<f64> {
+        Ok(self.app()?.evaluate_single(args))
+    }
+
+    pub fn evaluate_matrix(&mut self, args: &[f64], outs: &mut [f64], nrows: usize) -> Result<()> {
+        self.app()?.evaluate_matrix(args, outs, nrows);
+        Ok(())
+    }
+
+    pub fn into_app(mut self) -> Result<Application> {
+        self.app()?;
+        Ok(self.app.unwrap())
+    }
+}
+
+/// Like `compile`, but defers generating the machine code to the first
+/// evaluation of the returned `LazyApplication`. The lowering and translation
+/// are done here, so the errors in `ev` are still reported at once, but
+/// `Config::set_max_code_bytes` is only checked, and the self-check is not
+/// run, when the code is generated.
+pub fn compile_lazy<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: Config,
+    num_params: usize,
+) -> Result<LazyApplication> {
+    let limit = config.max_code_bytes();
+
+    Ok(LazyApplication {
+        translator: Some(prepare(ev, config, num_params)?),
+        app: None,
+        limit,
+    })
+}
+
+/// Compiles `ev`, whose parameters are the atoms returned by
+/// `array_params(specs)`, so that each array parameter occupies a contiguous
+/// range of parameter slots (see `param_range`), even if some of its elements
+/// are not used by the expression.
+pub fn compile_with_array_params<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    specs: &[ArrayParam],
+    config: Config,
+) -> Result<Application> {
+    let (instructions, _, _) = ev.export_instructions();
+    let num_params = params::count_slots(specs);
+    let used = passes::count_params(&instructions);
+
+    if used > num_params {
+        return Err(BridgeError::translate(anyhow!(
+            "the evaluator uses {} parameters, but the specs describe {}",
+            used,
+            num_params
+        )));
+    }
+
+    let mut config = config;
+    config.set_complex(T::IS_COMPLEX);
+    compile(ev, config, num_params)
+}
+
+/// The number of pseudo-random rows evaluated by `self_check`.
+const SELF_CHECK_ROWS: usize = 8;
+
+/// Evaluates `app` and an interpreted (bytecode) build of the same lowered
+/// instructions on a few pseudo-random inputs in [-1, 1] and returns an error
+/// if they disagree beyond a relative tolerance of 1e-9. Kernels with external
+/// functions are not checked.
+fn self_check(
+    instructions: Vec<Instruction>,
+    constants: Vec<Complex<f64>>,
+    config: Config,
+    num_params: usize,
+    is_complex: bool,
+    app: &Application,
+) -> Result<()> {
+    if passes::has_external_calls(&instructions) {
+        return Ok(());
+    }
+
+    let mut config = config.bytecode()?;
+    config.set_complex(is_complex);
+    let mut reference = prepare_lowered(instructions, constants, config, num_params)?.compile()?;
+
+    let n = SELF_CHECK_ROWS;
+    let mut seed: u64 = 0x9e3779b97f4a7c15;
+    let args: Vec<f64> = (0..n * app.count_params)
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+        })
+        .collect();
+
+    let mut expected = vec![0.0; n * app.count_obs];
+    reference.interpret_matrix(&args, &mut expected, n);
+
+    let mut outs = vec![0.0; n * app.count_obs];
+
+    if is_complex {
+        let z = |v: &[f64]| -> Vec<Complex<f64>> {
+            v.chunks(2).map(|p| Complex::new(p[0], p[1])).collect()
+        };
+        let args = z(&args);
+        let mut res = z(&outs);
+        app.evaluate_matrix(&args, &mut res, n);
+        outs = res.iter().flat_map(|z| [z.re, z.im]).collect();
+    } else {
+        app.evaluate_matrix(&args, &mut outs, n);
+    }
+
+    for (i, (x, y)) in outs.iter().zip(expected.iter()).enumerate() {
+        let agree = (x.is_nan() && y.is_nan()) || (x - y).abs() <= 1e-9 * y.abs().max(1.0);
+
+        if !agree {
+            return Err(anyhow!(
+                "self-check failed: output {} of row {} is {} compiled but {} interpreted",
+                i % app.count_obs,
+                i / app.count_obs,
+                x,
+                y
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The size in bytes of the machine code of `app` (the scalar and the SIMD
+/// code), or 0 for an application without machine code (bytecode).
+pub fn code_size(app: &mut Application) -> usize {
+    ["scalar", "simd"]
+        .iter()
+        .map(|what| runners::dump_code(app, what).map_or(0, |code| code.len()))
+        .sum()
+}
+
+/// Returns `app` if its machine code fits in `limit` bytes, and an error
+/// otherwise. Applications without machine code (bytecode) always pass.
+fn check_code_size(mut app: Application, limit: Option<usize>) -> Result<Application> {
+    let Some(limit) = limit else {
+        return Ok(app);
+    };
+
+    let size = code_size(&mut app);
+
+    if size > limit {
+        return Err(BridgeError::codegen(anyhow!(
+            "the generated code ({} bytes) exceeds the limit of {} bytes; \
+             consider using an interpreted runner for this expression",
+            size,
+            limit
+        )));
+    }
+
+    Ok(app)
+}
+
+/// Generates the code of `translator` and checks it against `limit`.
+fn generate_code(translator: &mut Translator, limit: Option<usize>) -> Result<Application> {
+    let app = translator.compile().map_err(BridgeError::codegen)?;
+    check_code_size(app, limit)
+}
+
+/// Compiles every output of `ev` into its own single-output application, so
+/// that the outputs can be evaluated independently. The subexpressions shared
+/// between outputs are recomputed by each application that needs them. All the
+/// applications take the same parameters as `ev`. The bridge passes of
+/// `config` are applied before the outputs are split.
+pub fn compile_split<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: Config,
+) -> Result<Vec<Application>> {
+    let (instructions, constants) = lower(ev, &config)?;
+    let num_temps = passes::count_temps(&instructions);
+    let num_params = passes::count_params(&instructions);
+
+    (0..passes::count_outputs(&instructions))
+        .map(|k| {
+            let (split, _) = passes::split_output(&instructions, num_temps, k);
+            compile_lowered(
+                split,
+                constants.clone(),
+                config.clone(),
+                num_params,
+                T::IS_COMPLEX,
+            )
+        })
+        .collect()
+}
+
+/// Compiles a pipeline in which the outputs of `first` are the parameters of
+/// `next` into a single application, so that no intermediate buffer or second
+/// call is needed. The number of outputs of `first` must match the number of
+/// parameters of `next`. The application takes the parameters of `first` and
+/// returns the outputs of `next`. The bridge passes of `config` are applied to
+/// the composed instructions.
+pub fn compile_then<T: Clone + Number>(
+    first: &ExpressionEvaluator<T>,
+    next: &ExpressionEvaluator<T>,
+    config: Config,
+) -> Result<Application> {
+    let (first_instructions, first_temps, first_consts) = first.export_instructions();
+    let (next_instructions, next_temps, next_consts) = next.export_instructions();
+    validate_instructions(&first_instructions, first_temps, first_consts.len())
+        .map_err(BridgeError::translate)?;
+    validate_instructions(&next_instructions, next_temps, next_consts.len())
+        .map_err(BridgeError::translate)?;
+
+    let (instructions, num_temps) = passes::compose(
+        &first_instructions,
+        first_temps,
+        first_consts.len(),
+        &next_instructions,
+        next_temps,
+    )
+    .map_err(BridgeError::translate)?;
+
+    let constants: Vec<Complex<f64>> = first_consts
+        .iter()
+        .chain(next_consts.iter())
+        .map(|x| x.as_complex())
+        .collect();
+
+    let num_params = passes::count_params(&first_instructions);
+    let (instructions, constants) =
+        lower_instructions(instructions, num_temps, constants, &config, T::IS_COMPLEX)?;
+    compile_lowered(instructions, constants, config, num_params, T::IS_COMPLEX)
+}
+
+/// Checks that `ev` can be compiled with `config` by running the translation
+/// and validation passes and building the bytecode of the kernel, which
+/// resolves every function against the symjit builtins and the registered
+/// `Defuns`. No machine code is generated and no executable memory is
+/// allocated (useful under hardened seccomp policies).
+pub fn validate<T: Clone + Number>(ev: &ExpressionEvaluator<T>, config: &Config) -> Result<()> {
+    let (instructions, constants) = lower(ev, config)?;
+    let mut config = config.clone().bytecode()?;
+    config.set_complex(T::IS_COMPLEX);
+    prepare_lowered(instructions, constants, config, 0)?
+        .compile()
+        .map_err(BridgeError::translate)?;
+    Ok(())
+}
+
+/// Compiles a list of real evaluators and returns the applications in the
+/// same order, or the first error encountered.
+///
+/// The evaluators are lowered and validated in parallel, but the code is
+/// generated on the calling thread, since symjit's `Translator` and
+/// `Application` cannot be sent between threads.
+pub fn compile_many(evs: &[ExpressionEvaluator<f64>], config: Config) -> Result<Vec<Application>> {
+    let mut config = config;
+    config.set_complex(false);
+
+    let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = evs.len().div_ceil(num_threads).max(1);
+
+    let lowered = thread::scope(|s| {
+        let handles: Vec<_> = evs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let config = &config;
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|ev| lower(ev, config))
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect();
+
+        let mut lowered = Vec::with_capacity(evs.len());
+
+        for h in handles {
+            lowered.extend(h.join().unwrap()?);
+        }
+
+        Ok::<_, anyhow::Error>(lowered)
+    })?;
+
+    lowered
+        .into_iter()
+        .map(|(instructions, constants)| {
+            compile_lowered(instructions, constants, config.clone(), 0, false)
+        })
+        .collect()
+}
+
+/// The number of rows that `evaluate_multi` passes to each kernel at a time.
+const MULTI_BLOCK_ROWS: usize = 64;
+
+/// Evaluates several real kernels over the same `nrows` rows of `args`, e.g.,
+/// the applications returned by `compile_many`. The rows are visited once, in
+/// small blocks that stay in cache while every kernel is applied to them,
+/// instead of reading the whole input matrix once per kernel. `outs[i]`
+/// receives the outputs of `apps[i]`, row by row. All the kernels must have
+/// the same number of parameters.
+pub fn evaluate_multi(
+    apps: &[&Application],
+    args: &[f64],
+    nrows: usize,
+    outs: &mut [&mut [f64]],
+) -> Result<()> {
+    let Some(first) = apps.first() else {
+        return Ok(());
+    };
+    let count_params = first.count_params;
+
+    if let Some((i, app)) = apps
+        .iter()
+        .enumerate()
+        .find(|(_, app)| app.count_params != count_params)
+    {
+        return Err(anyhow!(
+            "kernel {} has {} parameters, but kernel 0 has {}",
+            i,
+            app.count_params,
+            count_params
+        ));
+    }
+
+    if outs.len() != apps.len() {
+        return Err(anyhow!(
+            "{} output buffers for {} kernels",
+            outs.len(),
+            apps.len()
+        ));
+    }
+
+    if args.len() < nrows * count_params {
+        return Err(anyhow!("args must hold at least {} rows", nrows));
+    }
+
+    if let Some(i) = (0..apps.len()).find(|&i| outs[i].len() < nrows * apps[i].count_obs) {
+        return Err(anyhow!("outs[{}] must hold at least {} rows", i, nrows));
+    }
+
+    for start in (0..nrows).step_by(MULTI_BLOCK_ROWS) {
+        let m = MULTI_BLOCK_ROWS.min(nrows - start);
+        let a = &args[start * count_params..(start + m) * count_params];
+
+        for (app, o) in apps.iter().zip(outs.iter_mut()) {
+            let co = app.count_obs;
+            app.evaluate_matrix(a, &mut o[start * co..(start + m) * co], m);
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses `expr` with Symbolica, builds a real evaluator with the parameters
+/// `params` (in order), a default `FunctionMap`, and the default optimization
+/// settings, and compiles it. A shortcut for scripts and tests.
+pub fn compile_str(expr: &str, params: &[&str], config: Config) -> Result<Application> {
+    let expr = try_parse!(expr).map_err(BridgeError::Parse)?;
+    let params = params
+        .iter()
+        .map(|p| try_parse!(*p).map_err(BridgeError::Parse))
+        .collect::<Result<Vec<Atom>, _>>()?;
+
+    compile_real(&[expr], &params, config)
+}
+
+/// Rounds the rational `x` to an `f64` in the direction `mode`, e.g.,
+/// `ev.map_coeff(&|x| round_rational(&x.re, RoundMode::Down))`.
+pub fn round_rational(x: &Rational, mode: RoundMode) -> f64 {
+    if mode == RoundMode::Nearest && x.is_zero() {
+        return 0.0;
+    }
+
+    // `to_f64` truncates, so it is at most an ulp from `x`
+    let f = x.to_f64();
+
+    if f.is_infinite() {
+        // overflowed: the largest finite value is in the right direction
+        let toward_inf = match mode {
+            RoundMode::Nearest => true,
+            RoundMode::TowardZero => false,
+            RoundMode::Up => f > 0.0,
+            RoundMode::Down => f < 0.0,
+        };
+        return if toward_inf { f } else { f64::MAX.copysign(f) };
+    }
+
+    let exact = |f: f64| Rational::try_from(f).unwrap();
+
+    // the largest f64 not above x
+    let mut lo = f;
+    while exact(lo) > *x {
+        lo = lo.next_down();
+    }
+    while lo.next_up().is_finite() && exact(lo.next_up()) <= *x {
+        lo = lo.next_up();
+    }
+
+    let lo_exact = exact(lo);
+    let hi = lo.next_up();
+
+    if lo_exact == *x || !hi.is_finite() {
+        return lo;
+    }
+
+    match mode {
+        RoundMode::Down => lo,
+        RoundMode::Up => hi,
+        RoundMode::TowardZero if x.is_negative() => hi,
+        RoundMode::TowardZero => lo,
+        RoundMode::Nearest => match (x - &lo_exact).cmp(&(&exact(hi) - x)) {
+            std::cmp::Ordering::Less => lo,
+            std::cmp::Ordering::Greater => hi,
+            // ties to even
+            std::cmp::Ordering::Equal if lo.to_bits() & 1 == 0 => lo,
+            std::cmp::Ordering::Equal => hi,
+        },
+    }
+}
+
+/// Builds a real evaluator of `exprs` with a default `FunctionMap` and the
+/// default optimization settings, rounding the constants with `mode`.
+fn real_evaluator(
+    exprs: &[Atom],
+    params: &[Atom],
+    mode: RoundMode,
+) -> Result<ExpressionEvaluator<f64>> {
+    Ok(Atom::evaluator_multiple(
+        exprs,
+        &FunctionMap::new(),
+        params,
+        OptimizationSettings::default(),
+    )
+    .map_err(BridgeError::EvaluatorBuild)?
+    .map_coeff(&|x| round_rational(&x.re, mode)))
+}
+
+fn compile_real(exprs: &[Atom], params: &[Atom], mut config: Config) -> Result<Application> {
+    let ev = real_evaluator(exprs, params, config.round_mode())?;
+    config.set_complex(false);
+    compile(&ev, config, 0)
+}
+
+/// Compiles the ratio `num / den` of two real expressions with the
+/// parameters `params` into a `RationalRunner`, which evaluates the numerator
+/// and the denominator in one kernel and divides them once per row, returning
+/// NaN where the denominator is zero. The ratio is not simplified.
+pub fn compile_rational(
+    num: &Atom,
+    den: &Atom,
+    params: &[Atom],
+    mut config: Config,
+) -> Result<RationalRunner> {
+    let ev = real_evaluator(&[num.clone(), den.clone()], params, config.round_mode())?;
+    config.set_complex(false);
+    RationalRunner::compile(&ev, config)
+}
+
+/// Compiles the gradient of the single-output real evaluator `ev` with
+/// reverse-mode (adjoint) differentiation of its instruction stream: output
+/// `k` of the kernel is the partial derivative with respect to parameter `k`.
+/// All the partials cost a small multiple of one evaluation of `ev`, however
+/// many parameters there are, which makes it cheaper than
+/// `compile_value_and_gradient` for many parameters. The stream must be
+/// straight-line code with the builtins `sin`, `cos`, `exp`, `log`, and
+/// `sqrt`, and powers with constant exponents; otherwise, it fails with a
+/// `BridgeError`.
+pub fn compile_gradient_reverse(
+    ev: &ExpressionEvaluator<f64>,
+    mut config: Config,
+) -> Result<Application> {
+    config.set_complex(false);
+    let (instructions, mut constants) = lower(ev, &config)?;
+    let num_params = passes::count_params(&instructions);
+    let (gradient, _) =
+        passes::reverse_gradient(&instructions, &mut constants).map_err(BridgeError::translate)?;
+
+    let limit = config.max_code_bytes();
+    let mut translator = translate(gradient, constants, config, false)?;
     translator.set_num_params(num_params);
-    translator.compile()
+    generate_code(&mut translator, limit)
+}
+
+/// A real `Application` compiled by `compile_affine`, which knows whether its
+/// outputs are affine in the parameters. The methods of `Application` are
+/// available through `Deref`.
+pub struct AffineApplication {
+    app: Application,
+    affine: Option<(Vec<f64>, Vec<f64>)>,
+}
+
+impl AffineApplication {
+    /// Whether every output is an affine function `w . params + b` of the
+    /// parameters, in which case the kernel is a dot product per output.
+    pub fn is_affine(&self) -> bool {
+        self.affine.is_some()
+    }
+
+    /// The weights (`count_obs x count_params`, row-major) and the biases of
+    /// the outputs, if the application is affine.
+    pub fn affine_coeffs(&self) -> Option<(&[f64], &[f64])> {
+        self.affine
+            .as_ref()
+            .map(|(w, b)| (w.as_slice(), b.as_slice()))
+    }
+
+    /// Like `Application::evaluate_matrix` for `f64` rows, but an affine
+    /// application is evaluated as a matrix product, in blocks of rows that
+    /// reuse each weight across the block, instead of calling the kernel once
+    /// per row. The other element types are available through `Deref`.
+    pub fn evaluate_matrix(&self, args: &[f64], outs: &mut [f64], nrows: usize) {
+        let Some((weights, biases)) = &self.affine else {
+            self.app.evaluate_matrix(args, outs, nrows);
+            return;
+        };
+
+        const BLOCK: usize = 4;
+        let (cp, co) = (self.app.count_params, self.app.count_obs);
+        assert!(args.len() >= nrows * cp && outs.len() >= nrows * co);
+
+        for r0 in (0..nrows).step_by(BLOCK) {
+            let rows = BLOCK.min(nrows - r0);
+
+            for (k, b) in biases.iter().enumerate() {
+                let w = &weights[k * cp..(k + 1) * cp];
+                let mut acc = [*b; BLOCK];
+
+                for (j, w) in w.iter().enumerate() {
+                    for (i, acc) in acc.iter_mut().enumerate().take(rows) {
+                        *acc += w * args[(r0 + i) * cp + j];
+                    }
+                }
+
+                for (i, acc) in acc.iter().enumerate().take(rows) {
+                    outs[(r0 + i) * co + k] = *acc;
+                }
+            }
+        }
+    }
+
+    pub fn into_app(self) -> Application {
+        self.app
+    }
+}
+
+impl Deref for AffineApplication {
+    type Target = Application;
+
+    fn deref(&self) -> &Application {
+        &self.app
+    }
+}
+
+impl DerefMut for AffineApplication {
+    fn deref_mut(&mut self) -> &mut Application {
+        &mut self.app
+    }
+}
+
+/// Compiles the real evaluator `ev` like `compile`, but first checks whether
+/// its outputs are affine in the parameters (see `AffineApplication`). If so,
+/// the kernel is generated from the weights and biases instead of the
+/// instruction stream; otherwise, the general path is used.
+pub fn compile_affine(
+    ev: &ExpressionEvaluator<f64>,
+    mut config: Config,
+    num_params: usize,
+) -> Result<AffineApplication> {
+    config.set_complex(false);
+    let (instructions, mut constants) = lower(ev, &config)?;
+
+    let Some((weights, biases)) = passes::affine_form(&instructions, &constants, num_params) else {
+        let app = compile(ev, config, num_params)?;
+        return Ok(AffineApplication { app, affine: None });
+    };
+
+    let num_params = num_params.max(passes::count_params(&instructions));
+    let kernel = passes::affine_kernel(&weights, &biases, &mut constants);
+    let limit = config.max_code_bytes();
+    let mut translator = translate(kernel, constants, config, false)?;
+    translator.set_num_params(num_params);
+    let app = generate_code(&mut translator, limit)?;
+
+    Ok(AffineApplication {
+        app,
+        affine: Some((weights, biases)),
+    })
+}
+
+/// A real `Application` whose outputs have names (see `compile_named`). The
+/// methods of `Application` are available through `Deref`.
+pub struct NamedApplication {
+    app: Application,
+    names: Vec<String>,
+}
+
+impl NamedApplication {
+    /// The name of output `idx`, or `None` if there is no such output.
+    pub fn output_name(&self, idx: usize) -> Option<&str> {
+        self.names.get(idx).map(|s| s.as_str())
+    }
+
+    /// The index of the output named `name`.
+    pub fn output_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|s| s == name)
+    }
+
+    /// Evaluates a single row of parameters and returns the outputs by name.
+    pub fn evaluate_named(&self, args: &[f64]) -> HashMap<String, f64> {
+        assert_eq!(
+            args.len(),
+            self.app.count_params,
+            "evaluate_named takes a single row of parameters"
+        );
+
+        let mut outs = vec![0.0; self.app.count_obs];
+        self.app.evaluate_matrix(args, &mut outs, 1);
+        self.names.iter().cloned().zip(outs).collect()
+    }
+
+    /// Writes the application and its output names to `file` in the format
+    /// of `CompiledRealRunner::save`.
+    pub fn save(&self, file: &str) -> Result<()> {
+        runners::save_app(&self.app, file, false, self.names.clone())
+    }
+
+    /// Loads an application written by `save`, with its output names.
+    pub fn load(file: &str, config: &Config) -> Result<NamedApplication> {
+        let (app, header) = runners::load_app(file, config, false)?;
+
+        if header.names.len() != app.count_obs {
+            return Err(anyhow!(
+                "{} has {} output names for {} outputs",
+                file,
+                header.names.len(),
+                app.count_obs
+            ));
+        }
+
+        Ok(NamedApplication {
+            app,
+            names: header.names,
+        })
+    }
+
+    pub fn into_app(self) -> Application {
+        self.app
+    }
+}
+
+impl Deref for NamedApplication {
+    type Target = Application;
+
+    fn deref(&self) -> &Application {
+        &self.app
+    }
+}
+
+impl DerefMut for NamedApplication {
+    fn deref_mut(&mut self) -> &mut Application {
+        &mut self.app
+    }
+}
+
+/// Compiles the named real expressions `exprs` with the parameters `params`
+/// into a single multi-output kernel, whose output `i` is `exprs[i].1` and is
+/// named `exprs[i].0`. The names must be distinct.
+pub fn compile_named(
+    exprs: &[(String, Atom)],
+    params: &[Atom],
+    config: Config,
+) -> Result<NamedApplication> {
+    let names: Vec<String> = exprs.iter().map(|(name, _)| name.clone()).collect();
+
+    if let Some((i, name)) = names
+        .iter()
+        .enumerate()
+        .find(|(i, name)| names[..*i].contains(name))
+    {
+        return Err(anyhow!("output {} has the duplicate name {:?}", i, name));
+    }
+
+    let exprs: Vec<Atom> = exprs.iter().map(|(_, e)| e.clone()).collect();
+    let app = compile_real(&exprs, params, config)?;
+    Ok(NamedApplication { app, names })
+}
+
+/// The highest order accepted by `compile_derivative`.
+pub const MAX_DERIVATIVE_ORDER: usize = 16;
+
+/// Returns the symbol of `params[wrt]`, which must be a variable.
+fn param_symbol(params: &[Atom], wrt: usize) -> Result<Symbol> {
+    params
+        .get(wrt)
+        .ok_or_else(|| anyhow!("wrt is {}, but there are {} parameters", wrt, params.len()))?
+        .get_symbol()
+        .ok_or_else(|| anyhow!("parameter {} is not a variable", wrt))
+}
+
+/// Differentiates `expr` `order` times with respect to `params[wrt]` with
+/// Symbolica and compiles the result as a real kernel with the parameters
+/// `params` (e.g., `order == 2` gives a diagonal element of the Hessian). The
+/// order is capped at `MAX_DERIVATIVE_ORDER`. The derivative is compiled like
+/// any other expression, so it fails with a `BridgeError` if it contains
+/// functions that cannot be evaluated.
+pub fn compile_derivative(
+    expr: &Atom,
+    params: &[Atom],
+    wrt: usize,
+    order: usize,
+    config: Config,
+) -> Result<Application> {
+    if order > MAX_DERIVATIVE_ORDER {
+        return Err(anyhow!(
+            "derivative order {} exceeds the maximum of {}",
+            order,
+            MAX_DERIVATIVE_ORDER
+        ));
+    }
+
+    let x = param_symbol(params, wrt)?;
+    let derivative = (0..order).fold(sqrt_as_power(expr), |e, _| e.derivative(x));
+    compile_real(&[derivative], params, config)
+}
+
+/// Rewrites `sqrt(u)` as `u^(1/2)` before differentiating, since
+/// `Atom::derivative` returns `u'/sqrt(sqrt(u))` for `sqrt(u)`.
+fn sqrt_as_power(expr: &Atom) -> Atom {
+    expr.replace(parse!("sqrt(x_)")).with(parse!("x_^(1/2)"))
+}
+
+/// Returns `expr` followed by its partial derivatives with respect to
+/// `params[wrt[0]]`, `params[wrt[1]]`, and so on.
+fn value_and_gradient(expr: &Atom, params: &[Atom], wrt: &[usize]) -> Result<Vec<Atom>> {
+    let expr = sqrt_as_power(expr);
+    let mut exprs = vec![expr.clone()];
+
+    for &k in wrt {
+        exprs.push(expr.derivative(param_symbol(params, k)?));
+    }
+
+    Ok(exprs)
+}
+
+/// Builds a single real evaluator whose first output is `expr` and whose
+/// remaining outputs are its partial derivatives with respect to
+/// `params[wrt[0]]`, `params[wrt[1]]`, and so on. The outputs are optimized
+/// together, so the subexpressions shared by the value and the partials are
+/// computed once. The constants are rounded to the nearest `f64`.
+pub fn value_and_gradient_evaluator(
+    expr: &Atom,
+    params: &[Atom],
+    wrt: &[usize],
+) -> Result<ExpressionEvaluator<f64>> {
+    real_evaluator(
+        &value_and_gradient(expr, params, wrt)?,
+        params,
+        RoundMode::Nearest,
+    )
+}
+
+/// Compiles `value_and_gradient_evaluator(expr, params, wrt)`: a kernel that
+/// returns the value of `expr` followed by its partial derivatives with
+/// respect to the parameters in `wrt`, for gradient-based optimizers. The
+/// constants are rounded with `config.round_mode()`.
+pub fn compile_value_and_gradient(
+    expr: &Atom,
+    params: &[Atom],
+    wrt: &[usize],
+    mut config: Config,
+) -> Result<Application> {
+    let exprs = value_and_gradient(expr, params, wrt)?;
+    let ev = real_evaluator(&exprs, params, config.round_mode())?;
+    config.set_complex(false);
+    compile(&ev, config, 0)
 }
 
 pub fn compile_string(model: String, config: Config, num_params: usize) -> Result<Application> {
-    let mut comp = Compiler::with_config(config);
-    comp.translate(model, num_params)
+    let limit = config.max_code_bytes();
+    let mut comp = Compiler::with_config(config.into_symjit(false));
+    let app = comp
+        .translate(model, num_params)
+        .map_err(BridgeError::translate)?;
+    check_code_size(app, limit)
 }
@@ -0,0 +1,385 @@
+//! Native implementations of special functions that are not among the symjit
+//! builtins.
+//!
+//! The functions are exposed to Symbolica as external functions with the same
+//! name, e.g., `f.add_external_function(symbol!("lgamma"), "lgamma".to_string())`,
+//! and are registered with symjit through a `Defuns`:
+//!
+//! ```ignore
+//! let mut df = Defuns::new();
+//! natives::add_real_natives(&mut df)?;
+//! let runner = CompiledRealRunner::compile_with_funcs(&ev, Config::from_defuns(df)?, 0)?;
+//! ```
+//!
+//! Use `add_complex_natives` for the complex runners.
+//!
+//! symjit does not allow redefining its builtins, so the functions it already
+//! provides are not registered. The complex versions of real-only builtins
+//! take a `c` prefix instead, e.g., `cerf`.
+//!
+//! Functions with several outputs (e.g., `sincos`) are registered by
+//! `add_vector_func` as one external function per component, named `sincos_0`,
+//! `sincos_1`, and so on. The components share a per-thread cache of the last
+//! call, so evaluating all of them on the same arguments calls the underlying
+//! function only once.
+
+use crate::Complex;
+use anyhow::Result;
+use std::cell::RefCell;
+use std::f64::consts::FRAC_2_SQRT_PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use symjit::Defuns;
+
+type Func<T> = Box<dyn Fn(&[T]) -> T + Send + Sync>;
+
+/// The names of the functions registered by `add_real_natives`.
+pub const REAL_NATIVES: &[&str] = &[
+    "tgamma",
+    "lgamma",
+    "log10",
+    "hypot",
+    "fmod",
+    "remainder",
+    "sincos_0",
+    "sincos_1",
+    "asinh",
+    "acosh",
+    "atanh",
+    "floor",
+    "ceil",
+    "round_away",
+    "round_even",
+    "trunc",
+    "rsqrt",
+    "rsqrt_nr",
+    "sigmoid",
+    "softplus",
+];
+
+/// The names of the functions registered by `add_complex_natives`.
+pub const COMPLEX_NATIVES: &[&str] = &[
+    "cerf", "cerfc", "log10", "clog2", "catan2", "hypot", "asinh", "acosh", "atanh", "cexpm1",
+    "clog1p",
+];
+
+pub fn add_real_natives(df: &mut Defuns) -> Result<()> {
+    // `gamma` is a symjit builtin; `tgamma` (the C name) has the pole values below
+    df.add_sliced_func("tgamma", unary(gamma))?;
+    df.add_sliced_func("lgamma", unary(lgamma))?;
+    // `log2` and `atan2` are symjit builtins
+    df.add_sliced_func("log10", unary(f64::log10))?;
+    df.add_sliced_func("hypot", binary(f64::hypot))?;
+    // `fmod(x, y)` is `x - trunc(x / y) * y`, with the sign of the dividend
+    // (fmod(-7, 3) = -1), and `remainder(x, y)` is the IEEE remainder
+    // `x - round_even(x / y) * y` (remainder(7, 3) = 1, remainder(8, 3) = -1)
+    df.add_sliced_func("fmod", binary(|x, y| x % y))?;
+    df.add_sliced_func("remainder", binary(libm::remainder))?;
+    add_vector_func(df, "sincos", |x| {
+        let (s, c) = x[0].sin_cos();
+        [s, c]
+    })?;
+    // `sinh`, `cosh`, and `tanh` are symjit builtins
+    df.add_sliced_func("asinh", unary(f64::asinh))?;
+    df.add_sliced_func("acosh", unary(f64::acosh))?;
+    df.add_sliced_func("atanh", unary(f64::atanh))?;
+    df.add_sliced_func("floor", unary(f64::floor))?;
+    df.add_sliced_func("ceil", unary(f64::ceil))?;
+    // `round_away` rounds half-way cases away from zero (2.5 -> 3, -2.5 -> -3), and
+    // `round_even` rounds them to the nearest even integer (2.5 -> 2, -2.5 -> -2),
+    // like the symjit builtin `round`
+    df.add_sliced_func("round_away", unary(f64::round))?;
+    df.add_sliced_func("round_even", unary(f64::round_ties_even))?;
+    df.add_sliced_func("trunc", unary(f64::trunc))?;
+    // `expm1` and `log1p` are symjit builtins
+    df.add_sliced_func("rsqrt", unary(rsqrt))?;
+    df.add_sliced_func("rsqrt_nr", unary(rsqrt_nr))?;
+    df.add_sliced_func("sigmoid", unary(sigmoid))?;
+    df.add_sliced_func("softplus", unary(softplus))?;
+    Ok(())
+}
+
+pub fn add_complex_natives(df: &mut Defuns) -> Result<()> {
+    // `erf` and `erfc` are real symjit builtins, which have no complex versions
+    df.add_sliced_func("cerf", complex_unary(erf))?;
+    df.add_sliced_func("cerfc", complex_unary(erfc))?;
+    df.add_sliced_func("log10", complex_unary(|z| z.log10()))?;
+    df.add_sliced_func("clog2", complex_unary(|z| z.log2()))?;
+    df.add_sliced_func("catan2", complex_binary(atan2))?;
+    df.add_sliced_func("hypot", complex_binary(hypot))?;
+    // the inverse functions use the principal branches, with the cuts of C99
+    // (asinh: (-i*inf, -i] and [i, i*inf); acosh: (-inf, 1]; atanh: (-inf, -1] and [1, inf))
+    df.add_sliced_func("asinh", complex_unary(|z| z.asinh()))?;
+    df.add_sliced_func("acosh", complex_unary(|z| z.acosh()))?;
+    df.add_sliced_func("atanh", complex_unary(|z| z.atanh()))?;
+    df.add_sliced_func("cexpm1", complex_unary(expm1))?;
+    df.add_sliced_func("clog1p", complex_unary(log1p))?;
+    Ok(())
+}
+
+/// The id of the next vector function, used as its index into `VECTOR_CACHE`.
+static NEXT_VECTOR_FUNC: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The last arguments and outputs of each vector function on this thread.
+    static VECTOR_CACHE: RefCell<Vec<(Vec<f64>, Vec<f64>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Registers `f`, a function with `N` outputs, as the `N` external functions
+/// `name_0`, ..., `name_{N-1}`, where `name_k(args)` returns `f(args)[k]`.
+pub fn add_vector_func<const N: usize>(
+    df: &mut Defuns,
+    name: &str,
+    f: fn(&[f64]) -> [f64; N],
+) -> Result<()> {
+    let id = NEXT_VECTOR_FUNC.fetch_add(1, Ordering::Relaxed);
+
+    for k in 0..N {
+        let g: Func<f64> = Box::new(move |x: &[f64]| {
+            VECTOR_CACHE.with(|cache| {
+                let mut cache = cache.borrow_mut();
+
+                if cache.len() <= id {
+                    cache.resize(id + 1, (Vec::new(), Vec::new()));
+                }
+
+                let (args, outs) = &mut cache[id];
+
+                // the bitwise comparison also distinguishes 0.0 from -0.0 and matches NaNs
+                let hit = outs.len() == N
+                    && args.len() == x.len()
+                    && args.iter().zip(x).all(|(a, b)| a.to_bits() == b.to_bits());
+
+                if !hit {
+                    args.clear();
+                    args.extend_from_slice(x);
+                    outs.clear();
+                    outs.extend_from_slice(&f(x));
+                }
+
+                outs[k]
+            })
+        });
+
+        df.add_sliced_func(&format!("{}_{}", name, k), g)?;
+    }
+
+    Ok(())
+}
+
+fn unary(f: fn(f64) -> f64) -> Func<f64> {
+    Box::new(move |x: &[f64]| f(x[0]))
+}
+
+fn binary(f: fn(f64, f64) -> f64) -> Func<f64> {
+    Box::new(move |x: &[f64]| f(x[0], x[1]))
+}
+
+fn complex_unary(f: fn(Complex<f64>) -> Complex<f64>) -> Func<Complex<f64>> {
+    Box::new(move |x: &[Complex<f64>]| f(x[0]))
+}
+
+fn complex_binary(f: fn(Complex<f64>, Complex<f64>) -> Complex<f64>) -> Func<Complex<f64>> {
+    Box::new(move |x: &[Complex<f64>]| f(x[0], x[1]))
+}
+
+/// The complex error function. On the real axis, it is the same as `libm::erf`.
+/// Otherwise, it is calculated from its Maclaurin series near the origin and
+/// the imaginary axis (where the terms do not cancel), and from the continued
+/// fraction of `erfc` elsewhere.
+pub fn erf(z: Complex<f64>) -> Complex<f64> {
+    if z.im == 0.0 {
+        return Complex::new(libm::erf(z.re), 0.0);
+    }
+
+    if z.re < 0.0 {
+        return -erf(-z);
+    }
+
+    if use_continued_fraction(z) {
+        return Complex::new(1.0, 0.0) - erfc_continued_fraction(z);
+    }
+
+    // erf(z) = 2/sqrt(pi) * sum_n (-1)^n z^(2n+1) / (n! (2n+1))
+    let z2 = z * z;
+    let mut term = z;
+    let mut sum = z;
+
+    for n in 1..5000 {
+        term = term * -z2 / (n as f64);
+        let t = term / ((2 * n + 1) as f64);
+        sum += t;
+
+        if t.norm() <= f64::EPSILON * sum.norm() {
+            break;
+        }
+    }
+
+    sum * FRAC_2_SQRT_PI
+}
+
+pub fn erfc(z: Complex<f64>) -> Complex<f64> {
+    if z.im == 0.0 {
+        return Complex::new(libm::erfc(z.re), 0.0);
+    }
+
+    if use_continued_fraction(z) {
+        erfc_continued_fraction(z)
+    } else if use_continued_fraction(-z) {
+        Complex::new(2.0, 0.0) - erfc_continued_fraction(-z)
+    } else {
+        Complex::new(1.0, 0.0) - erf(z)
+    }
+}
+
+/// Whether the continued fraction of `erfc(z)` converges quickly, i.e., `z`
+/// is far enough from the origin and from the imaginary axis.
+fn use_continued_fraction(z: Complex<f64>) -> bool {
+    z.re >= 1.0 && z.norm() >= 3.0
+}
+
+/// `erfc(z)` for `Re(z) > 0` from its continued fraction
+/// `exp(-z^2)/sqrt(pi) / (z + (1/2)/(z + 1/(z + (3/2)/(z + ...))))`,
+/// evaluated with the modified Lentz algorithm.
+fn erfc_continued_fraction(z: Complex<f64>) -> Complex<f64> {
+    const TINY: f64 = 1e-300;
+    let mut f = z;
+    let mut c = z;
+    let mut d = Complex::new(0.0, 0.0);
+
+    for n in 1..5000 {
+        let a = 0.5 * n as f64;
+
+        d = z + d * a;
+        if d.norm() < TINY {
+            d = Complex::new(TINY, 0.0);
+        }
+        d = d.inv();
+
+        c = z + a / c;
+        if c.norm() < TINY {
+            c = Complex::new(TINY, 0.0);
+        }
+
+        let delta = c * d;
+        f *= delta;
+
+        if (delta - 1.0).norm() <= f64::EPSILON {
+            break;
+        }
+    }
+
+    (-z * z).exp() / f * (0.5 * FRAC_2_SQRT_PI)
+}
+
+/// The gamma function, registered as `tgamma`. At the poles, it returns `inf` for `x = ±0` (with the
+/// sign of the zero) and `NaN` for negative integers.
+pub fn gamma(x: f64) -> f64 {
+    if x < 0.0 && x == x.floor() {
+        f64::NAN
+    } else {
+        libm::tgamma(x)
+    }
+}
+
+/// The logarithm of the absolute value of the gamma function. It returns `+inf`
+/// at the poles (zero and negative integers).
+pub fn lgamma(x: f64) -> f64 {
+    if x <= 0.0 && x == x.floor() {
+        f64::INFINITY
+    } else {
+        libm::lgamma(x)
+    }
+}
+
+/// A fast approximation of `1 / sqrt(x)` from a single-precision estimate.
+/// Its relative error is up to about 1e-7, much larger than that of
+/// `1.0 / x.sqrt()`. Arguments outside the normal range of f32 are computed
+/// exactly.
+pub fn rsqrt(x: f64) -> f64 {
+    if (f32::MIN_POSITIVE as f64..=f32::MAX as f64).contains(&x) {
+        (x as f32).sqrt().recip() as f64
+    } else {
+        1.0 / x.sqrt()
+    }
+}
+
+/// `rsqrt` followed by one Newton-Raphson step, `y * (1.5 - 0.5 * x * y^2)`,
+/// which brings the relative error down to about 1e-14.
+pub fn rsqrt_nr(x: f64) -> f64 {
+    let y = rsqrt(x);
+
+    if (f32::MIN_POSITIVE as f64..=f32::MAX as f64).contains(&x) {
+        y * (1.5 - 0.5 * x * y * y)
+    } else {
+        y
+    }
+}
+
+/// The logistic function `1 / (1 + exp(-x))`, computed from `exp(-|x|)` so
+/// that it does not overflow for large `|x|` (sigmoid(1000) = 1).
+pub fn sigmoid(x: f64) -> f64 {
+    let e = (-x.abs()).exp();
+
+    if x >= 0.0 {
+        1.0 / (1.0 + e)
+    } else {
+        e / (1.0 + e)
+    }
+}
+
+/// `log(1 + exp(x))`, computed as `max(x, 0) + log1p(exp(-|x|))`, which
+/// neither overflows for large `x` nor loses the small values for negative
+/// `x`.
+pub fn softplus(x: f64) -> f64 {
+    x.max(0.0) + (-x.abs()).exp().ln_1p()
+}
+
+/// The two-argument arctangent `atan2(y, x)`, registered as `catan2`. For real arguments, it is the
+/// same as `f64::atan2`, including the signs of the axes; otherwise, it is
+/// `-i * log((x + i*y) / sqrt(x^2 + y^2))`.
+pub fn atan2(y: Complex<f64>, x: Complex<f64>) -> Complex<f64> {
+    if y.im == 0.0 && x.im == 0.0 {
+        return Complex::new(y.re.atan2(x.re), 0.0);
+    }
+
+    let i = Complex::new(0.0, 1.0);
+    let r = (x * x + y * y).sqrt();
+    -i * ((x + i * y) / r).ln()
+}
+
+/// `sqrt(x^2 + y^2)` without intermediate overflow or underflow. For real
+/// arguments, it is the same as `f64::hypot`; otherwise, `x` and `y` are scaled
+/// by the larger of their magnitudes before squaring.
+pub fn hypot(x: Complex<f64>, y: Complex<f64>) -> Complex<f64> {
+    if x.im == 0.0 && y.im == 0.0 {
+        return Complex::new(x.re.hypot(y.re), 0.0);
+    }
+
+    let s = x.norm().max(y.norm());
+
+    if s == 0.0 {
+        return Complex::default();
+    }
+
+    let (x, y) = (x / s, y / s);
+    (x * x + y * y).sqrt() * s
+}
+
+/// `exp(z) - 1`, registered as `cexpm1`, accurate for small `|z|`: the real part is computed as
+/// `expm1(x)*cos(y) - 2*sin(y/2)^2` to avoid the cancellation of `exp(z) - 1`.
+pub fn expm1(z: Complex<f64>) -> Complex<f64> {
+    let s = (0.5 * z.im).sin();
+    Complex::new(
+        z.re.exp_m1() * z.im.cos() - 2.0 * s * s,
+        z.re.exp() * z.im.sin(),
+    )
+}
+
+/// `log(1 + z)`, registered as `clog1p`, accurate for small `|z|`: the real part is computed as
+/// `log1p(x*(2 + x) + y^2) / 2` instead of from `|1 + z|`.
+pub fn log1p(z: Complex<f64>) -> Complex<f64> {
+    Complex::new(
+        0.5 * (z.re * (2.0 + z.re) + z.im * z.im).ln_1p(),
+        z.im.atan2(1.0 + z.re),
+    )
+}
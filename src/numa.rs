@@ -0,0 +1,98 @@
+//! NUMA topology detection and per-node replication of compiled code.
+
+use crate::Config;
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use symjit::{Applet, Application, Storage};
+
+/// Parses a sysfs CPU list such as `0-3,8-11`.
+fn parse_cpu_list(s: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+
+    for part in s.trim().split(',').filter(|p| !p.is_empty()) {
+        let mut ends = part.splitn(2, '-').map(|x| x.parse::<usize>());
+
+        match (ends.next(), ends.next()) {
+            (Some(Ok(a)), None) => cpus.push(a),
+            (Some(Ok(a)), Some(Ok(b))) => cpus.extend(a..=b),
+            _ => {}
+        }
+    }
+
+    cpus
+}
+
+/// Returns the CPUs of each NUMA node, in node order. On systems without NUMA
+/// information (including non-Linux platforms), it returns a single node with
+/// an empty CPU list.
+pub fn numa_nodes() -> Vec<Vec<usize>> {
+    let mut nodes: Vec<(usize, Vec<usize>)> = fs::read_dir("/sys/devices/system/node")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let id = name.strip_prefix("node")?.parse::<usize>().ok()?;
+            let cpus = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            Some((id, parse_cpu_list(&cpus)))
+        })
+        .filter(|(_, cpus)| !cpus.is_empty())
+        .collect();
+
+    if nodes.is_empty() {
+        return vec![Vec::new()];
+    }
+
+    nodes.sort_by_key(|(id, _)| *id);
+    nodes.into_iter().map(|(_, cpus)| cpus).collect()
+}
+
+/// Makes one copy of the code of `app` per NUMA node (see `numa_nodes`). Each
+/// copy is loaded by a thread pinned to a CPU of its node, so that its pages
+/// are allocated in the local memory of that node, and is sealed for use by
+/// the workers on that node. On a system with a single node, it returns one
+/// copy. `config` is the config used to load the copies.
+pub fn replicate_for_numa(app: &Application, config: &Config) -> Result<Vec<Applet>> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let path = std::env::temp_dir().join(format!(
+        "symjit-numa-{}-{}.sjb",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    app.save(&mut fs::File::create(&path)?)?;
+
+    let load = |cpu: Option<usize>, config: Config| -> Result<Applet> {
+        if let Some(id) = cpu {
+            core_affinity::set_for_current(core_affinity::CoreId { id });
+        }
+
+        let mut fs = fs::File::open(&path)?;
+        Application::load(&mut fs, &config)?.seal()
+    };
+
+    let replicas = thread::scope(|s| {
+        let handles: Vec<_> = numa_nodes()
+            .into_iter()
+            .map(|cpus| {
+                let config = config.clone();
+                let load = &load;
+                s.spawn(move || load(cpus.first().copied(), config))
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| {
+                h.join()
+                    .map_err(|_| anyhow!("a NUMA replica thread panicked"))?
+            })
+            .collect::<Result<Vec<Applet>>>()
+    });
+
+    fs::remove_file(&path)?;
+    replicas
+}
@@ -0,0 +1,200 @@
+//! A minimal ELF64 relocatable object writer for the generated machine code.
+
+use anyhow::{bail, Result};
+use symjit::Application;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const STB_GLOBAL_STT_FUNC: u8 = 0x12;
+
+fn machine() -> Result<u16> {
+    if cfg!(target_vendor = "apple") {
+        bail!("object files are written in ELF, which Apple platforms do not link")
+    } else if cfg!(target_arch = "x86_64") {
+        Ok(62)
+    } else if cfg!(target_arch = "aarch64") {
+        Ok(183)
+    } else if cfg!(target_arch = "riscv64") {
+        Ok(243)
+    } else {
+        bail!("unsupported architecture for object files")
+    }
+}
+
+fn align(buf: &mut Vec<u8>, n: usize) {
+    while !buf.len().is_multiple_of(n) {
+        buf.push(0);
+    }
+}
+
+struct Section {
+    name: u32,
+    ty: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl Section {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.name.to_le_bytes());
+        buf.extend_from_slice(&self.ty.to_le_bytes());
+        buf.extend_from_slice(&self.flags.to_le_bytes());
+        buf.extend_from_slice(&0u64.to_le_bytes()); // sh_addr
+        buf.extend_from_slice(&self.offset.to_le_bytes());
+        buf.extend_from_slice(&self.size.to_le_bytes());
+        buf.extend_from_slice(&self.link.to_le_bytes());
+        buf.extend_from_slice(&self.info.to_le_bytes());
+        buf.extend_from_slice(&self.align.to_le_bytes());
+        buf.extend_from_slice(&self.entsize.to_le_bytes());
+    }
+}
+
+/// Wraps `code` in an ELF64 relocatable object with a `.text` section and a
+/// single global function symbol, `symbol`, pointing to its start.
+pub(crate) fn elf_object(code: &[u8], symbol: &str) -> Result<Vec<u8>> {
+    let machine = machine()?;
+    let mut buf = vec![0u8; 64];
+
+    align(&mut buf, 16);
+    let text_offset = buf.len();
+    buf.extend_from_slice(code);
+
+    align(&mut buf, 8);
+    let symtab_offset = buf.len();
+    buf.extend_from_slice(&[0u8; 24]); // the null symbol
+    buf.extend_from_slice(&1u32.to_le_bytes()); // st_name
+    buf.push(STB_GLOBAL_STT_FUNC); // st_info
+    buf.push(0); // st_other
+    buf.extend_from_slice(&1u16.to_le_bytes()); // st_shndx (.text)
+    buf.extend_from_slice(&0u64.to_le_bytes()); // st_value
+    buf.extend_from_slice(&(code.len() as u64).to_le_bytes()); // st_size
+
+    let strtab_offset = buf.len();
+    buf.push(0);
+    buf.extend_from_slice(symbol.as_bytes());
+    buf.push(0);
+    let strtab_size = buf.len() - strtab_offset;
+
+    let shstrtab_offset = buf.len();
+    let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+    buf.extend_from_slice(shstrtab);
+
+    align(&mut buf, 8);
+    let shoff = buf.len();
+
+    let sections = [
+        Section {
+            name: 0,
+            ty: 0,
+            flags: 0,
+            offset: 0,
+            size: 0,
+            link: 0,
+            info: 0,
+            align: 0,
+            entsize: 0,
+        },
+        Section {
+            name: 1,
+            ty: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            offset: text_offset as u64,
+            size: code.len() as u64,
+            link: 0,
+            info: 0,
+            align: 16,
+            entsize: 0,
+        },
+        Section {
+            name: 7,
+            ty: SHT_SYMTAB,
+            flags: 0,
+            offset: symtab_offset as u64,
+            size: 48,
+            link: 3,
+            info: 1,
+            align: 8,
+            entsize: 24,
+        },
+        Section {
+            name: 15,
+            ty: SHT_STRTAB,
+            flags: 0,
+            offset: strtab_offset as u64,
+            size: strtab_size as u64,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        },
+        Section {
+            name: 23,
+            ty: SHT_STRTAB,
+            flags: 0,
+            offset: shstrtab_offset as u64,
+            size: shstrtab.len() as u64,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        },
+    ];
+
+    for s in sections.iter() {
+        s.write(&mut buf);
+    }
+
+    let mut header = Vec::with_capacity(64);
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    header.extend_from_slice(&[0u8; 8]);
+    header.extend_from_slice(&1u16.to_le_bytes()); // e_type = ET_REL
+    header.extend_from_slice(&machine.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    header.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+    header.extend_from_slice(&0u64.to_le_bytes()); // e_phoff
+    header.extend_from_slice(&(shoff as u64).to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    header.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+    header.extend_from_slice(&0u16.to_le_bytes()); // e_phentsize
+    header.extend_from_slice(&0u16.to_le_bytes()); // e_phnum
+    header.extend_from_slice(&64u16.to_le_bytes()); // e_shentsize
+    header.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes()); // e_shstrndx
+    buf[..64].copy_from_slice(&header);
+
+    Ok(buf)
+}
+
+/// Writes the scalar machine code of `app` to `path` as an ELF relocatable
+/// object with a single global function symbol, `symbol`, at the kernel entry
+/// point. The code is copied without relocations, so kernels that call
+/// builtins or external functions, which the code reaches through a table of
+/// absolute addresses, are rejected.
+pub(crate) fn write_object(app: &Application, path: &str, symbol: &str) -> Result<()> {
+    let mut calls: Vec<&str> = app.prog.builder.ft.iter().map(|s| s.as_str()).collect();
+
+    if !calls.is_empty() {
+        calls.sort_unstable();
+        bail!(
+            "the kernel calls {}, which would need relocations in an object file",
+            calls.join(", ")
+        );
+    }
+
+    let code = app.dumps();
+
+    if code.is_empty() {
+        bail!("the kernel has no machine code");
+    }
+
+    std::fs::write(path, elf_object(&code, symbol)?)?;
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::ops::Range;
+use symbolica::atom::{Atom, FunctionBuilder, Symbol};
+
+/// A parameter of a kernel, either a scalar or an array of `len` elements,
+/// named by a symbol of the caller (e.g., `symbol!("w")`), so that its atoms
+/// match the ones parsed by the caller.
+///
+/// An array `w` of length `n` is represented in the expression by the indexed
+/// atoms `w(0)`, ..., `w(n-1)`, which occupy a contiguous range of parameter
+/// slots. `array_params` lists the parameter atoms in slot order, to be passed
+/// to `evaluator`:
+///
+/// ```ignore
+/// let specs = [ArrayParam::array(symbol!("w"), 3), ArrayParam::scalar(symbol!("x"))];
+/// let ev = parse!("w(0) + w(1)*x + w(2)*x^2")
+///     .evaluator(&f, &array_params(&specs), OptimizationSettings::default())
+///     .unwrap()
+///     .map_coeff(&|x| x.re.to_f64());
+/// let app = compile_with_array_params(&ev, &specs, Config::default())?;
+/// // the args are [w0, w1, w2, x]
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArrayParam {
+    pub symbol: Symbol,
+    pub len: usize,
+    pub is_array: bool,
+}
+
+impl ArrayParam {
+    pub fn scalar(symbol: Symbol) -> Self {
+        Self {
+            symbol,
+            len: 1,
+            is_array: false,
+        }
+    }
+
+    pub fn array(symbol: Symbol, len: usize) -> Self {
+        Self {
+            symbol,
+            len,
+            is_array: true,
+        }
+    }
+
+    /// The parameter atoms of this parameter: `symbol` for a scalar and
+    /// `symbol(0)`, ..., `symbol(len-1)` for an array.
+    pub fn atoms(&self) -> Vec<Atom> {
+        if self.is_array {
+            (0..self.len)
+                .map(|i| {
+                    FunctionBuilder::new(self.symbol)
+                        .add_arg(Atom::num(i as i64))
+                        .finish()
+                })
+                .collect()
+        } else {
+            vec![Atom::var(self.symbol)]
+        }
+    }
+}
+
+/// Returns the parameter atoms of `specs` in slot order.
+pub fn array_params(specs: &[ArrayParam]) -> Vec<Atom> {
+    specs.iter().flat_map(|spec| spec.atoms()).collect()
+}
+
+/// Returns the range of parameter slots occupied by the parameter `symbol`.
+pub fn param_range(specs: &[ArrayParam], symbol: Symbol) -> Option<Range<usize>> {
+    let mut start = 0;
+
+    for spec in specs {
+        if spec.symbol == symbol {
+            return Some(start..start + spec.len);
+        }
+        start += spec.len;
+    }
+
+    None
+}
+
+/// The total number of parameter slots of `specs`.
+pub(crate) fn count_slots(specs: &[ArrayParam]) -> usize {
+    specs.iter().map(|s| s.len).sum()
+}
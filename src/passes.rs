@@ -0,0 +1,1041 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::{HashMap, HashSet};
+
+use symbolica::atom::Symbol;
+use symbolica::evaluate::{BuiltinSymbol, Instruction, Slot};
+
+use crate::Complex;
+
+/// Returns the slot written by an instruction, if any.
+pub(crate) fn lhs(q: &Instruction) -> Option<Slot> {
+    match q {
+        Instruction::Add(lhs, _, _)
+        | Instruction::Mul(lhs, _, _)
+        | Instruction::Pow(lhs, _, _, _)
+        | Instruction::Powf(lhs, _, _, _)
+        | Instruction::Assign(lhs, _)
+        | Instruction::Fun(lhs, _, _, _)
+        | Instruction::Join(lhs, _, _, _)
+        | Instruction::ExternalFun(lhs, _, _) => Some(*lhs),
+        Instruction::Label(_) | Instruction::IfElse(_, _) | Instruction::Goto(_) => None,
+    }
+}
+
+/// Returns the slots read by an instruction.
+pub(crate) fn args(q: &Instruction) -> Vec<Slot> {
+    match q {
+        Instruction::Add(_, args, _)
+        | Instruction::Mul(_, args, _)
+        | Instruction::ExternalFun(_, _, args) => args.clone(),
+        Instruction::Pow(_, arg, _, _)
+        | Instruction::Assign(_, arg)
+        | Instruction::Fun(_, _, arg, _) => vec![*arg],
+        Instruction::Powf(_, arg, p, _) => vec![*arg, *p],
+        Instruction::Join(_, cond, true_val, false_val) => vec![*cond, *true_val, *false_val],
+        Instruction::IfElse(cond, _) => vec![*cond],
+        Instruction::Label(_) | Instruction::Goto(_) => Vec::new(),
+    }
+}
+
+/// Returns the name of the operation of an instruction ("add", "mul", "pow",
+/// "powf", "assign", "join", "label", "if_else", "goto", the builtin function
+/// name, or the name of an external function).
+pub(crate) fn op_name(q: &Instruction) -> String {
+    match q {
+        Instruction::Add(..) => "add".to_string(),
+        Instruction::Mul(..) => "mul".to_string(),
+        Instruction::Pow(..) => "pow".to_string(),
+        Instruction::Powf(..) => "powf".to_string(),
+        Instruction::Assign(..) => "assign".to_string(),
+        Instruction::Fun(_, fun, _, _) => fun.get_symbol().get_stripped_name().to_string(),
+        Instruction::Join(..) => "join".to_string(),
+        Instruction::Label(_) => "label".to_string(),
+        Instruction::IfElse(..) => "if_else".to_string(),
+        Instruction::Goto(_) => "goto".to_string(),
+        Instruction::ExternalFun(_, op, _) => op.clone(),
+    }
+}
+
+/// Returns a copy of `q` with every slot (written or read) replaced by `f(slot)`.
+pub(crate) fn map_slots(q: &Instruction, f: impl Fn(Slot) -> Slot) -> Instruction {
+    let g = |v: &[Slot]| v.iter().map(|s| f(*s)).collect::<Vec<Slot>>();
+
+    match q {
+        Instruction::Add(lhs, args, n) => Instruction::Add(f(*lhs), g(args), *n),
+        Instruction::Mul(lhs, args, n) => Instruction::Mul(f(*lhs), g(args), *n),
+        Instruction::Pow(lhs, arg, p, is_real) => Instruction::Pow(f(*lhs), f(*arg), *p, *is_real),
+        Instruction::Powf(lhs, arg, p, is_real) => {
+            Instruction::Powf(f(*lhs), f(*arg), f(*p), *is_real)
+        }
+        Instruction::Assign(lhs, rhs) => Instruction::Assign(f(*lhs), f(*rhs)),
+        Instruction::Fun(lhs, fun, arg, is_real) => {
+            Instruction::Fun(f(*lhs), *fun, f(*arg), *is_real)
+        }
+        Instruction::Join(lhs, cond, true_val, false_val) => {
+            Instruction::Join(f(*lhs), f(*cond), f(*true_val), f(*false_val))
+        }
+        Instruction::Label(id) => Instruction::Label(*id),
+        Instruction::IfElse(cond, id) => Instruction::IfElse(f(*cond), *id),
+        Instruction::Goto(id) => Instruction::Goto(*id),
+        Instruction::ExternalFun(lhs, op, args) => {
+            Instruction::ExternalFun(f(*lhs), op.clone(), g(args))
+        }
+    }
+}
+
+fn count_slots(instructions: &[Instruction], pick: impl Fn(Slot) -> Option<usize>) -> usize {
+    instructions
+        .iter()
+        .flat_map(|q| lhs(q).into_iter().chain(args(q)))
+        .filter_map(pick)
+        .map(|id| id + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The number of outputs, i.e., one more than the highest `Out` index.
+pub(crate) fn count_outputs(instructions: &[Instruction]) -> usize {
+    count_slots(instructions, |s| match s {
+        Slot::Out(id) => Some(id),
+        _ => None,
+    })
+}
+
+/// The number of parameters, i.e., one more than the highest `Param` index.
+pub(crate) fn count_params(instructions: &[Instruction]) -> usize {
+    count_slots(instructions, |s| match s {
+        Slot::Param(id) => Some(id),
+        _ => None,
+    })
+}
+
+/// The number of temporaries, i.e., one more than the highest `Temp` index.
+pub(crate) fn count_temps(instructions: &[Instruction]) -> usize {
+    count_slots(instructions, |s| match s {
+        Slot::Temp(id) => Some(id),
+        _ => None,
+    })
+}
+
+pub(crate) fn has_control_flow(instructions: &[Instruction]) -> bool {
+    instructions.iter().any(|q| {
+        matches!(
+            q,
+            Instruction::Label(_) | Instruction::IfElse(_, _) | Instruction::Goto(_)
+        )
+    })
+}
+
+/// Whether the stream branches, either through jumps or a `Join`.
+pub(crate) fn has_conditionals(instructions: &[Instruction]) -> bool {
+    has_control_flow(instructions)
+        || instructions
+            .iter()
+            .any(|q| matches!(q, Instruction::Join(_, _, _, _)))
+}
+
+pub(crate) fn has_external_calls(instructions: &[Instruction]) -> bool {
+    instructions
+        .iter()
+        .any(|q| matches!(q, Instruction::ExternalFun(_, _, _)))
+}
+
+/// Removes the instructions that do not contribute to any output. Only
+/// straight-line code is pruned; streams with jumps are returned unchanged.
+pub(crate) fn eliminate_dead_code(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    if has_control_flow(&instructions) {
+        return instructions;
+    }
+
+    // the last write to each output is live; an earlier one only if the
+    // output is read before it is overwritten
+    let mut live: HashSet<Slot> = instructions
+        .iter()
+        .filter_map(lhs)
+        .filter(|s| matches!(s, Slot::Out(_)))
+        .collect();
+    let mut keep = vec![false; instructions.len()];
+
+    for (i, q) in instructions.iter().enumerate().rev() {
+        let Some(lhs) = lhs(q) else {
+            continue;
+        };
+
+        if live.contains(&lhs) {
+            keep[i] = true;
+            live.remove(&lhs);
+            live.extend(args(q));
+        }
+    }
+
+    instructions
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(q, k)| k.then_some(q))
+        .collect()
+}
+
+/// Extracts the instructions needed to compute output `k` as a standalone
+/// stream whose only output is `Out(0)`. The other outputs, which may still
+/// hold intermediate values, are moved to fresh temporaries after the existing
+/// `num_temps` ones. Returns the new stream and its number of temporaries.
+pub(crate) fn split_output(
+    instructions: &[Instruction],
+    num_temps: usize,
+    k: usize,
+) -> (Vec<Instruction>, usize) {
+    let num_outs = count_outputs(instructions);
+
+    let rename = |s: Slot| match s {
+        Slot::Out(id) if id == k => Slot::Out(0),
+        Slot::Out(id) => Slot::Temp(num_temps + id),
+        s => s,
+    };
+
+    let split = instructions.iter().map(|q| map_slots(q, rename)).collect();
+    (eliminate_dead_code(split), num_temps + num_outs)
+}
+
+/// Concatenates two instruction streams so that the outputs of `first` feed
+/// the parameters of `next`. The outputs of `first` become temporaries, the
+/// temporaries and constants of `next` are shifted past those of `first`, and
+/// the labels of `next` are renumbered. Returns the combined stream and the
+/// total number of temporaries. The constants of `next` must be appended to
+/// those of `first`.
+pub(crate) fn compose(
+    first: &[Instruction],
+    first_temps: usize,
+    first_consts: usize,
+    next: &[Instruction],
+    next_temps: usize,
+) -> Result<(Vec<Instruction>, usize)> {
+    let num_outs = count_outputs(first);
+    let num_params = count_params(next);
+
+    if num_outs != num_params {
+        bail!(
+            "cannot compose: the first kernel has {} outputs but the next one takes {} parameters",
+            num_outs,
+            num_params
+        );
+    }
+
+    let label_offset = first
+        .iter()
+        .filter_map(|q| match q {
+            Instruction::Label(id) => Some(id + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+
+    let rename_first = |s: Slot| match s {
+        Slot::Out(id) => Slot::Temp(first_temps + id),
+        s => s,
+    };
+
+    let rename_next = |s: Slot| match s {
+        Slot::Param(id) => Slot::Temp(first_temps + id),
+        Slot::Temp(id) => Slot::Temp(first_temps + num_outs + id),
+        Slot::Const(id) => Slot::Const(first_consts + id),
+        s => s,
+    };
+
+    let mut composed: Vec<Instruction> = first.iter().map(|q| map_slots(q, rename_first)).collect();
+
+    for q in next.iter() {
+        let q = match map_slots(q, rename_next) {
+            Instruction::Label(id) => Instruction::Label(id + label_offset),
+            Instruction::IfElse(cond, id) => Instruction::IfElse(cond, id + label_offset),
+            Instruction::Goto(id) => Instruction::Goto(id + label_offset),
+            q => q,
+        };
+        composed.push(q);
+    }
+
+    Ok((composed, first_temps + num_outs + next_temps))
+}
+
+/// Returns the `sincos` component computed by a `sin` (0) or `cos` (1)
+/// instruction, if `q` is one. The pass only runs on real kernels, so the
+/// `is_real` flag of the instruction, which Symbolica does not always set for
+/// real arguments, is ignored.
+fn sincos_component(q: &Instruction) -> Option<(usize, Slot, Slot)> {
+    match q {
+        Instruction::Fun(lhs, fun, arg, _) if fun.get_symbol() == Symbol::SIN => {
+            Some((0, *lhs, *arg))
+        }
+        Instruction::Fun(lhs, fun, arg, _) if fun.get_symbol() == Symbol::COS => {
+            Some((1, *lhs, *arg))
+        }
+        _ => None,
+    }
+}
+
+/// Merges identical constants (compared bitwise, so `0.0` and `-0.0` stay
+/// distinct) into a single pool entry, keeping the first occurrence of each,
+/// and rewrites the `Const` slots accordingly.
+pub(crate) fn dedup_constants(
+    instructions: Vec<Instruction>,
+    constants: Vec<Complex<f64>>,
+) -> (Vec<Instruction>, Vec<Complex<f64>>) {
+    let mut pool: Vec<Complex<f64>> = Vec::with_capacity(constants.len());
+    let mut index: HashMap<(u64, u64), usize> = HashMap::new();
+
+    let remap: Vec<usize> = constants
+        .iter()
+        .map(|z| {
+            *index
+                .entry((z.re.to_bits(), z.im.to_bits()))
+                .or_insert_with(|| {
+                    pool.push(*z);
+                    pool.len() - 1
+                })
+        })
+        .collect();
+
+    if pool.len() == constants.len() {
+        return (instructions, constants);
+    }
+
+    let instructions = instructions
+        .iter()
+        .map(|q| {
+            map_slots(q, |s| match s {
+                Slot::Const(id) => Slot::Const(remap[id]),
+                _ => s,
+            })
+        })
+        .collect();
+
+    (instructions, pool)
+}
+
+/// Adds with more than this many terms are split by `pairwise_sums`.
+const PAIRWISE_THRESHOLD: usize = 8;
+
+/// Replaces each `Add` with more than `PAIRWISE_THRESHOLD` terms with a
+/// balanced tree of two-term adds into fresh temporaries after the existing
+/// `num_temps` ones. The rounding error of pairwise summation grows with the
+/// logarithm of the number of terms instead of linearly, as in a chain.
+/// Returns the new stream and its number of temporaries.
+pub(crate) fn pairwise_sums(
+    instructions: Vec<Instruction>,
+    num_temps: usize,
+) -> (Vec<Instruction>, usize) {
+    fn tree(terms: &[Slot], lhs: Slot, out: &mut Vec<Instruction>, num_temps: &mut usize) {
+        let mut half = |terms: &[Slot]| {
+            if terms.len() == 1 {
+                return terms[0];
+            }
+            let t = Slot::Temp(*num_temps);
+            *num_temps += 1;
+            tree(terms, t, out, num_temps);
+            t
+        };
+
+        let (left, right) = terms.split_at(terms.len() / 2);
+        let args = vec![half(left), half(right)];
+        out.push(Instruction::Add(lhs, args, 0));
+    }
+
+    let mut num_temps = num_temps;
+    let mut out = Vec::with_capacity(instructions.len());
+
+    for q in instructions {
+        match q {
+            Instruction::Add(lhs, args, _) if args.len() > PAIRWISE_THRESHOLD => {
+                tree(&args, lhs, &mut out, &mut num_temps)
+            }
+            q => out.push(q),
+        }
+    }
+
+    (out, num_temps)
+}
+
+/// Prepends a prologue that computes `(param_i - offsets[i]) * scales[i]`
+/// into fresh temporaries after the existing `num_temps` ones and makes the
+/// rest of the stream read them instead of the parameters. The offsets and
+/// scales are appended to `constants`. Returns the new stream and its number
+/// of temporaries.
+pub(crate) fn transform_inputs(
+    instructions: Vec<Instruction>,
+    num_temps: usize,
+    constants: &mut Vec<Complex<f64>>,
+    scales: &[f64],
+    offsets: &[f64],
+) -> (Vec<Instruction>, usize) {
+    let mut prologue = Vec::new();
+
+    for (i, (scale, offset)) in scales.iter().zip(offsets.iter()).enumerate() {
+        let shifted = Slot::Temp(num_temps + 2 * i);
+        let scaled = Slot::Temp(num_temps + 2 * i + 1);
+
+        constants.push(Complex::new(-offset, 0.0));
+        let offset = Slot::Const(constants.len() - 1);
+        constants.push(Complex::new(*scale, 0.0));
+        let scale = Slot::Const(constants.len() - 1);
+
+        prologue.push(Instruction::Add(shifted, vec![Slot::Param(i), offset], 0));
+        prologue.push(Instruction::Mul(scaled, vec![shifted, scale], 0));
+    }
+
+    let body = instructions.iter().map(|q| {
+        map_slots(q, |s| match s {
+            Slot::Param(i) if i < scales.len() => Slot::Temp(num_temps + 2 * i + 1),
+            _ => s,
+        })
+    });
+
+    prologue.extend(body);
+    (eliminate_dead_code(prologue), num_temps + 2 * scales.len())
+}
+
+/// Fuses each real `sin` with a later `cos` of the same argument (or vice
+/// versa) into the `sincos_0` and `sincos_1` natives, so that the argument
+/// reduction is done once. Both components are computed at the first
+/// instruction, the second into a fresh temporary after the existing
+/// `num_temps` ones, which the later instruction copies. Only straight-line
+/// code is fused. Returns the new stream and its number of temporaries.
+pub(crate) fn fuse_sincos(
+    mut instructions: Vec<Instruction>,
+    num_temps: usize,
+) -> (Vec<Instruction>, usize) {
+    if has_control_flow(&instructions) {
+        return (instructions, num_temps);
+    }
+
+    let mut num_temps = num_temps;
+    let mut fused: Vec<(usize, usize, usize)> = Vec::new();
+    let mut taken = vec![false; instructions.len()];
+
+    for i in 0..instructions.len() {
+        let Some((k, out, arg)) = sincos_component(&instructions[i]) else {
+            continue;
+        };
+
+        if taken[i] || out == arg {
+            continue;
+        }
+
+        for j in i + 1..instructions.len() {
+            if let Some((k2, _, arg2)) = sincos_component(&instructions[j]) {
+                if !taken[j] && k2 != k && arg2 == arg {
+                    taken[i] = true;
+                    taken[j] = true;
+                    fused.push((i, j, num_temps));
+                    num_temps += 1;
+                    break;
+                }
+            }
+
+            if lhs(&instructions[j]) == Some(arg) {
+                break;
+            }
+        }
+    }
+
+    let name = |k: usize| format!("sincos_{}", k);
+    let mut companions: Vec<Option<Instruction>> = vec![None; instructions.len()];
+
+    for (i, j, t) in fused {
+        let (k, out, arg) = sincos_component(&instructions[i]).unwrap();
+        let (k2, out2, _) = sincos_component(&instructions[j]).unwrap();
+        instructions[i] = Instruction::ExternalFun(out, name(k), vec![arg]);
+        companions[i] = Some(Instruction::ExternalFun(Slot::Temp(t), name(k2), vec![arg]));
+        instructions[j] = Instruction::Assign(out2, Slot::Temp(t));
+    }
+
+    let fused = instructions
+        .into_iter()
+        .zip(companions)
+        .flat_map(|(q, c)| std::iter::once(q).chain(c))
+        .collect();
+
+    (fused, num_temps)
+}
+
+/// Replaces the integer powers `x^p` with `|p| > 2` by `Powf` with a constant
+/// exponent, i.e., a call to `pow` instead of an expansion into repeated
+/// squarings and multiplications. The new constants are appended to
+/// `constants`.
+pub(crate) fn powers_to_calls(
+    instructions: Vec<Instruction>,
+    constants: &mut Vec<Complex<f64>>,
+) -> Vec<Instruction> {
+    instructions
+        .into_iter()
+        .map(|q| match q {
+            Instruction::Pow(lhs, arg, p, is_real) if p.abs() > 2 => {
+                Instruction::Powf(lhs, arg, konst(constants, p as f64), is_real)
+            }
+            q => q,
+        })
+        .collect()
+}
+
+/// Replaces the real `x^-0.5` (`Powf` by a constant for which `is_minus_half`
+/// is true) and `1 / sqrt(x)` patterns by calls to the external function
+/// `name`. The `sqrt` of the second pattern is left in place for other uses
+/// and is otherwise removed as dead code. Only straight-line code is rewritten.
+/// As in `fuse_sincos`, the `is_real` flags are ignored (real kernels only).
+pub(crate) fn fuse_rsqrt(
+    instructions: Vec<Instruction>,
+    is_minus_half: impl Fn(usize) -> bool,
+    name: &str,
+) -> Vec<Instruction> {
+    if has_control_flow(&instructions) {
+        return instructions;
+    }
+
+    // the argument of the last `sqrt` written to each slot, as long as the
+    // argument has not been overwritten since
+    let mut roots: Vec<(Slot, Slot)> = Vec::new();
+    let mut rewritten = Vec::with_capacity(instructions.len());
+
+    for q in instructions {
+        let q = match q {
+            Instruction::Powf(lhs, arg, Slot::Const(id), _) if is_minus_half(id) => {
+                Instruction::ExternalFun(lhs, name.to_string(), vec![arg])
+            }
+            Instruction::Pow(lhs, root, -1, _) => match roots.iter().find(|(r, _)| *r == root) {
+                Some((_, arg)) => Instruction::ExternalFun(lhs, name.to_string(), vec![*arg]),
+                None => q,
+            },
+            q => q,
+        };
+
+        if let Some(lhs) = lhs(&q) {
+            roots.retain(|(r, arg)| *r != lhs && *arg != lhs);
+
+            if let Instruction::Fun(_, fun, arg, _) = &q {
+                if fun.get_symbol() == Symbol::SQRT && *arg != lhs {
+                    roots.push((lhs, *arg));
+                }
+            }
+        }
+
+        rewritten.push(q);
+    }
+
+    eliminate_dead_code(rewritten)
+}
+
+/// A builtin function that is folded: its symbol and its real and complex
+/// versions.
+type Foldable = (Symbol, fn(f64) -> f64, fn(Complex<f64>) -> Complex<f64>);
+
+/// Evaluates `q` if all its arguments are constants (`value` returns the
+/// value of a constant slot) and it is a pure arithmetic instruction or a
+/// builtin function call.
+fn fold(q: &Instruction, value: impl Fn(Slot) -> Option<Complex<f64>>) -> Option<Complex<f64>> {
+    let vals = args(q)
+        .into_iter()
+        .map(&value)
+        .collect::<Option<Vec<Complex<f64>>>>()?;
+
+    let real =
+        |f: fn(f64) -> f64, z: Complex<f64>| (z.im == 0.0).then(|| Complex::new(f(z.re), 0.0));
+
+    match q {
+        Instruction::Add(..) => Some(vals.iter().sum()),
+        Instruction::Mul(..) => Some(vals.iter().product()),
+        Instruction::Assign(..) => Some(vals[0]),
+        Instruction::Pow(_, _, p, true) => real(|x| x, vals[0]).map(|z| z.powi(*p as i32)),
+        Instruction::Pow(_, _, p, false) => Some(vals[0].powi(*p as i32)),
+        Instruction::Powf(_, _, _, true) if vals[0].im == 0.0 && vals[1].im == 0.0 => {
+            Some(Complex::new(vals[0].re.powf(vals[1].re), 0.0))
+        }
+        Instruction::Powf(_, _, _, false) => Some(vals[0].powc(vals[1])),
+        Instruction::Fun(_, fun, _, is_real) => {
+            let table: [Foldable; 5] = [
+                (Symbol::EXP, f64::exp, |z| z.exp()),
+                (Symbol::LOG, f64::ln, |z| z.ln()),
+                (Symbol::SIN, f64::sin, |z| z.sin()),
+                (Symbol::COS, f64::cos, |z| z.cos()),
+                (Symbol::SQRT, f64::sqrt, |z| z.sqrt()),
+            ];
+            let (_, f, g) = table.iter().find(|(s, _, _)| *s == fun.get_symbol())?;
+
+            if *is_real {
+                real(*f, vals[0])
+            } else {
+                Some(g(vals[0]))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Evaluates the instructions whose arguments are all constants, directly or
+/// through slots computed from constants, at compile time. The results are
+/// appended to `constants` and forwarded into the later reads of the slots,
+/// the folded instructions become assignments of the new constants, and the
+/// assignments that are no longer read are removed. Only straight-line code
+/// is folded.
+pub(crate) fn fold_constants(
+    instructions: Vec<Instruction>,
+    constants: &mut Vec<Complex<f64>>,
+) -> Vec<Instruction> {
+    if has_control_flow(&instructions) {
+        return instructions;
+    }
+
+    // the slots that currently hold a known constant
+    let mut known: HashMap<Slot, usize> = HashMap::new();
+    let mut folded = Vec::with_capacity(instructions.len());
+
+    for q in instructions {
+        let Some(out) = lhs(&q) else {
+            folded.push(q);
+            continue;
+        };
+
+        // the reads (including one of the written slot) see the old values
+        let q = map_slots(&q, |s| known.get(&s).map_or(s, |&id| Slot::Const(id)));
+        let q = with_lhs(&q, out);
+        known.remove(&out);
+
+        let value = fold(&q, |s| match s {
+            Slot::Const(id) => Some(constants[id]),
+            _ => None,
+        });
+
+        match (value, &q) {
+            (_, Instruction::Assign(_, Slot::Const(id))) => {
+                known.insert(out, *id);
+                folded.push(q);
+            }
+            (Some(z), _) => {
+                constants.push(z);
+                let id = constants.len() - 1;
+                known.insert(out, id);
+
+                folded.push(Instruction::Assign(out, Slot::Const(id)));
+            }
+            _ => folded.push(q),
+        }
+    }
+
+    eliminate_dead_code(folded)
+}
+
+/// An affine function `w . params + b` of the parameters.
+type Affine = (Vec<f64>, f64);
+
+/// Returns the weights (`count_outputs x num_params`, row-major) and the
+/// biases of the outputs if every output of the stream is a real affine
+/// function of the parameters. Subexpressions of the constants alone (e.g.,
+/// `sin(2)`) are folded, so `exp(1)*x` is affine, while any product or
+/// function of the parameters is not.
+pub(crate) fn affine_form(
+    instructions: &[Instruction],
+    constants: &[Complex<f64>],
+    num_params: usize,
+) -> Option<(Vec<f64>, Vec<f64>)> {
+    if has_control_flow(instructions) {
+        return None;
+    }
+
+    let num_params = num_params.max(count_params(instructions));
+    let mut forms: HashMap<Slot, Affine> = HashMap::new();
+
+    let form = |forms: &HashMap<Slot, Affine>, s: Slot| -> Option<Affine> {
+        match s {
+            Slot::Param(j) => {
+                let mut w = vec![0.0; num_params];
+                w[j] = 1.0;
+                Some((w, 0.0))
+            }
+            Slot::Const(id) if constants[id].im == 0.0 => {
+                Some((vec![0.0; num_params], constants[id].re))
+            }
+            Slot::Const(_) => None,
+            _ => forms.get(&s).cloned(),
+        }
+    };
+
+    let is_const = |f: &Affine| f.0.iter().all(|w| *w == 0.0);
+
+    for q in instructions {
+        let out = lhs(q)?;
+        let vals = args(q)
+            .into_iter()
+            .map(|s| form(&forms, s))
+            .collect::<Option<Vec<Affine>>>()?;
+
+        let folded = vals.iter().all(is_const).then(|| {
+            fold(q, |s| {
+                let i = args(q).iter().position(|t| *t == s)?;
+                Some(Complex::new(vals[i].1, 0.0))
+            })
+        });
+
+        let f = match (q, folded.flatten()) {
+            (_, Some(z)) if z.im == 0.0 => (vec![0.0; num_params], z.re),
+            (Instruction::Add(..), None) => vals.into_iter().reduce(|(mut w, b), (v, c)| {
+                w.iter_mut().zip(v).for_each(|(x, y)| *x += y);
+                (w, b + c)
+            })?,
+            (Instruction::Mul(..), None) => {
+                // at most one factor may depend on the parameters
+                let (vars, consts): (Vec<Affine>, Vec<Affine>) =
+                    vals.into_iter().partition(|f| !is_const(f));
+                let scale: f64 = consts.iter().map(|f| f.1).product();
+
+                match vars.as_slice() {
+                    [(w, b)] => (w.iter().map(|x| x * scale).collect(), b * scale),
+                    _ => return None,
+                }
+            }
+            (Instruction::Assign(..), None) => vals.into_iter().next()?,
+            (Instruction::Pow(_, _, 1, _), None) => vals.into_iter().next()?,
+            _ => return None,
+        };
+
+        forms.insert(out, f);
+    }
+
+    let num_outputs = count_outputs(instructions);
+    let mut weights = Vec::with_capacity(num_outputs * num_params);
+    let mut biases = Vec::with_capacity(num_outputs);
+
+    for k in 0..num_outputs {
+        let (w, b) = forms.remove(&Slot::Out(k))?;
+        weights.extend(w);
+        biases.push(b);
+    }
+
+    Some((weights, biases))
+}
+
+/// Builds a straight-line stream that computes `Out(k) = w[k] . params +
+/// b[k]` for the weights and biases returned by `affine_form`, skipping the
+/// zero weights. New constants are appended to `constants`.
+pub(crate) fn affine_kernel(
+    weights: &[f64],
+    biases: &[f64],
+    constants: &mut Vec<Complex<f64>>,
+) -> Vec<Instruction> {
+    let num_params = weights.len() / biases.len().max(1);
+    let mut num_temps = 0;
+    let mut kernel = Vec::new();
+
+    for (k, b) in biases.iter().enumerate() {
+        let mut terms = Vec::new();
+
+        for (j, w) in weights[k * num_params..(k + 1) * num_params]
+            .iter()
+            .enumerate()
+        {
+            if *w == 1.0 {
+                terms.push(Slot::Param(j));
+            } else if *w != 0.0 {
+                let t = fresh(&mut num_temps);
+                kernel.push(Instruction::Mul(
+                    t,
+                    vec![konst(constants, *w), Slot::Param(j)],
+                    0,
+                ));
+                terms.push(t);
+            }
+        }
+
+        if *b != 0.0 || terms.is_empty() {
+            terms.push(konst(constants, *b));
+        }
+
+        let q = match terms.as_slice() {
+            [t] => Instruction::Assign(Slot::Out(k), *t),
+            _ => Instruction::Add(Slot::Out(k), terms, 0),
+        };
+        kernel.push(q);
+    }
+
+    kernel
+}
+
+/// Returns a copy of `q` that writes `out` instead of its own left-hand side.
+fn with_lhs(q: &Instruction, out: Slot) -> Instruction {
+    match q.clone() {
+        Instruction::Add(_, args, n) => Instruction::Add(out, args, n),
+        Instruction::Mul(_, args, n) => Instruction::Mul(out, args, n),
+        Instruction::Pow(_, arg, p, is_real) => Instruction::Pow(out, arg, p, is_real),
+        Instruction::Powf(_, arg, p, is_real) => Instruction::Powf(out, arg, p, is_real),
+        Instruction::Assign(_, rhs) => Instruction::Assign(out, rhs),
+        Instruction::Fun(_, fun, arg, is_real) => Instruction::Fun(out, fun, arg, is_real),
+        Instruction::Join(_, cond, true_val, false_val) => {
+            Instruction::Join(out, cond, true_val, false_val)
+        }
+        Instruction::ExternalFun(_, op, args) => Instruction::ExternalFun(out, op, args),
+        q => q,
+    }
+}
+
+/// A fresh temporary after the `num_temps` ones in use.
+fn fresh(num_temps: &mut usize) -> Slot {
+    *num_temps += 1;
+    Slot::Temp(*num_temps - 1)
+}
+
+/// Appends the real constant `x` to `constants` and returns its slot.
+fn konst(constants: &mut Vec<Complex<f64>>, x: f64) -> Slot {
+    constants.push(Complex::new(x, 0.0));
+    Slot::Const(constants.len() - 1)
+}
+
+/// Adds the adjoint term `t` to the slot `u`, unless `u` is a constant.
+fn give(terms: &mut HashMap<Slot, Vec<Slot>>, u: Slot, t: Slot) {
+    if !matches!(u, Slot::Const(_)) {
+        terms.entry(u).or_default().push(t);
+    }
+}
+
+/// Builds the reverse-mode (adjoint) gradient of a real, single-output,
+/// straight-line stream. The forward stream is first rewritten so that every
+/// instruction writes a fresh temporary (keeping the intermediate values for
+/// the backward sweep); the adjoints are then propagated from the output back
+/// to the parameters, and `Out(k)` receives the partial derivative with
+/// respect to `Param(k)`. The cost is a small multiple of one forward pass,
+/// independent of the number of parameters.
+///
+/// `Powf` needs a constant exponent; builtins other than `sin`, `cos`,
+/// `exp`, `log`, and `sqrt`, external functions, and `Join` are rejected. New
+/// constants are appended to `constants`. Returns the stream and its number
+/// of temporaries.
+pub(crate) fn reverse_gradient(
+    instructions: &[Instruction],
+    constants: &mut Vec<Complex<f64>>,
+) -> Result<(Vec<Instruction>, usize)> {
+    if has_control_flow(instructions) {
+        bail!("reverse-mode gradients need straight-line code");
+    }
+
+    if count_outputs(instructions) != 1 {
+        bail!(
+            "reverse-mode gradients need a single output, not {}",
+            count_outputs(instructions)
+        );
+    }
+
+    let num_params = count_params(instructions);
+    let mut num_temps = 0;
+
+    // the forward stream, with one fresh temporary per instruction
+    let mut version: HashMap<Slot, Slot> = HashMap::new();
+    let mut forward = Vec::with_capacity(instructions.len());
+
+    for q in instructions {
+        match q {
+            Instruction::Add(..)
+            | Instruction::Mul(..)
+            | Instruction::Pow(..)
+            | Instruction::Powf(..)
+            | Instruction::Assign(..)
+            | Instruction::Fun(..) => {}
+            _ => bail!("reverse-mode gradients do not support {}", op_name(q)),
+        }
+
+        let out = fresh(&mut num_temps);
+        let renamed = map_slots(q, |s| version.get(&s).copied().unwrap_or(s));
+        version.insert(lhs(q).unwrap(), out);
+        forward.push(with_lhs(&renamed, out));
+    }
+
+    let value = *version
+        .get(&Slot::Out(0))
+        .ok_or_else(|| anyhow!("the output is never written"))?;
+
+    // the adjoint terms of each slot, all known by the time the backward sweep
+    // reaches the instruction that writes it
+    let mut terms: HashMap<Slot, Vec<Slot>> = HashMap::new();
+    terms.insert(value, vec![konst(constants, 1.0)]);
+
+    let mut backward = Vec::new();
+
+    for q in forward.iter().rev() {
+        let out = lhs(q).unwrap();
+
+        let Some(ts) = terms.remove(&out) else {
+            continue;
+        };
+
+        let a = if ts.len() == 1 {
+            ts[0]
+        } else {
+            let t = fresh(&mut num_temps);
+            backward.push(Instruction::Add(t, ts, 0));
+            t
+        };
+
+        match q {
+            Instruction::Add(_, args, _) => {
+                for u in args {
+                    give(&mut terms, *u, a);
+                }
+            }
+            Instruction::Assign(_, u) => give(&mut terms, *u, a),
+            Instruction::Mul(_, args, _) => {
+                for k in 0..args.len() {
+                    let mut factors = vec![a];
+                    factors.extend(
+                        args.iter()
+                            .enumerate()
+                            .filter(|(j, _)| *j != k)
+                            .map(|(_, s)| *s),
+                    );
+                    let t = fresh(&mut num_temps);
+                    backward.push(Instruction::Mul(t, factors, 0));
+                    give(&mut terms, args[k], t);
+                }
+            }
+            Instruction::Pow(_, u, p, is_real) => {
+                if *p == 0 {
+                    continue;
+                }
+
+                let d = match p {
+                    1 => None,
+                    2 => Some(*u),
+                    _ => {
+                        let t = fresh(&mut num_temps);
+                        backward.push(Instruction::Pow(t, *u, p - 1, *is_real));
+                        Some(t)
+                    }
+                };
+
+                let t = fresh(&mut num_temps);
+                let mut factors = vec![a, konst(constants, *p as f64)];
+                factors.extend(d);
+                backward.push(Instruction::Mul(t, factors, 0));
+                give(&mut terms, *u, t);
+            }
+            Instruction::Powf(_, u, Slot::Const(id), is_real) => {
+                let v = constants[*id];
+                constants.push(v - 1.0);
+                let w = Slot::Const(constants.len() - 1);
+
+                let t1 = fresh(&mut num_temps);
+                backward.push(Instruction::Powf(t1, *u, w, *is_real));
+                let t = fresh(&mut num_temps);
+                backward.push(Instruction::Mul(t, vec![a, Slot::Const(*id), t1], 0));
+                give(&mut terms, *u, t);
+            }
+            Instruction::Powf(..) => {
+                bail!("reverse-mode gradients need constant exponents in powf")
+            }
+            Instruction::Fun(_, fun, u, is_real) => {
+                let symbol = fun.get_symbol();
+
+                let factors = if symbol == Symbol::SIN {
+                    let t = fresh(&mut num_temps);
+                    backward.push(Instruction::Fun(t, builtin(Symbol::COS), *u, *is_real));
+                    vec![a, t]
+                } else if symbol == Symbol::COS {
+                    let t = fresh(&mut num_temps);
+                    backward.push(Instruction::Fun(t, builtin(Symbol::SIN), *u, *is_real));
+                    vec![a, t, konst(constants, -1.0)]
+                } else if symbol == Symbol::EXP {
+                    vec![a, out]
+                } else if symbol == Symbol::LOG {
+                    let t = fresh(&mut num_temps);
+                    backward.push(Instruction::Pow(t, *u, -1, *is_real));
+                    vec![a, t]
+                } else if symbol == Symbol::SQRT {
+                    let t = fresh(&mut num_temps);
+                    backward.push(Instruction::Pow(t, out, -1, *is_real));
+                    vec![a, t, konst(constants, 0.5)]
+                } else {
+                    bail!("reverse-mode gradients do not support {}", op_name(q));
+                };
+
+                let t = fresh(&mut num_temps);
+                backward.push(Instruction::Mul(t, factors, 0));
+                give(&mut terms, *u, t);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    let mut gradient = forward;
+    gradient.extend(backward);
+
+    for k in 0..num_params {
+        let q = match terms.remove(&Slot::Param(k)) {
+            None => Instruction::Assign(Slot::Out(k), konst(constants, 0.0)),
+            Some(ts) if ts.len() == 1 => Instruction::Assign(Slot::Out(k), ts[0]),
+            Some(ts) => Instruction::Add(Slot::Out(k), ts, 0),
+        };
+        gradient.push(q);
+    }
+
+    let gradient = eliminate_dead_code(gradient);
+    let num_temps = count_temps(&gradient);
+    Ok((gradient, num_temps))
+}
+
+/// The builtin for `symbol`. `BuiltinSymbol` has no public constructor, but
+/// it decodes from the id of its symbol.
+fn builtin(symbol: Symbol) -> BuiltinSymbol {
+    let config = bincode::config::standard();
+    let bytes = bincode::encode_to_vec(symbol.get_id(), config).unwrap();
+    bincode::decode_from_slice(&bytes, config).unwrap().0
+}
+
+/// Checks that every slot of an instruction stream is in range, that no
+/// instruction writes to a parameter or a constant, and that every jump
+/// targets an existing label, so that a malformed stream is rejected with
+/// an error instead of reaching the code generator or the interpreter.
+///
+/// `num_temps` and `num_consts` are the number of temporaries and constants,
+/// as returned by `ExpressionEvaluator::export_instructions`.
+pub fn validate_instructions(
+    instructions: &[Instruction],
+    num_temps: usize,
+    num_consts: usize,
+) -> Result<()> {
+    let labels: HashSet<usize> = instructions
+        .iter()
+        .filter_map(|q| match q {
+            Instruction::Label(id) => Some(*id),
+            _ => None,
+        })
+        .collect();
+
+    for (i, q) in instructions.iter().enumerate() {
+        if let Some(s @ (Slot::Param(_) | Slot::Const(_))) = lhs(q) {
+            bail!("instruction {}: cannot write to {:?}", i, s)
+        }
+
+        for s in lhs(q).into_iter().chain(args(q)) {
+            match s {
+                Slot::Temp(id) if id >= num_temps => {
+                    bail!(
+                        "instruction {}: Temp({}) is out of range ({} temporaries)",
+                        i,
+                        id,
+                        num_temps
+                    )
+                }
+                Slot::Const(id) if id >= num_consts => {
+                    bail!(
+                        "instruction {}: Const({}) is out of range ({} constants)",
+                        i,
+                        id,
+                        num_consts
+                    )
+                }
+                _ => {}
+            }
+        }
+
+        if let Instruction::IfElse(_, id) | Instruction::Goto(id) = q {
+            if !labels.contains(id) {
+                return Err(anyhow!("instruction {}: undefined label {}", i, id));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use symbolica::atom::{Atom, FunctionBuilder, Symbol};
+
+/// A piecewise-defined expression built from `(condition, value)` cases.
+///
+/// The cases are tried in order and the value of the first case whose
+/// condition is nonzero is selected; if none matches, the `otherwise` value is
+/// used. The cases are lowered into nested `if(cond, value, rest)` calls, which
+/// Symbolica compiles into conditional instructions, so the selection is done
+/// per row (and per SIMD lane).
+///
+/// The symjit comparisons (`lt`, `leq`, `gt`, and `geq`) return a nonzero mask
+/// or 0 and are convenient as conditions:
+///
+/// ```ignore
+/// // f(x) = -x if x < 0, x^2 if 0 <= x < 1, and 1 otherwise
+/// let f = Piecewise::new()
+///     .case(parse!("lt(x, 0)"), parse!("-x"))
+///     .case(parse!("lt(x, 1)"), parse!("x^2"))
+///     .otherwise(parse!("1"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Piecewise {
+    cases: Vec<(Atom, Atom)>,
+}
+
+impl Piecewise {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a case that selects `value` if `cond` is nonzero and none of
+    /// the previous cases matched.
+    pub fn case(mut self, cond: Atom, value: Atom) -> Self {
+        self.cases.push((cond, value));
+        self
+    }
+
+    /// Closes the definition with the fallback `value` and returns the
+    /// lowered expression.
+    pub fn otherwise(self, value: Atom) -> Atom {
+        let mut rest = value;
+
+        for (cond, value) in self.cases.into_iter().rev() {
+            rest = FunctionBuilder::new(Symbol::IF)
+                .add_arg(cond)
+                .add_arg(value)
+                .add_arg(rest)
+                .finish();
+        }
+
+        rest
+    }
+}
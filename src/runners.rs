@@ -1,146 +1,1736 @@
-use crate::{compile, compile_string};
-use anyhow::Result;
+use crate::cpu::{self, CpuFeature};
+use crate::fpenv::FlushDenormals;
+use crate::instrument::Counters;
+use crate::interp::{Program, SimdElem};
+use crate::savefile::{self, Header};
+use crate::{
+    compile, compile_string, object, passes, threads, Accuracy, ComplexLayout, Config, FpModel,
+    Number,
+};
+use anyhow::{anyhow, Result};
+use std::ffi::c_void;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use symbolica::evaluate::ExpressionEvaluator;
-use symjit::Storage;
-pub use symjit::{Applet, Application, Complex, Config, Element};
+pub use symjit::{Applet, Application, Complex, Element};
+use symjit::{Compiled, Storage};
+use wide::f64x4;
+
+#[cfg(target_arch = "x86_64")]
+use wide::f64x2;
+
+#[cfg(feature = "nalgebra")]
+use nalgebra::DMatrix;
+
+/// The number of rows used by `warmup`, enough to exercise both the SIMD
+/// and the scalar (remainder) code paths.
+const WARMUP_ROWS: usize = 9;
+
+/// The number of rows staged at a time by `evaluate_inplace`.
+const INPLACE_BLOCK: usize = 64;
+
+/// Returns the machine code of `app` (`what` is "scalar" or "simd"), which
+/// is empty if there is none. The SIMD code is generated on demand.
+pub(crate) fn dump_code(app: &mut Application, what: &str) -> Result<Vec<u8>> {
+    match what {
+        "scalar" => Ok(app.dumps()),
+        "simd" => {
+            app.prepare_simd();
+            Ok(app.compiled_simd.as_ref().map_or(Vec::new(), |f| f.dumps()))
+        }
+        _ => Err(anyhow!("unknown kind of code: {}", what)),
+    }
+}
+
+/// Whether `app` has, or generates on demand, SIMD code.
+pub(crate) fn has_simd(app: &Application) -> bool {
+    app.compiled_simd.is_some() || (app.use_simd && (app.config.has_avx() || app.config.is_arm64()))
+}
+
+/// A closure applied to the outputs of each row (see `map_outputs`).
+type PostFn = Arc<dyn Fn(&mut [f64]) + Send + Sync>;
+
+/// Applies `post` to each of the `n` rows of `outs`, `count_obs` outputs per
+/// row. The lanes of SIMD rows are gathered into a scalar row first.
+fn map_rows<T>(post: &PostFn, outs: &mut [T], count_obs: usize, n: usize) {
+    let lanes = std::mem::size_of::<T>() / std::mem::size_of::<f64>();
+    let flat = flatten_vec_mut(outs);
+
+    if lanes == 1 {
+        for row in flat[..n * count_obs].chunks_exact_mut(count_obs) {
+            post(row);
+        }
+        return;
+    }
+
+    let mut row = vec![0.0; count_obs];
+
+    for block in flat[..n * count_obs * lanes].chunks_exact_mut(count_obs * lanes) {
+        for l in 0..lanes {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = block[j * lanes + l];
+            }
+            post(&mut row);
+            for (j, x) in row.iter().enumerate() {
+                block[j * lanes + l] = *x;
+            }
+        }
+    }
+}
 
 fn flatten_vec<T>(v: &[T]) -> &[f64] {
-    let n = v.len();
+    let n = std::mem::size_of_val(v) / std::mem::size_of::<f64>();
     let p: *const f64 = unsafe { std::mem::transmute(v.as_ptr()) };
-    let q: &[f64] = unsafe {
-        std::slice::from_raw_parts(p, n * std::mem::size_of::<T>() / std::mem::size_of::<f64>())
-    };
+    let q: &[f64] = unsafe { std::slice::from_raw_parts(p, n) };
     q
 }
 
 fn flatten_vec_mut<T>(v: &mut [T]) -> &mut [f64] {
-    let n = v.len();
+    let n = std::mem::size_of_val(v) / std::mem::size_of::<f64>();
     let p: *mut f64 = unsafe { std::mem::transmute(v.as_mut_ptr()) };
-    let q: &mut [f64] = unsafe {
-        std::slice::from_raw_parts_mut(p, n * std::mem::size_of::<T>() / std::mem::size_of::<f64>())
-    };
+    let q: &mut [f64] = unsafe { std::slice::from_raw_parts_mut(p, n) };
     q
 }
 
+/// The inverse of `flatten_vec`: `v` must be a whole number of `T`s flattened
+/// by it, so that the pointer is aligned for `T`.
+fn unflatten_vec<T>(v: &[f64]) -> &[T] {
+    let n = std::mem::size_of_val(v) / std::mem::size_of::<T>();
+    let p: *const T = v.as_ptr().cast();
+    unsafe { std::slice::from_raw_parts(p, n) }
+}
+
+fn unflatten_vec_mut<T>(v: &mut [f64]) -> &mut [T] {
+    let n = std::mem::size_of_val(v) / std::mem::size_of::<T>();
+    let p: *mut T = v.as_mut_ptr().cast();
+    unsafe { std::slice::from_raw_parts_mut(p, n) }
+}
+
+/// Evaluates `n` rows of `app`, each `cp` elements of `args` and `co` of
+/// `outs`, with denormals flushed to zero if `flush` is true. The flushing
+/// mode is per thread, and symjit's worker threads do not inherit it, so a
+/// kernel compiled with `use_threads` is passed `threaded` (and compiled
+/// without): its rows are split across threads here, and each thread flushes
+/// on its own.
+fn evaluate_flushed<T: Element>(
+    app: &Application,
+    args: &[T],
+    outs: &mut [T],
+    (n, cp, co): (usize, usize, usize),
+    flush: bool,
+    threaded: bool,
+) {
+    if !threaded {
+        let _guard = FlushDenormals::new(flush);
+        app.evaluate_matrix(args, outs, n);
+        return;
+    }
+
+    let applet = app.as_applet();
+    let k = std::mem::size_of::<T>() / std::mem::size_of::<f64>();
+
+    threads::for_each_chunk(
+        flatten_vec(&args[..n * cp]),
+        cp * k,
+        flatten_vec_mut(&mut outs[..n * co]),
+        co * k,
+        n,
+        threads::resolve_threads(0),
+        false,
+        |a, o, m| {
+            let _guard = FlushDenormals::new(flush);
+            applet.evaluate_matrix::<T>(unflatten_vec(a), unflatten_vec_mut(o), m);
+        },
+    );
+}
+
+/// Whether `app` must be split across threads by `evaluate_flushed`, in which
+/// case its own threading is turned off.
+fn take_threads(app: &mut Application, flush: bool) -> bool {
+    let threaded = flush && app.use_threads;
+    app.use_threads &= !threaded;
+    threaded
+}
+
+/// A buffer of real or complex scalars, so that code that handles both kinds
+/// of runners can allocate and fill buffers uniformly. `as_flat` and
+/// `as_flat_mut` view a complex buffer as interleaved (re, im) pairs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scalars {
+    Real(Vec<f64>),
+    Complex(Vec<Complex<f64>>),
+}
+
+impl Scalars {
+    /// `n` real zeros.
+    pub fn real(n: usize) -> Self {
+        Scalars::Real(vec![0.0; n])
+    }
+
+    /// `n` complex zeros (`2 * n` flat values).
+    pub fn complex(n: usize) -> Self {
+        Scalars::Complex(vec![Complex::default(); n])
+    }
+
+    pub fn is_complex(&self) -> bool {
+        matches!(self, Scalars::Complex(_))
+    }
+
+    /// The number of scalars (complex numbers count once).
+    pub fn len(&self) -> usize {
+        match self {
+            Scalars::Real(v) => v.len(),
+            Scalars::Complex(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_flat(&self) -> &[f64] {
+        match self {
+            Scalars::Real(v) => v,
+            Scalars::Complex(v) => flatten_vec(v),
+        }
+    }
+
+    pub fn as_flat_mut(&mut self) -> &mut [f64] {
+        match self {
+            Scalars::Real(v) => v,
+            Scalars::Complex(v) => flatten_vec_mut(v),
+        }
+    }
+
+    pub fn as_real(&self) -> Option<&[f64]> {
+        match self {
+            Scalars::Real(v) => Some(v),
+            Scalars::Complex(_) => None,
+        }
+    }
+
+    pub fn as_real_mut(&mut self) -> Option<&mut [f64]> {
+        match self {
+            Scalars::Real(v) => Some(v),
+            Scalars::Complex(_) => None,
+        }
+    }
+
+    pub fn as_complex(&self) -> Option<&[Complex<f64>]> {
+        match self {
+            Scalars::Complex(v) => Some(v),
+            Scalars::Real(_) => None,
+        }
+    }
+
+    pub fn as_complex_mut(&mut self) -> Option<&mut [Complex<f64>]> {
+        match self {
+            Scalars::Complex(v) => Some(v),
+            Scalars::Real(_) => None,
+        }
+    }
+}
+
+impl From<Vec<f64>> for Scalars {
+    fn from(v: Vec<f64>) -> Self {
+        Scalars::Real(v)
+    }
+}
+
+impl From<Vec<Complex<f64>>> for Scalars {
+    fn from(v: Vec<Complex<f64>>) -> Self {
+        Scalars::Complex(v)
+    }
+}
+
+/// Transposes complex rows of `row_len` values each, stored one after the
+/// other (array of structures), into SIMD rows for the complex runners: lane
+/// `l` of the `j`-th value of SIMD row `k` is value `j` of row `4 * k + l`,
+/// with the real and imaginary parts in separate vectors. If the number of
+/// rows is not a multiple of 4, the last SIMD row is padded with zeros.
+pub fn pack_complex_aos_to_simd(values: &[Complex<f64>], row_len: usize) -> Vec<Complex<f64x4>> {
+    let nrows = checked_rows(values.len(), row_len);
+    let zero = Complex::default();
+
+    (0..nrows.div_ceil(4) * row_len)
+        .map(|i| {
+            let (k, j) = (i / row_len, i % row_len);
+            let z: [Complex<f64>; 4] = std::array::from_fn(|l| {
+                values
+                    .get((4 * k + l) * row_len + j)
+                    .copied()
+                    .unwrap_or(zero)
+            });
+            Complex::new(f64x4::from(z.map(|z| z.re)), f64x4::from(z.map(|z| z.im)))
+        })
+        .collect()
+}
+
+/// The inverse of `pack_complex_aos_to_simd`: returns the first `nrows` rows of
+/// `row_len` values each from the SIMD rows in `packed`, dropping the padding.
+pub fn unpack_complex_simd_to_aos(
+    packed: &[Complex<f64x4>],
+    row_len: usize,
+    nrows: usize,
+) -> Vec<Complex<f64>> {
+    assert!(
+        packed.len() >= nrows.div_ceil(4) * row_len,
+        "packed holds fewer than {} rows",
+        nrows
+    );
+
+    (0..nrows * row_len)
+        .map(|i| {
+            let (r, j) = (i / row_len, i % row_len);
+            let z = packed[(r / 4) * row_len + j];
+            Complex::new(z.re.to_array()[r % 4], z.im.to_array()[r % 4])
+        })
+        .collect()
+}
+
+/// The layout of a matrix of outputs with one row per input row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputLayout {
+    /// `outs[i * count_obs + j]` holds output `j` of row `i`.
+    #[default]
+    RowMajor,
+    /// `outs[j * nrows + i]` holds output `j` of row `i` (the Fortran/BLAS order).
+    ColumnMajor,
+}
+
+/// The operation that combines the lanes in `evaluate_simd_reduce`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReduceOp {
+    Sum,
+    Product,
+}
+
+/// The number of lanes of the SIMD code generated for this architecture.
+fn simd_lanes() -> usize {
+    if cfg!(target_arch = "aarch64") {
+        2
+    } else {
+        4
+    }
+}
+
+/// Writes `app` to `file` in the framed format of `savefile`, with the given
+/// output names (empty if the outputs are not named).
+pub(crate) fn save_app(
+    app: &Application,
+    file: &str,
+    complex: bool,
+    names: Vec<String>,
+) -> Result<()> {
+    let header = Header {
+        complex,
+        simd: has_simd(app),
+        names,
+    };
+
+    let mut fs = std::fs::File::create(file)?;
+    savefile::write_header(&mut fs, &header)?;
+    app.save(&mut fs)
+}
+
+/// Reads a file written by `save_app`, checking its version and that it
+/// holds a complex kernel if and only if `complex` is true.
+pub(crate) fn load_app(
+    file: &str,
+    config: &Config,
+    complex: bool,
+) -> Result<(Application, Header)> {
+    let mut fs = std::fs::File::open(file)?;
+    let header = savefile::read_header(&mut fs).map_err(|e| anyhow!("{}: {}", file, e))?;
+
+    if header.complex != complex {
+        return Err(anyhow!(
+            "{} holds a {} kernel, but a {} runner was requested",
+            file,
+            if header.complex { "complex" } else { "real" },
+            if complex { "complex" } else { "real" }
+        ));
+    }
+
+    let app = Application::load(&mut fs, config)?;
+    Ok((app, header))
+}
+
+/// Checks that `app` has at least one parameter and one output, since the
+/// runners find the number of rows by dividing the buffer lengths by them.
+fn checked_shape(app: Application) -> Result<Application> {
+    if app.count_obs == 0 {
+        return Err(anyhow!("the kernel has no outputs"));
+    }
+
+    if app.count_params == 0 {
+        return Err(anyhow!(
+            "the kernel has no parameters; for an expression that does not use its \
+             parameters, pass their number as num_params to compile_with_funcs"
+        ));
+    }
+
+    Ok(app)
+}
+
+/// Compiles `ev` after checking the shape of its instructions like
+/// `checked_shape`. The instructions are checked before symjit sees them,
+/// since symjit reports at least one parameter for a constant kernel; the
+/// parameters may also be given explicitly by `num_params`.
+fn compile_checked<T: Clone + Number>(
+    ev: &ExpressionEvaluator<T>,
+    config: Config,
+    num_params: usize,
+) -> Result<Application> {
+    let (instructions, _, _) = ev.export_instructions();
+
+    if passes::count_outputs(&instructions) == 0 {
+        return Err(anyhow!("the kernel has no outputs"));
+    }
+
+    if num_params == 0 && passes::count_params(&instructions) == 0 {
+        return Err(anyhow!(
+            "the kernel has no parameters; for an expression that does not use its \
+             parameters, pass their number as num_params to compile_with_funcs"
+        ));
+    }
+
+    checked_shape(compile(ev, config, num_params)?)
+}
+
+/// Returns the number of rows in a buffer of `len` real slots, panicking if
+/// `len` is not an exact multiple of `count` (the real slots per row).
+fn checked_rows(len: usize, count: usize) -> usize {
+    assert!(
+        len.is_multiple_of(count),
+        "input length ({} real slots) is not a multiple of the {} real slots per row",
+        len,
+        count
+    );
+    len / count
+}
+
+/// Evaluates complex rows stored as split real and imaginary arrays. `cp` and
+/// `co` are the number of complex parameters and outputs per row. The rows are
+/// interleaved into a small staging buffer block by block, so no full-size
+/// interleaved copy is made. `eval(args, outs, n)` evaluates `n` rows.
+fn evaluate_split_blocks(
+    cp: usize,
+    co: usize,
+    re: &[f64],
+    im: &[f64],
+    out_re: &mut [f64],
+    out_im: &mut [f64],
+    mut eval: impl FnMut(&[Complex<f64>], &mut [Complex<f64>], usize),
+) {
+    assert_eq!(re.len(), im.len());
+    assert_eq!(out_re.len(), out_im.len());
+    let nrows = checked_rows(re.len(), cp);
+    assert!(out_re.len() >= nrows * co);
+
+    let mut args = vec![Complex::<f64>::default(); INPLACE_BLOCK * cp];
+    let mut outs = vec![Complex::<f64>::default(); INPLACE_BLOCK * co];
+
+    for start in (0..nrows).step_by(INPLACE_BLOCK) {
+        let m = INPLACE_BLOCK.min(nrows - start);
+        let (a, b) = (start * cp, (start + m) * cp);
+
+        for (z, (x, y)) in args.iter_mut().zip(re[a..b].iter().zip(&im[a..b])) {
+            *z = Complex::new(*x, *y);
+        }
+
+        eval(&args[..m * cp], &mut outs[..m * co], m);
+
+        let (a, b) = (start * co, (start + m) * co);
+
+        for (z, (x, y)) in outs
+            .iter()
+            .zip(out_re[a..b].iter_mut().zip(&mut out_im[a..b]))
+        {
+            *x = z.re;
+            *y = z.im;
+        }
+    }
+}
+
+/// Like `evaluate_split_blocks`, but packs groups of four rows into
+/// `Complex<f64x4>` elements, with the real and imaginary parts of the rows in
+/// separate vectors. The rows left over after the last whole group are
+/// evaluated by `eval_rest` as `Complex<f64>` rows.
+#[allow(clippy::too_many_arguments)]
+fn evaluate_split_planar(
+    cp: usize,
+    co: usize,
+    re: &[f64],
+    im: &[f64],
+    out_re: &mut [f64],
+    out_im: &mut [f64],
+    mut eval: impl FnMut(&[Complex<f64x4>], &mut [Complex<f64x4>], usize),
+    eval_rest: impl FnMut(&[Complex<f64>], &mut [Complex<f64>], usize),
+) {
+    assert_eq!(re.len(), im.len());
+    assert_eq!(out_re.len(), out_im.len());
+    let nrows = checked_rows(re.len(), cp);
+    assert!(out_re.len() >= nrows * co);
+
+    let zero = Complex::new(f64x4::default(), f64x4::default());
+    let groups = INPLACE_BLOCK / 4;
+    let mut args = vec![zero; groups * cp];
+    let mut outs = vec![zero; groups * co];
+    let whole = nrows / 4 * 4;
+
+    for start in (0..whole).step_by(INPLACE_BLOCK) {
+        let g = groups.min((whole - start) / 4);
+
+        for k in 0..g {
+            for p in 0..cp {
+                let row = |v: &[f64]| {
+                    f64x4::from(std::array::from_fn::<f64, 4, _>(|l| {
+                        v[(start + 4 * k + l) * cp + p]
+                    }))
+                };
+                args[k * cp + p] = Complex::new(row(re), row(im));
+            }
+        }
+
+        eval(&args[..g * cp], &mut outs[..g * co], g);
+
+        for k in 0..g {
+            for j in 0..co {
+                let z = outs[k * co + j];
+                let (x, y) = (z.re.to_array(), z.im.to_array());
+
+                for l in 0..4 {
+                    out_re[(start + 4 * k + l) * co + j] = x[l];
+                    out_im[(start + 4 * k + l) * co + j] = y[l];
+                }
+            }
+        }
+    }
+
+    evaluate_split_blocks(
+        cp,
+        co,
+        &re[whole * cp..],
+        &im[whole * cp..],
+        &mut out_re[whole * co..nrows * co],
+        &mut out_im[whole * co..nrows * co],
+        eval_rest,
+    );
+}
+
 /********************* CompiledRealRunner ************************/
 
-pub struct CompiledRealRunner {
-    app: Application,
-}
+/// The C calling convention of `CompiledRealRunner::entry_ptr`:
+/// `entry(context, args, outs, nrows)`, where `context` is the runner
+/// (`context_ptr`), `args` points to `nrows * count_params` parameters stored
+/// row by row, and `outs` to room for `nrows * count_obs` outputs. It returns
+/// 0, or -1 if the evaluation panicked (e.g., in a `map_outputs` closure), in
+/// which case the contents of `outs` are undefined.
+pub type EntryFn = unsafe extern "C" fn(*const c_void, *const f64, *mut f64, usize) -> i32;
+
+/// The Rust trampoline behind `entry_ptr`. A panic must not unwind into the C
+/// caller, so it is caught and reported as -1.
+unsafe extern "C" fn entry(
+    context: *const c_void,
+    args: *const f64,
+    outs: *mut f64,
+    nrows: usize,
+) -> i32 {
+    let runner = &*(context as *const CompiledRealRunner);
+    let args = std::slice::from_raw_parts(args, nrows * runner.app.count_params);
+    let outs = std::slice::from_raw_parts_mut(outs, nrows * runner.app.count_obs);
+
+    match catch_unwind(AssertUnwindSafe(|| runner.run(args, outs, nrows))) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// The first operation that produced a non-finite value, as found by
+/// `CompiledRealRunner::evaluate_matrix_traced`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonFiniteTrace {
+    /// The first row with a non-finite output.
+    pub row: usize,
+    /// The index of the culprit in `ExpressionEvaluator::export_instructions`,
+    /// or `None` if the interpreter evaluated the row without a non-finite
+    /// value (e.g., because of `FpModel::Fast` approximations).
+    pub instruction: Option<usize>,
+    /// The operation of the culprit, as reported by `num_instructions` (e.g.,
+    /// "add" or "sqrt").
+    pub op: Option<String>,
+}
+
+pub struct CompiledRealRunner {
+    app: Application,
+    flush_denormals: bool,
+    threaded: bool,
+    counters: Option<Counters>,
+    post: Option<PostFn>,
+    trace: Option<Program>,
+}
+
+impl CompiledRealRunner {
+    pub fn compile(ev: &ExpressionEvaluator<f64>, config: Config) -> Result<Self> {
+        Self::compile_with_funcs(ev, config, 0)
+    }
+
+    pub fn compile_with_funcs(
+        ev: &ExpressionEvaluator<f64>,
+        mut config: Config,
+        num_params: usize,
+    ) -> Result<Self> {
+        config.set_complex(false);
+        let flush_denormals = config.flush_denormals();
+        let instrument = config.instrument();
+        let trace = if config.trace_nonfinite() {
+            Some(Program::new(ev)?)
+        } else {
+            None
+        };
+        let app = compile_checked(ev, config.clone(), num_params)?;
+        let mut runner = Self::new(app, flush_denormals, instrument);
+        runner.trace = trace;
+        Ok(runner)
+    }
+
+    pub fn compile_string(model: String, config: Config) -> Result<Self> {
+        Self::compile_string_with_funcs(model, config, 0)
+    }
+
+    pub fn compile_string_with_funcs(
+        model: String,
+        mut config: Config,
+        num_params: usize,
+    ) -> Result<Self> {
+        config.set_complex(false);
+        config.set_simd(true);
+        let flush_denormals = config.flush_denormals();
+        let instrument = config.instrument();
+        let app = checked_shape(compile_string(model, config, num_params)?)?;
+        Ok(Self::new(app, flush_denormals, instrument))
+    }
+
+    pub(crate) fn new(mut app: Application, flush_denormals: bool, instrument: bool) -> Self {
+        let counters =
+            instrument.then(|| Counters::new(if has_simd(&app) { simd_lanes() } else { 1 }));
+        let threaded = take_threads(&mut app, flush_denormals);
+
+        Self {
+            app,
+            flush_denormals,
+            threaded,
+            counters,
+            post: None,
+            trace: None,
+        }
+    }
+
+    /// Evaluates `n` rows, with denormals flushed to zero if requested, and
+    /// applies the `map_outputs` closure to them.
+    fn run<T: Element>(&self, args: &[T], outs: &mut [T], n: usize) {
+        let shape = (n, self.app.count_params, self.app.count_obs);
+        evaluate_flushed(
+            &self.app,
+            args,
+            outs,
+            shape,
+            self.flush_denormals,
+            self.threaded,
+        );
+
+        if let Some(post) = &self.post {
+            map_rows(post, outs, self.app.count_obs, n);
+        }
+
+        if let Some(counters) = &self.counters {
+            counters.record::<T>(n);
+        }
+    }
+
+    /// Sets a closure that every evaluation applies to the outputs of each row
+    /// (e.g., a table lookup) right after the kernel, replacing any previous
+    /// one. The lanes of SIMD rows are passed one at a time. It is kept by
+    /// `into_threaded`, where each thread applies it to its own rows, so no
+    /// second pass over the outputs is needed.
+    pub fn map_outputs(&mut self, f: impl Fn(&mut [f64]) + Send + Sync + 'static) {
+        self.post = Some(Arc::new(f));
+    }
+
+    /// An estimate of the number of times the generated code has been entered,
+    /// computed from the rows evaluated (one entry per row for the scalar code
+    /// and one per group of `simd_lanes()` rows for the SIMD code), or `None`
+    /// if the runner was compiled without `Config.set_instrument(true)`. The
+    /// entries are not counted in the generated code, so the scalar reruns of
+    /// SIMD groups whose lanes take different branches are missing.
+    pub fn estimated_call_count(&self) -> Option<u64> {
+        self.counters.as_ref().map(|c| c.estimated_calls())
+    }
+
+    /// The timestamp counter (`rdtsc` on x86-64 and `cntvct_el0` on aarch64)
+    /// read at the end of the last call, or `None` without instrumentation.
+    pub fn last_call_timestamp(&self) -> Option<u64> {
+        self.counters.as_ref().map(|c| c.last_timestamp())
+    }
+
+    pub fn count_params(&self) -> usize {
+        self.app.count_params
+    }
+
+    pub fn count_obs(&self) -> usize {
+        self.app.count_obs
+    }
+
+    /// A C ABI entry point (see `EntryFn`) that evaluates the rows like
+    /// `evaluate`, for calling the kernel from C or Python (ctypes/cffi)
+    /// without going through Rust. It is a Rust trampoline that takes the
+    /// runner as a context pointer, not the raw function pointer of the
+    /// generated code (whose calling convention is internal to symjit): pass
+    /// `context_ptr()` as the first argument. Both pointers are valid only
+    /// while the runner is alive and not moved.
+    pub fn entry_ptr(&self) -> *const c_void {
+        entry as EntryFn as *const c_void
+    }
+
+    /// The context argument of `entry_ptr`, which identifies this runner.
+    pub fn context_ptr(&self) -> *const c_void {
+        self as *const Self as *const c_void
+    }
+
+    pub fn evaluate<T>(&self, args: &[T], outs: &mut [T])
+    where
+        T: Element,
+    {
+        let n = args.len() / self.app.count_params;
+        assert!(outs.len() / self.app.count_obs >= n);
+        self.run(args, outs, n);
+    }
+
+    /// Like `evaluate`, but checks the element type and the buffer sizes
+    /// first. `T` must be `f64` or a SIMD vector with as many lanes as the
+    /// generated SIMD code (`simd_lanes()`), and `args` must hold a whole number
+    /// of rows. Otherwise, an error is returned and nothing is evaluated.
+    pub fn try_evaluate<T>(&self, args: &[T], outs: &mut [T]) -> Result<()>
+    where
+        T: SimdElem + Element,
+    {
+        if T::LANES != 1 && T::LANES != self.simd_lanes() {
+            return Err(anyhow!(
+                "{}-lane elements passed to a runner compiled for {} lanes",
+                T::LANES,
+                self.simd_lanes()
+            ));
+        }
+
+        let count_params = self.app.count_params;
+        let count_obs = self.app.count_obs;
+
+        if !args.len().is_multiple_of(count_params) {
+            return Err(anyhow!(
+                "args has {} elements, which is not a multiple of {} parameters",
+                args.len(),
+                count_params
+            ));
+        }
+
+        let n = args.len() / count_params;
+
+        if outs.len() < n * count_obs {
+            return Err(anyhow!(
+                "outs has {} elements, but {} rows need {}",
+                outs.len(),
+                n,
+                n * count_obs
+            ));
+        }
+
+        self.run(args, outs, n);
+        Ok(())
+    }
+
+    /// Evaluates the SIMD rows in `args` (`count_params` vectors per row) of a
+    /// single-output kernel and combines all the lanes of the results with
+    /// `op`, so that the caller does not need to extract the lanes.
+    pub fn evaluate_simd_reduce(&self, args: &[f64x4], op: ReduceOp) -> f64 {
+        assert_eq!(
+            self.app.count_obs, 1,
+            "evaluate_simd_reduce needs a single-output kernel"
+        );
+        let n = checked_rows(args.len(), self.app.count_params);
+        let mut outs = vec![f64x4::default(); n];
+        self.run(args, &mut outs, n);
+
+        let lanes = outs.iter().flat_map(|v| v.to_array());
+
+        match op {
+            ReduceOp::Sum => lanes.sum(),
+            ReduceOp::Product => lanes.product(),
+        }
+    }
+
+    /// Evaluates `nrows` rows of scalar data stored row by row. It is an alias
+    /// of `evaluate` on the first `nrows` rows: given `f64` rows, symjit
+    /// already transposes each group of `simd_lanes()` rows into the lanes of
+    /// the SIMD code and runs the remaining rows as scalar code.
+    pub fn evaluate_auto_simd(&self, args: &[f64], outs: &mut [f64], nrows: usize) {
+        let count_params = self.app.count_params;
+        let count_obs = self.app.count_obs;
+
+        assert!(
+            args.len() >= nrows * count_params && outs.len() >= nrows * count_obs,
+            "args and outs must hold at least {} rows",
+            nrows
+        );
+
+        self.run(
+            &args[..nrows * count_params],
+            &mut outs[..nrows * count_obs],
+            nrows,
+        );
+    }
+
+    /// The number of lanes of the generated SIMD code (4 on x86-64 and 2 on
+    /// aarch64).
+    pub fn simd_lanes(&self) -> usize {
+        simd_lanes()
+    }
+
+    /// Runs the kernel once on zeroed inputs so that the code pages are mapped
+    /// and cached before the first timed call. It does not affect later outputs.
+    pub fn warmup(&self) {
+        let args = vec![0.0; WARMUP_ROWS * self.app.count_params];
+        let mut outs = vec![0.0; WARMUP_ROWS * self.app.count_obs];
+        self.evaluate(&args, &mut outs);
+    }
+
+    /// Evaluates `nrows` rows of the row-major `args` and writes the outputs
+    /// in the given `layout`. For `ColumnMajor`, the rows are evaluated block
+    /// by block into a small staging buffer and scattered into the columns, so
+    /// no full-size transpose is needed.
+    pub fn evaluate_matrix_layout(
+        &self,
+        args: &[f64],
+        outs: &mut [f64],
+        nrows: usize,
+        layout: OutputLayout,
+    ) {
+        let count_params = self.app.count_params;
+        let count_obs = self.app.count_obs;
+        assert!(args.len() >= nrows * count_params);
+        assert!(outs.len() >= nrows * count_obs);
+
+        if layout == OutputLayout::RowMajor {
+            self.run(&args[..nrows * count_params], outs, nrows);
+            return;
+        }
+
+        let mut staging = vec![0.0; INPLACE_BLOCK * count_obs];
+
+        for start in (0..nrows).step_by(INPLACE_BLOCK) {
+            let m = INPLACE_BLOCK.min(nrows - start);
+            let a = &args[start * count_params..(start + m) * count_params];
+            self.run(a, &mut staging[..m * count_obs], m);
+
+            for i in 0..m {
+                for j in 0..count_obs {
+                    outs[j * nrows + start + i] = staging[i * count_obs + j];
+                }
+            }
+        }
+    }
+
+    /// Evaluates the rows yielded by `rows`, each holding one row of
+    /// parameters, and appends their outputs to `out`. The rows are gathered
+    /// into blocks so that the SIMD code is used. If a row does not have
+    /// `count_params` elements, an error is returned and `out` is left as it was.
+    pub fn evaluate_rows<'a>(
+        &self,
+        rows: impl Iterator<Item = &'a [f64]>,
+        out: &mut Vec<f64>,
+    ) -> Result<()> {
+        let count_params = self.app.count_params;
+        let count_obs = self.app.count_obs;
+        let len = out.len();
+        let mut staging = Vec::with_capacity(INPLACE_BLOCK * count_params);
+
+        let flush = |staging: &mut Vec<f64>, out: &mut Vec<f64>| {
+            let m = staging.len() / count_params;
+            let start = out.len();
+            out.resize(start + m * count_obs, 0.0);
+            self.run(staging, &mut out[start..], m);
+            staging.clear();
+        };
+
+        for (i, row) in rows.enumerate() {
+            if row.len() != count_params {
+                out.truncate(len);
+                return Err(anyhow!(
+                    "row {} has {} elements, but the model has {} parameters",
+                    i,
+                    row.len(),
+                    count_params
+                ));
+            }
+
+            staging.extend_from_slice(row);
+
+            if staging.len() == INPLACE_BLOCK * count_params {
+                flush(&mut staging, out);
+            }
+        }
+
+        if !staging.is_empty() {
+            flush(&mut staging, out);
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates only the rows of `args` (`nrows` rows of parameters) whose
+    /// `mask` entry is true and leaves the outputs of the other rows untouched.
+    /// The active rows are gathered into blocks, so that the SIMD code runs on
+    /// active rows only. Panics if `args`, `outs`, or `mask` hold fewer than
+    /// `nrows` rows.
+    pub fn evaluate_masked(&self, args: &[f64], outs: &mut [f64], mask: &[bool], nrows: usize) {
+        let count_params = self.app.count_params;
+        let count_obs = self.app.count_obs;
+
+        assert!(
+            args.len() >= nrows * count_params
+                && outs.len() >= nrows * count_obs
+                && mask.len() >= nrows,
+            "args, outs, and mask must hold at least {} rows",
+            nrows
+        );
+
+        let mut staging = Vec::with_capacity(INPLACE_BLOCK * count_params);
+        let mut results = vec![0.0; INPLACE_BLOCK * count_obs];
+        let mut rows = Vec::with_capacity(INPLACE_BLOCK);
+
+        let mut flush = |staging: &mut Vec<f64>, rows: &mut Vec<usize>, outs: &mut [f64]| {
+            let m = rows.len();
+            self.run(staging, &mut results[..m * count_obs], m);
+
+            for (k, i) in rows.iter().enumerate() {
+                outs[i * count_obs..(i + 1) * count_obs]
+                    .copy_from_slice(&results[k * count_obs..(k + 1) * count_obs]);
+            }
+
+            staging.clear();
+            rows.clear();
+        };
+
+        for i in (0..nrows).filter(|&i| mask[i]) {
+            staging.extend_from_slice(&args[i * count_params..(i + 1) * count_params]);
+            rows.push(i);
+
+            if rows.len() == INPLACE_BLOCK {
+                flush(&mut staging, &mut rows, outs);
+            }
+        }
+
+        if !rows.is_empty() {
+            flush(&mut staging, &mut rows, outs);
+        }
+    }
+
+    /// Evaluates `nrows` rows whose parameters come from two row-major
+    /// sources: the parameters listed in `int_slots` (in increasing order) are
+    /// taken from `ints`, `int_slots.len()` per row, and cast to f64, and the
+    /// others are taken from `floats` in order. This avoids converting integer
+    /// columns (e.g., indices or flags) beforehand. Panics if `int_slots` is
+    /// not increasing or out of range, or if a buffer is too short.
+    pub fn evaluate_with_ints(
+        &self,
+        int_slots: &[usize],
+        ints: &[i32],
+        floats: &[f64],
+        outs: &mut [f64],
+        nrows: usize,
+    ) {
+        let count_params = self.app.count_params;
+        let count_obs = self.app.count_obs;
+        let ni = int_slots.len();
+        let nf = count_params.saturating_sub(ni);
+
+        assert!(
+            int_slots.windows(2).all(|w| w[0] < w[1])
+                && int_slots.iter().all(|&k| k < count_params),
+            "int_slots must be increasing and less than {}",
+            count_params
+        );
+        assert!(
+            ints.len() >= nrows * ni
+                && floats.len() >= nrows * nf
+                && outs.len() >= nrows * count_obs,
+            "ints, floats, and outs must hold at least {} rows",
+            nrows
+        );
+
+        let mut staging = Vec::with_capacity(INPLACE_BLOCK * count_params);
+
+        for start in (0..nrows).step_by(INPLACE_BLOCK) {
+            let m = (nrows - start).min(INPLACE_BLOCK);
+            staging.clear();
+
+            for i in start..start + m {
+                let mut ints = ints[i * ni..(i + 1) * ni].iter();
+                let mut floats = floats[i * nf..(i + 1) * nf].iter();
+                let mut slots = int_slots.iter().peekable();
+
+                for j in 0..count_params {
+                    if slots.next_if_eq(&&j).is_some() {
+                        staging.push(f64::from(*ints.next().unwrap()));
+                    } else {
+                        staging.push(*floats.next().unwrap());
+                    }
+                }
+            }
+
+            self.run(
+                &staging,
+                &mut outs[start * count_obs..(start + m) * count_obs],
+                m,
+            );
+        }
+    }
+
+    /// Evaluates the kernel over the windows of `window` consecutive samples of
+    /// `signal`, starting every `stride` samples, with the samples of a window
+    /// as the parameters (`window` must be `count_params`). The outputs of the
+    /// `(signal.len() - window) / stride + 1` windows are stored row by row in
+    /// `outs`. Overlapping windows are staged block by block instead of
+    /// building the whole args matrix.
+    pub fn evaluate_windowed(
+        &self,
+        signal: &[f64],
+        window: usize,
+        stride: usize,
+        outs: &mut [f64],
+    ) {
+        let count_obs = self.app.count_obs;
+        assert_eq!(
+            window, self.app.count_params,
+            "the window must have one sample per parameter"
+        );
+        assert!(stride > 0, "the stride must be positive");
+
+        if signal.len() < window {
+            return;
+        }
+
+        let nrows = (signal.len() - window) / stride + 1;
+        assert!(outs.len() >= nrows * count_obs);
+
+        if stride == window {
+            // the windows are back to back and already form the args matrix
+            self.run(
+                &signal[..nrows * window],
+                &mut outs[..nrows * count_obs],
+                nrows,
+            );
+            return;
+        }
+
+        let mut staging = Vec::with_capacity(INPLACE_BLOCK * window);
+
+        for start in (0..nrows).step_by(INPLACE_BLOCK) {
+            let m = (nrows - start).min(INPLACE_BLOCK);
+            staging.clear();
+
+            for i in start..start + m {
+                staging.extend_from_slice(&signal[i * stride..i * stride + window]);
+            }
+
+            self.run(
+                &staging,
+                &mut outs[start * count_obs..(start + m) * count_obs],
+                m,
+            );
+        }
+    }
+
+    /// Evaluates `nrows` rows like `evaluate_matrix_checked`. If an output is
+    /// not finite, the first such row is evaluated again, instruction by
+    /// instruction, by the interpreter to find the first operation whose
+    /// result is not finite. Returns `None` if all the outputs are finite.
+    /// The runner must be compiled with `Config::set_trace_nonfinite(true)`.
+    pub fn evaluate_matrix_traced(
+        &self,
+        args: &[f64],
+        outs: &mut [f64],
+        nrows: usize,
+    ) -> Result<Option<NonFiniteTrace>> {
+        let Some(prog) = &self.trace else {
+            return Err(anyhow!(
+                "the runner was compiled without Config::set_trace_nonfinite(true)"
+            ));
+        };
+
+        let flags = self.evaluate_matrix_checked(args, outs, nrows);
+
+        let Some(row) = flags.iter().position(|ok| !ok) else {
+            return Ok(None);
+        };
+
+        let cp = self.app.count_params;
+        let culprit = prog.trace_nonfinite(&args[row * cp..(row + 1) * cp]);
+
+        Ok(Some(NonFiniteTrace {
+            row,
+            instruction: culprit.map(|(i, _)| i),
+            op: culprit.map(|(_, op)| op.to_string()),
+        }))
+    }
+
+    /// Evaluates `nrows` rows like `evaluate` and returns one flag per row,
+    /// true if all the outputs of that row are finite (not NaN or infinite),
+    /// so that invalid parameter sets (e.g., `log` of a negative number) can be
+    /// filtered out without scanning the outputs again. Rows evaluated together
+    /// by the SIMD code are flagged per lane.
+    pub fn evaluate_matrix_checked(
+        &self,
+        args: &[f64],
+        outs: &mut [f64],
+        nrows: usize,
+    ) -> Vec<bool> {
+        let count_obs = self.app.count_obs;
+
+        assert!(
+            args.len() >= nrows * self.app.count_params && outs.len() >= nrows * count_obs,
+            "args and outs must hold at least {} rows",
+            nrows
+        );
+
+        let outs = &mut outs[..nrows * count_obs];
+        self.run(args, outs, nrows);
+
+        outs.chunks(count_obs)
+            .map(|row| row.iter().all(|x| x.is_finite()))
+            .collect()
+    }
+
+    /// Evaluates the kernel over the Cartesian grid of `axes`, one axis of
+    /// values per parameter, and returns the outputs in row-major grid order
+    /// (the last axis varies fastest), `count_obs` values per grid point. For
+    /// two parameters, the result is a `axes[0].len() x axes[1].len()` heatmap.
+    pub fn evaluate_grid(&self, axes: &[&[f64]]) -> Vec<f64> {
+        let count_params = self.app.count_params;
+        assert_eq!(
+            axes.len(),
+            count_params,
+            "evaluate_grid needs one axis per parameter"
+        );
+
+        let n: usize = axes.iter().map(|a| a.len()).product();
+        let mut args = Vec::with_capacity(n * count_params);
+
+        for i in 0..n {
+            let mut rest = i;
+            let start = args.len();
+            args.resize(start + count_params, 0.0);
+
+            for (k, axis) in axes.iter().enumerate().rev() {
+                args[start + k] = axis[rest % axis.len()];
+                rest /= axis.len();
+            }
+        }
+
+        let mut outs = vec![0.0; n * self.app.count_obs];
+        self.run(&args, &mut outs, n);
+        outs
+    }
+
+    /// Returns the central finite-difference derivatives of the outputs with
+    /// respect to the parameters at `args`, `(f(args + h*e_j) - f(args - h*e_j)) / 2h`,
+    /// as a row-major `count_obs x count_params` matrix (the gradient for a
+    /// single output). The `2 * count_params` perturbed points are evaluated in
+    /// one batch. The error is `O(h^2)` plus the rounding error `O(eps / h)`.
+    pub fn evaluate_fd_gradient(&self, args: &[f64], h: f64) -> Vec<f64> {
+        let np = self.app.count_params;
+        let no = self.app.count_obs;
+        assert_eq!(args.len(), np);
+
+        let mut points = Vec::with_capacity(2 * np * np);
+
+        for j in 0..np {
+            for sign in [1.0, -1.0] {
+                let start = points.len();
+                points.extend_from_slice(args);
+                points[start + j] += sign * h;
+            }
+        }
+
+        let mut outs = vec![0.0; 2 * np * no];
+        self.run(&points, &mut outs, 2 * np);
+
+        let mut jac = vec![0.0; no * np];
+
+        for j in 0..np {
+            let plus = &outs[2 * j * no..(2 * j + 1) * no];
+            let minus = &outs[(2 * j + 1) * no..(2 * j + 2) * no];
+
+            for i in 0..no {
+                jac[i * np + j] = (plus[i] - minus[i]) / (2.0 * h);
+            }
+        }
+
+        jac
+    }
+
+    /// Evaluates `nrows` rows in place: on entry, `buf` holds the row-major
+    /// `nrows x count_params` parameters, and on return, its head holds the
+    /// row-major `nrows x count_obs` outputs (the rest is left unspecified).
+    /// Requires `count_obs <= count_params`.
+    ///
+    /// The generated code may use the output slots as scratch before all the
+    /// parameters of a row are read, so the parameters are copied block by block
+    /// to a small staging buffer. The outputs of a block only overwrite the
+    /// parameters of the same or earlier blocks, which are already staged.
+    pub fn evaluate_inplace(&self, buf: &mut [f64], nrows: usize) {
+        let count_params = self.app.count_params;
+        let count_obs = self.app.count_obs;
+        assert!(count_obs <= count_params);
+        assert!(buf.len() >= nrows * count_params);
+
+        let mut staging = vec![0.0; INPLACE_BLOCK * count_params];
+
+        for start in (0..nrows).step_by(INPLACE_BLOCK) {
+            let m = INPLACE_BLOCK.min(nrows - start);
+            let args = &mut staging[..m * count_params];
+            args.copy_from_slice(&buf[start * count_params..(start + m) * count_params]);
+            let outs = &mut buf[start * count_obs..(start + m) * count_obs];
+            self.run(args, outs, m);
+        }
+    }
+
+    /// Evaluates `nrows` rows, where the first `nvarying` parameters change per
+    /// row (`varying` is a row-major `nrows x nvarying` matrix) and the remaining
+    /// parameters are broadcast to all rows from `constants` (one value per
+    /// parameter). Returns the row-major `nrows x count_obs` outputs.
+    pub fn evaluate_matrix_broadcast(
+        &self,
+        varying: &[f64],
+        nvarying: usize,
+        constants: &[f64],
+        nrows: usize,
+    ) -> Vec<f64> {
+        let count_params = self.app.count_params;
+        assert_eq!(nvarying + constants.len(), count_params);
+        assert!(varying.len() >= nrows * nvarying);
+
+        let mut args = Vec::with_capacity(nrows * count_params);
+
+        for i in 0..nrows {
+            args.extend_from_slice(&varying[i * nvarying..(i + 1) * nvarying]);
+            args.extend_from_slice(constants);
+        }
+
+        let mut outs = vec![0.0; nrows * self.app.count_obs];
+        self.run(&args, &mut outs, nrows);
+        outs
+    }
+
+    /// Evaluates the kernel over the columns of `args`, i.e., `args` is a
+    /// `count_params x N` matrix with one evaluation point per column. Because
+    /// nalgebra stores matrices in column-major order, each column is already a
+    /// contiguous row of parameters and no copy is needed. The result is a
+    /// `count_obs x N` matrix with one output column per evaluation point.
+    #[cfg(feature = "nalgebra")]
+    pub fn evaluate_nalgebra(&self, args: &DMatrix<f64>) -> DMatrix<f64> {
+        assert_eq!(args.nrows(), self.app.count_params);
+        let n = args.ncols();
+        let mut outs = DMatrix::<f64>::zeros(self.app.count_obs, n);
+        self.run(args.as_slice(), outs.as_mut_slice(), n);
+        outs
+    }
+
+    /// Evaluates the rows of a columnar batch (e.g., the `f64` columns of an
+    /// Arrow record batch or a Polars data frame), where `columns[j]` holds
+    /// parameter `j` of every row. The rows are gathered block by block into a
+    /// small staging buffer instead of a row-major copy of the whole batch.
+    /// The outputs are written row by row.
+    pub fn evaluate_columns(&self, columns: &[&[f64]], outs: &mut [f64]) {
+        let count_params = self.app.count_params;
+        let count_obs = self.app.count_obs;
+        assert_eq!(columns.len(), count_params, "one column per parameter");
+
+        let nrows = columns[0].len();
+        assert!(
+            columns.iter().all(|c| c.len() == nrows),
+            "the columns must have the same length"
+        );
+        assert!(outs.len() >= nrows * count_obs);
+
+        let mut staging = Vec::with_capacity(INPLACE_BLOCK * count_params);
+
+        for start in (0..nrows).step_by(INPLACE_BLOCK) {
+            let m = (nrows - start).min(INPLACE_BLOCK);
+            staging.clear();
+
+            for i in start..start + m {
+                staging.extend(columns.iter().map(|c| c[i]));
+            }
+
+            self.run(
+                &staging,
+                &mut outs[start * count_obs..(start + m) * count_obs],
+                m,
+            );
+        }
+    }
+
+    pub fn is_complex(&self) -> bool {
+        false
+    }
+
+    /// Writes the scalar machine code to `path` as an ELF relocatable object
+    /// with a single global function symbol, `symbol`, at the kernel entry
+    /// point, to be linked statically into another binary. The kernel uses the
+    /// symjit calling convention. Kernels that call builtins (e.g., `sin`) or
+    /// external functions are rejected with an error, since their calls go
+    /// through absolute addresses that the object does not relocate.
+    pub fn write_object(&self, path: &str, symbol: &str) -> Result<()> {
+        object::write_object(&self.app, path, symbol)
+    }
+
+    /// The instruction-set extensions that the generated code uses. `load`
+    /// checks them against the current CPU.
+    pub fn required_features(&self) -> Vec<CpuFeature> {
+        cpu::required_features(&self.app)
+    }
+
+    /// Writes the runner to `file` with a versioned header (see `load`).
+    pub fn save(&self, file: &str) -> Result<()> {
+        save_app(&self.app, file, false, Vec::new())
+    }
+
+    /// Loads a runner written by `save`. Files of another format version, or
+    /// holding a complex kernel, are rejected with an error.
+    pub fn load(file: &str, config: &Config) -> Result<Self> {
+        let (app, _) = load_app(file, config, false)?;
+        let app = checked_shape(app)?;
+        cpu::check_features(&cpu::required_features(&app))?;
+        Ok(Self::new(
+            app,
+            config.flush_denormals(),
+            config.instrument(),
+        ))
+    }
+
+    /// Note that the `Applet` does not keep the `flush_denormals` and
+    /// `instrument` options.
+    pub fn seal(mut self) -> Result<Applet> {
+        self.app.use_threads |= self.threaded;
+        self.app.seal()
+    }
+
+    /// Converts the runner into a `ThreadedRealRunner` that splits large
+    /// batches across threads, keeping the `flush_denormals` option and the
+    /// counters of `Config.set_instrument(true)`.
+    pub fn into_threaded(self) -> Result<ThreadedRealRunner> {
+        let mut runner = ThreadedRealRunner::from_application(self.app, self.flush_denormals)?;
+        runner.post = self.post;
+        runner.counters = self.counters;
+        Ok(runner)
+    }
+}
+
+/************************ CompiledComplexRunner ***************************/
+
+pub struct CompiledComplexRunner {
+    pub app: Application,
+    flush_denormals: bool,
+    threaded: bool,
+    layout: ComplexLayout,
+}
+
+impl CompiledComplexRunner {
+    pub fn compile(ev: &ExpressionEvaluator<Complex<f64>>, config: Config) -> Result<Self> {
+        Self::compile_with_funcs(ev, config, 0)
+    }
+
+    pub fn compile_with_funcs(
+        ev: &ExpressionEvaluator<Complex<f64>>,
+        mut config: Config,
+        num_params: usize,
+    ) -> Result<Self> {
+        config.set_complex(true);
+        let flush_denormals = config.flush_denormals();
+        let layout = config.complex_layout();
+        let app = compile_checked(ev, config, num_params)?;
+        Ok(Self::new(app, flush_denormals, layout))
+    }
+
+    pub fn compile_string(model: String, config: Config) -> Result<Self> {
+        Self::compile_string_with_funcs(model, config, 0)
+    }
+
+    pub fn compile_string_with_funcs(
+        model: String,
+        mut config: Config,
+        num_params: usize,
+    ) -> Result<Self> {
+        config.set_complex(true);
+        config.set_simd(true);
+        let flush_denormals = config.flush_denormals();
+        let layout = config.complex_layout();
+        let app = checked_shape(compile_string(model, config, num_params)?)?;
+        Ok(Self::new(app, flush_denormals, layout))
+    }
+
+    /// Makes a runner and resolves the `Auto` layout by timing both layouts.
+    fn new(mut app: Application, flush_denormals: bool, layout: ComplexLayout) -> Self {
+        let threaded = take_threads(&mut app, flush_denormals);
+
+        let mut runner = CompiledComplexRunner {
+            app,
+            flush_denormals,
+            threaded,
+            layout: ComplexLayout::Interleaved,
+        };
+
+        runner.layout = match layout {
+            ComplexLayout::Planar if cfg!(target_arch = "x86_64") => ComplexLayout::Planar,
+            ComplexLayout::Auto if cfg!(target_arch = "x86_64") => runner.pick_layout(),
+            _ => ComplexLayout::Interleaved,
+        };
+
+        runner
+    }
+
+    /// Times `evaluate_split` on a few rows with each layout and returns the
+    /// faster one.
+    fn pick_layout(&mut self) -> ComplexLayout {
+        const ROWS: usize = 64;
+        const REPEATS: usize = 5;
+
+        let cp = self.app.count_params / 2;
+        let co = self.app.count_obs / 2;
+        let re = vec![0.5; ROWS * cp];
+        let im = vec![0.25; ROWS * cp];
+        let mut out_re = vec![0.0; ROWS * co];
+        let mut out_im = vec![0.0; ROWS * co];
+
+        let mut time = |layout: ComplexLayout| -> Duration {
+            self.layout = layout;
+
+            (0..REPEATS)
+                .map(|_| {
+                    let start = Instant::now();
+                    self.evaluate_split(&re, &im, &mut out_re, &mut out_im);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+
+        if time(ComplexLayout::Planar) < time(ComplexLayout::Interleaved) {
+            ComplexLayout::Planar
+        } else {
+            ComplexLayout::Interleaved
+        }
+    }
+
+    /// The layout used by `evaluate_split` (`Interleaved` or `Planar`; `Auto`
+    /// is resolved at compile time).
+    pub fn complex_layout(&self) -> ComplexLayout {
+        self.layout
+    }
+
+    /// Evaluates `n` rows, with denormals flushed to zero if requested.
+    fn run<T: Element>(&self, args: &[T], outs: &mut [T], n: usize) {
+        let shape = (n, self.app.count_params / 2, self.app.count_obs / 2);
+        evaluate_flushed(
+            &self.app,
+            args,
+            outs,
+            shape,
+            self.flush_denormals,
+            self.threaded,
+        );
+    }
+
+    /// `args` holds the parameters row by row. Note that `count_params` counts
+    /// real slots, two per complex parameter, so `2 * args.len()` must be an exact
+    /// multiple of it; otherwise this function panics instead of processing a
+    /// truncated batch.
+    pub fn evaluate<T>(&self, args: &[T], outs: &mut [T])
+    where
+        T: Element,
+    {
+        let n = checked_rows(2 * args.len(), self.app.count_params);
+        assert!(2 * outs.len() / self.app.count_obs >= n);
+        self.run(args, outs, n);
+    }
+
+    /// Evaluates the rows of `z` and writes the outputs at `z` to `outs_z` and
+    /// those at the conjugate parameters to `outs_conj`. The kernel must have
+    /// real coefficients (and real-valued external functions, if any), so that
+    /// `f(conj(z)) == conj(f(z))`; the kernel then runs only once and the
+    /// outputs at the conjugates are the conjugates of `outs_z`. For other
+    /// kernels, evaluate the conjugates separately with `evaluate`.
+    pub fn evaluate_conjugate_pairs(
+        &self,
+        z: &[Complex<f64>],
+        outs_z: &mut [Complex<f64>],
+        outs_conj: &mut [Complex<f64>],
+    ) {
+        let n = checked_rows(2 * z.len(), self.app.count_params);
+        let m = n * self.app.count_obs / 2;
+        assert!(outs_z.len() >= m && outs_conj.len() >= m);
+
+        self.run(z, &mut outs_z[..m], n);
+
+        for (w, v) in outs_conj.iter_mut().zip(&outs_z[..m]) {
+            *w = v.conj();
+        }
+    }
+
+    /// A layout adapter for data stored as `Complex<f64x2>` (two rows per
+    /// vector, e.g., from 128-bit code). It does not change the generated
+    /// code, which stays 4 lanes wide on x86-64: pairs of `f64x2` rows are
+    /// packed into `f64x4` rows (with a zero row after an odd last row),
+    /// evaluated, and unpacked afterward.
+    #[cfg(target_arch = "x86_64")]
+    pub fn evaluate_f64x2_layout(
+        &self,
+        args: &[Complex<f64x2>],
+        outs: &mut [Complex<f64x2>],
+    ) -> Result<()> {
+        let cp = self.app.count_params / 2;
+        let co = self.app.count_obs / 2;
+
+        if cp == 0 || !args.len().is_multiple_of(cp) {
+            return Err(anyhow!(
+                "args has {} elements, which is not a multiple of {} parameters",
+                args.len(),
+                cp
+            ));
+        }
+
+        let n = args.len() / cp;
+
+        if outs.len() < n * co {
+            return Err(anyhow!(
+                "outs has {} elements, but {} rows need {}",
+                outs.len(),
+                n,
+                n * co
+            ));
+        }
+
+        let zero = Complex::new(f64x2::default(), f64x2::default());
+        let join = |lo: f64x2, hi: f64x2| {
+            let (lo, hi) = (lo.to_array(), hi.to_array());
+            f64x4::from([lo[0], lo[1], hi[0], hi[1]])
+        };
+
+        let m = n.div_ceil(2);
+        let packed: Vec<Complex<f64x4>> = (0..m * cp)
+            .map(|k| {
+                let (p, j) = (k / cp, k % cp);
+                let lo = args[2 * p * cp + j];
+                let hi = args.get((2 * p + 1) * cp + j).copied().unwrap_or(zero);
+                Complex::new(join(lo.re, hi.re), join(lo.im, hi.im))
+            })
+            .collect();
+
+        let mut results = vec![Complex::new(f64x4::default(), f64x4::default()); m * co];
+        self.run(&packed, &mut results, m);
+
+        let split = |v: f64x4| {
+            let v = v.to_array();
+            (f64x2::from([v[0], v[1]]), f64x2::from([v[2], v[3]]))
+        };
+
+        for (k, z) in results.iter().enumerate() {
+            let (p, j) = (k / co, k % co);
+            let (re_lo, re_hi) = split(z.re);
+            let (im_lo, im_hi) = split(z.im);
+            outs[2 * p * co + j] = Complex::new(re_lo, im_lo);
+
+            if 2 * p + 1 < n {
+                outs[(2 * p + 1) * co + j] = Complex::new(re_hi, im_hi);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates complex rows stored as split arrays (structure of arrays):
+    /// `re` and `im` hold the real and imaginary parts of the row-major
+    /// parameters, and `out_re` and `out_im` receive those of the outputs.
+    pub fn evaluate_split(&self, re: &[f64], im: &[f64], out_re: &mut [f64], out_im: &mut [f64]) {
+        let cp = self.app.count_params / 2;
+        let co = self.app.count_obs / 2;
 
-impl CompiledRealRunner {
-    pub fn compile(ev: &ExpressionEvaluator<f64>, config: Config) -> Result<Self> {
-        Self::compile_with_funcs(ev, config, 0)
+        if self.layout == ComplexLayout::Planar {
+            evaluate_split_planar(
+                cp,
+                co,
+                re,
+                im,
+                out_re,
+                out_im,
+                |args, outs, n| self.run(args, outs, n),
+                |args, outs, n| self.run(args, outs, n),
+            );
+        } else {
+            evaluate_split_blocks(cp, co, re, im, out_re, out_im, |args, outs, n| {
+                self.run(args, outs, n)
+            });
+        }
     }
 
-    pub fn compile_with_funcs(
-        ev: &ExpressionEvaluator<f64>,
-        mut config: Config,
-        num_params: usize,
-    ) -> Result<Self> {
-        config.set_complex(false);
-        let app = compile(&ev, config.clone(), num_params)?;
-        Ok(Self { app })
+    /// Runs the kernel once on zeroed inputs so that the code pages are mapped
+    /// and cached before the first timed call. It does not affect later outputs.
+    pub fn warmup(&self) {
+        let args = vec![Complex::<f64>::default(); WARMUP_ROWS * self.app.count_params / 2];
+        let mut outs = vec![Complex::<f64>::default(); WARMUP_ROWS * self.app.count_obs / 2];
+        self.evaluate(&args, &mut outs);
     }
 
-    pub fn compile_string(model: String, config: Config) -> Result<Self> {
-        Self::compile_string_with_funcs(model, config, 0)
+    pub fn is_complex(&self) -> bool {
+        true
     }
 
-    pub fn compile_string_with_funcs(
-        model: String,
-        mut config: Config,
-        num_params: usize,
-    ) -> Result<Self> {
-        config.set_complex(false);
-        config.set_simd(true);
-        let app = compile_string(model, config, num_params)?;
-        Ok(Self { app })
+    /// Like `CompiledRealRunner::write_object`.
+    pub fn write_object(&self, path: &str, symbol: &str) -> Result<()> {
+        object::write_object(&self.app, path, symbol)
     }
 
-    pub fn evaluate<T>(&self, args: &[T], outs: &mut [T])
-    where
-        T: Element,
-    {
-        let n = args.len() / self.app.count_params;
-        assert!(outs.len() / self.app.count_obs >= n);
-        self.app.evaluate_matrix(args, outs, n);
+    /// The instruction-set extensions that the generated code uses. `load`
+    /// checks them against the current CPU.
+    pub fn required_features(&self) -> Vec<CpuFeature> {
+        cpu::required_features(&self.app)
     }
 
     pub fn save(&self, file: &str) -> Result<()> {
-        let mut fs = std::fs::File::create(file)?;
-        self.app.save(&mut fs)
+        save_app(&self.app, file, true, Vec::new())
     }
 
     pub fn load(file: &str, config: &Config) -> Result<Self> {
-        let mut fs = std::fs::File::open(file)?;
-        let app = Application::load(&mut fs, config)?;
-        Ok(Self { app })
+        let (app, _) = load_app(file, config, true)?;
+        let app = checked_shape(app)?;
+        cpu::check_features(&cpu::required_features(&app))?;
+        Ok(Self::new(
+            app,
+            config.flush_denormals(),
+            config.complex_layout(),
+        ))
     }
 
-    pub fn seal(self) -> Result<Applet> {
+    /// Note that the `Applet` does not keep the `flush_denormals` option.
+    pub fn seal(mut self) -> Result<Applet> {
+        self.app.use_threads |= self.threaded;
         self.app.seal()
     }
 }
 
-/************************ CompiledComplexRunner ***************************/
+/********************* CompiledPolarRunner ************************/
 
-pub struct CompiledComplexRunner {
-    pub app: Application,
+/// A complex kernel whose outputs are returned in polar form.
+pub struct CompiledPolarRunner {
+    runner: CompiledComplexRunner,
 }
 
-impl CompiledComplexRunner {
+impl CompiledPolarRunner {
     pub fn compile(ev: &ExpressionEvaluator<Complex<f64>>, config: Config) -> Result<Self> {
         Self::compile_with_funcs(ev, config, 0)
     }
 
     pub fn compile_with_funcs(
         ev: &ExpressionEvaluator<Complex<f64>>,
-        mut config: Config,
+        config: Config,
         num_params: usize,
     ) -> Result<Self> {
-        config.set_complex(true);
-        let app = compile(&ev, config, num_params)?;
-        Ok(CompiledComplexRunner { app })
+        let runner = CompiledComplexRunner::compile_with_funcs(ev, config, num_params)?;
+        Ok(Self { runner })
     }
 
-    pub fn compile_string(model: String, config: Config) -> Result<Self> {
-        Self::compile_string_with_funcs(model, config, 0)
+    /// Evaluates the complex rows of `args` and writes each output `z` as the
+    /// interleaved pair `[|z|, arg(z)]`. The phase is in `(-pi, pi]` and is 0
+    /// for `z == 0`.
+    pub fn evaluate(&self, args: &[Complex<f64>], outs: &mut [f64]) {
+        let app = &self.runner.app;
+        let n = checked_rows(2 * args.len(), app.count_params);
+        assert!(outs.len() >= n * app.count_obs);
+
+        let mut vals = vec![Complex::<f64>::default(); n * app.count_obs / 2];
+        self.runner.run(args, &mut vals, n);
+
+        for (p, z) in outs.chunks_exact_mut(2).zip(vals) {
+            let phase = if z.re == 0.0 && z.im == 0.0 {
+                0.0
+            } else {
+                let phase = z.im.atan2(z.re);
+                // atan2 returns -pi for a negative real part and an imaginary part of -0
+                if phase == -std::f64::consts::PI {
+                    std::f64::consts::PI
+                } else {
+                    phase
+                }
+            };
+
+            p[0] = z.norm();
+            p[1] = phase;
+        }
     }
 
-    pub fn compile_string_with_funcs(
-        model: String,
-        mut config: Config,
-        num_params: usize,
-    ) -> Result<Self> {
-        config.set_complex(true);
-        config.set_simd(true);
-        let app = compile_string(model, config, num_params)?;
-        Ok(CompiledComplexRunner { app })
+    pub fn is_complex(&self) -> bool {
+        true
     }
+}
 
-    pub fn evaluate<T>(&self, args: &[T], outs: &mut [T])
-    where
-        T: Element,
-    {
-        let n = (2 * args.len()) / self.app.count_params;
-        assert!(2 * outs.len() / self.app.count_obs >= n);
-        self.app.evaluate_matrix(args, outs, n);
+/********************* CompiledPhasorRunner ***********************/
+
+/// A complex kernel whose outputs are normalized to unit magnitude, for
+/// computations where only the phase matters.
+pub struct CompiledPhasorRunner {
+    runner: CompiledComplexRunner,
+}
+
+impl CompiledPhasorRunner {
+    pub fn compile(ev: &ExpressionEvaluator<Complex<f64>>, config: Config) -> Result<Self> {
+        Self::compile_with_funcs(ev, config, 0)
     }
 
-    pub fn save(&self, file: &str) -> Result<()> {
-        let mut fs = std::fs::File::create(file)?;
-        self.app.save(&mut fs)
+    pub fn compile_with_funcs(
+        ev: &ExpressionEvaluator<Complex<f64>>,
+        config: Config,
+        num_params: usize,
+    ) -> Result<Self> {
+        let runner = CompiledComplexRunner::compile_with_funcs(ev, config, num_params)?;
+        Ok(Self { runner })
     }
 
-    pub fn load(file: &str, config: &Config) -> Result<Self> {
-        let mut fs = std::fs::File::open(file)?;
-        let app = Application::load(&mut fs, config)?;
-        Ok(Self { app })
+    /// Evaluates the complex rows of `args` and writes each output `z` as the
+    /// phasor `z / |z|`, or `1 + 0i` for `z == 0`. Non-finite outputs give NaN.
+    pub fn evaluate(&self, args: &[Complex<f64>], outs: &mut [Complex<f64>]) {
+        let app = &self.runner.app;
+        let n = checked_rows(2 * args.len(), app.count_params);
+        let m = n * app.count_obs / 2;
+        assert!(outs.len() >= m);
+
+        self.runner.run(args, &mut outs[..m], n);
+
+        for z in outs[..m].iter_mut() {
+            let r = z.norm();
+
+            *z = if r == 0.0 {
+                Complex::new(1.0, 0.0)
+            } else {
+                Complex::new(z.re / r, z.im / r)
+            };
+        }
     }
 
-    pub fn seal(self) -> Result<Applet> {
-        self.app.seal()
+    pub fn is_complex(&self) -> bool {
+        true
     }
 }
 
@@ -148,6 +1738,8 @@ impl CompiledComplexRunner {
 
 pub struct InterpretedRealRunner {
     app: Application,
+    #[cfg(feature = "arbitrary-precision")]
+    precise: Option<(Program, u32)>,
 }
 
 impl InterpretedRealRunner {
@@ -160,11 +1752,47 @@ impl InterpretedRealRunner {
         config: Config,
         num_params: usize,
     ) -> Result<Self> {
-        let mut c = Config::from_name("bytecode", config.opt)?;
+        let mut c = config.bytecode()?;
         c.set_complex(false);
         c.set_simd(false);
-        let app = compile(&ev, c, num_params)?;
-        Ok(Self { app })
+        let app = compile_checked(ev, c, num_params)?;
+        Ok(Self::from_application(app))
+    }
+
+    /// Compiles a reference runner that evaluates in `bits`-bit floating
+    /// point (e.g., 128 or 256) and rounds the outputs to f64. It is meant as
+    /// an oracle to separate the rounding error of f64 from the error of an
+    /// algorithm, not for performance. Control flow and external functions
+    /// are not supported. `save` writes the f64 (bytecode) model.
+    #[cfg(feature = "arbitrary-precision")]
+    pub fn compile_with_precision(
+        ev: &ExpressionEvaluator<f64>,
+        config: Config,
+        bits: u32,
+    ) -> Result<Self> {
+        let prog = Program::new(ev)?;
+        let mut runner = Self::compile(ev, config)?;
+        runner.precise = Some((prog, bits));
+        Ok(runner)
+    }
+
+    /// The precision in bits of a runner made by `compile_with_precision`, or
+    /// 53 (f64).
+    pub fn precision(&self) -> u32 {
+        #[cfg(feature = "arbitrary-precision")]
+        if let Some((_, bits)) = &self.precise {
+            return *bits;
+        }
+
+        f64::MANTISSA_DIGITS
+    }
+
+    fn from_application(app: Application) -> Self {
+        Self {
+            app,
+            #[cfg(feature = "arbitrary-precision")]
+            precise: None,
+        }
     }
 
     pub fn compile_string(model: String, config: Config) -> Result<Self> {
@@ -176,28 +1804,48 @@ impl InterpretedRealRunner {
         config: Config,
         num_params: usize,
     ) -> Result<Self> {
-        let mut c = Config::from_name("bytecode", config.opt)?;
+        let mut c = config.bytecode()?;
         c.set_complex(false);
         c.set_simd(false);
-        let app = compile_string(model, c, num_params)?;
-        Ok(Self { app })
+        let app = checked_shape(compile_string(model, c, num_params)?)?;
+        Ok(Self::from_application(app))
     }
 
     pub fn evaluate(&mut self, args: &[f64], outs: &mut [f64]) {
         let n = args.len() / self.app.count_params;
         assert!(outs.len() / self.app.count_obs >= n);
+
+        #[cfg(feature = "arbitrary-precision")]
+        if let Some((prog, bits)) = &self.precise {
+            let (cp, co) = (prog.count_params, prog.count_obs);
+
+            for (a, o) in args.chunks_exact(cp).zip(outs.chunks_exact_mut(co)) {
+                prog.run_precise(a, o, *bits);
+            }
+
+            return;
+        }
+
         self.app.interpret_matrix(args, outs, n);
     }
 
+    pub fn warmup(&mut self) {
+        let args = vec![0.0; self.app.count_params];
+        let mut outs = vec![0.0; self.app.count_obs];
+        self.evaluate(&args, &mut outs);
+    }
+
+    pub fn is_complex(&self) -> bool {
+        false
+    }
+
     pub fn save(&self, file: &str) -> Result<()> {
-        let mut fs = std::fs::File::create(file)?;
-        self.app.save(&mut fs)
+        save_app(&self.app, file, false, Vec::new())
     }
 
     pub fn load(file: &str, config: &Config) -> Result<Self> {
-        let mut fs = std::fs::File::open(file)?;
-        let app = Application::load(&mut fs, config)?;
-        Ok(Self { app })
+        let (app, _) = load_app(file, config, false)?;
+        Ok(Self::from_application(checked_shape(app)?))
     }
 }
 
@@ -217,10 +1865,10 @@ impl InterpretedComplexRunner {
         config: Config,
         num_params: usize,
     ) -> Result<Self> {
-        let mut c = Config::from_name("bytecode", config.opt)?;
+        let mut c = config.bytecode()?;
         c.set_complex(true);
         c.set_simd(false);
-        let app = compile(&ev, c, num_params)?;
+        let app = compile_checked(ev, c, num_params)?;
         Ok(Self { app })
     }
 
@@ -233,15 +1881,16 @@ impl InterpretedComplexRunner {
         config: Config,
         num_params: usize,
     ) -> Result<Self> {
-        let mut c = Config::from_name("bytecode", config.opt)?;
+        let mut c = config.bytecode()?;
         c.set_complex(true);
         c.set_simd(false);
-        let app = compile_string(model, c, num_params)?;
+        let app = checked_shape(compile_string(model, c, num_params)?)?;
         Ok(Self { app })
     }
 
+    /// See `CompiledComplexRunner::evaluate` for the layout of `args`.
     pub fn evaluate(&mut self, args: &[Complex<f64>], outs: &mut [Complex<f64>]) {
-        let n = (2 * args.len()) / self.app.count_params;
+        let n = checked_rows(2 * args.len(), self.app.count_params);
         assert!((2 * outs.len()) / self.app.count_obs >= n);
 
         let args = flatten_vec(args);
@@ -250,14 +1899,629 @@ impl InterpretedComplexRunner {
         self.app.interpret_matrix(args, outs, n);
     }
 
+    /// See `CompiledComplexRunner::evaluate_split`.
+    pub fn evaluate_split(
+        &mut self,
+        re: &[f64],
+        im: &[f64],
+        out_re: &mut [f64],
+        out_im: &mut [f64],
+    ) {
+        let cp = self.app.count_params / 2;
+        let co = self.app.count_obs / 2;
+        let app = &mut self.app;
+        evaluate_split_blocks(cp, co, re, im, out_re, out_im, |args, outs, n| {
+            app.interpret_matrix(flatten_vec(args), flatten_vec_mut(outs), n)
+        });
+    }
+
+    pub fn warmup(&mut self) {
+        let args = vec![Complex::<f64>::default(); self.app.count_params / 2];
+        let mut outs = vec![Complex::<f64>::default(); self.app.count_obs / 2];
+        self.evaluate(&args, &mut outs);
+    }
+
+    pub fn is_complex(&self) -> bool {
+        true
+    }
+
     pub fn save(&self, file: &str) -> Result<()> {
-        let mut fs = std::fs::File::create(file)?;
-        self.app.save(&mut fs)
+        save_app(&self.app, file, true, Vec::new())
     }
 
     pub fn load(file: &str, config: &Config) -> Result<Self> {
-        let mut fs = std::fs::File::open(file)?;
-        let app = Application::load(&mut fs, config)?;
-        Ok(Self { app })
+        let (app, _) = load_app(file, config, true)?;
+        Ok(Self {
+            app: checked_shape(app)?,
+        })
+    }
+}
+
+/********************* InterpretedSimdRealRunner ************************/
+
+/// Interprets a real kernel over `f64x4` or `f64x2` lanes without generating
+/// any machine code, for hosts where the JIT is unavailable. External functions
+/// and control flow are not supported.
+pub struct InterpretedSimdRealRunner {
+    prog: Program,
+}
+
+impl InterpretedSimdRealRunner {
+    pub fn compile(ev: &ExpressionEvaluator<f64>) -> Result<Self> {
+        let prog = Program::new(ev)?;
+        Ok(Self { prog })
+    }
+
+    /// Like `compile`, but with `FpModel::Fast`, `exp`, `log`, `sin`, and
+    /// `cos` are computed by the vectorized approximations of `vmath`, which
+    /// keep all the lanes in vector registers, instead of lane by lane.
+    /// `Accuracy::Reduced` selects the cheaper, lower-degree approximations.
+    pub fn compile_with_config(ev: &ExpressionEvaluator<f64>, config: &Config) -> Result<Self> {
+        let mut prog = Program::new(ev)?;
+        prog.fast = config.fp_model() == FpModel::Fast;
+        prog.reduced = config.transcendental_accuracy() == Accuracy::Reduced;
+        Ok(Self { prog })
+    }
+
+    pub fn evaluate<T: SimdElem>(&self, args: &[T], outs: &mut [T]) {
+        let n = args.len() / self.prog.count_params;
+        assert!(outs.len() / self.prog.count_obs >= n);
+
+        let mut temps: Vec<T> = Vec::new();
+
+        for i in 0..n {
+            let a = &args[i * self.prog.count_params..(i + 1) * self.prog.count_params];
+            let o = &mut outs[i * self.prog.count_obs..(i + 1) * self.prog.count_obs];
+            self.prog.run(a, o, &mut temps);
+        }
+    }
+
+    pub fn is_complex(&self) -> bool {
+        false
+    }
+}
+
+/********************* CompiledBoolRunner ************************/
+
+/// A real kernel whose outputs are read as booleans, e.g., the comparison
+/// builtins (`lt`, `leq`, `gt`, and `geq`) or their combinations. An output is
+/// true if any of its bits is set, which includes the all-ones mask (a NaN)
+/// returned by the comparisons, but not `-0.0`.
+pub struct CompiledBoolRunner {
+    runner: CompiledRealRunner,
+}
+
+fn truthy(x: f64) -> bool {
+    x.to_bits() & !(1 << 63) != 0
+}
+
+impl CompiledBoolRunner {
+    pub fn compile(ev: &ExpressionEvaluator<f64>, config: Config) -> Result<Self> {
+        Self::compile_with_funcs(ev, config, 0)
+    }
+
+    pub fn compile_with_funcs(
+        ev: &ExpressionEvaluator<f64>,
+        config: Config,
+        num_params: usize,
+    ) -> Result<Self> {
+        let runner = CompiledRealRunner::compile_with_funcs(ev, config, num_params)?;
+        Ok(Self { runner })
+    }
+
+    /// Evaluates the rows of `args` and writes one `bool` per output.
+    pub fn evaluate(&self, args: &[f64], outs: &mut [bool]) {
+        let app = &self.runner.app;
+        let n = args.len() / app.count_params;
+        assert!(outs.len() / app.count_obs >= n);
+
+        let mut vals = vec![0.0; n * app.count_obs];
+        self.runner.run(args, &mut vals, n);
+
+        for (b, x) in outs.iter_mut().zip(vals) {
+            *b = truthy(x);
+        }
+    }
+
+    /// Evaluates `nrows` rows and returns the outputs packed into bitmasks,
+    /// one column per output: bit `i % 64` of word `j * nrows.div_ceil(64) + i / 64`
+    /// is output `j` of row `i`.
+    pub fn evaluate_bitmask(&self, args: &[f64], nrows: usize) -> Vec<u64> {
+        let app = &self.runner.app;
+        let count_obs = app.count_obs;
+        assert!(args.len() >= nrows * app.count_params);
+
+        let mut vals = vec![0.0; nrows * count_obs];
+        self.runner
+            .run(&args[..nrows * app.count_params], &mut vals, nrows);
+
+        let words = nrows.div_ceil(64);
+        let mut masks = vec![0u64; count_obs * words];
+
+        for i in 0..nrows {
+            for j in 0..count_obs {
+                if truthy(vals[i * count_obs + j]) {
+                    masks[j * words + i / 64] |= 1 << (i % 64);
+                }
+            }
+        }
+
+        masks
+    }
+
+    /// Evaluates SIMD rows and writes one lane mask per output, with bit `k`
+    /// set if lane `k` is true (like `movemask` on a comparison result).
+    pub fn evaluate_lanes(&self, args: &[f64x4], outs: &mut [u8]) {
+        let app = &self.runner.app;
+        let n = checked_rows(args.len(), app.count_params);
+        assert!(outs.len() >= n * app.count_obs);
+
+        let mut vals = vec![f64x4::default(); n * app.count_obs];
+        self.runner.run(args, &mut vals, n);
+
+        for (m, v) in outs.iter_mut().zip(vals) {
+            *m = v
+                .to_array()
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| truthy(**x))
+                .fold(0, |m, (k, _)| m | (1 << k));
+        }
+    }
+}
+
+/********************* RationalRunner ************************/
+
+/// A real kernel with two outputs, a numerator `P` and a denominator `Q`,
+/// that returns `P / Q` per row (see `compile_rational`). `P` and `Q` share
+/// one kernel, so their common subexpressions are computed once and the row
+/// is divided only once. Rows with `Q == 0` return NaN.
+///
+/// The ratio is not simplified algebraically: near a removable singularity
+/// (e.g., `(x^2 - 1) / (x - 1)` near `x = 1`), both `P` and `Q` are small and
+/// the quotient loses precision to cancellation, and at the singularity it
+/// is NaN.
+pub struct RationalRunner {
+    runner: CompiledRealRunner,
+}
+
+impl RationalRunner {
+    /// Compiles `ev`, whose outputs must be the numerator and the denominator.
+    pub fn compile(ev: &ExpressionEvaluator<f64>, config: Config) -> Result<Self> {
+        let runner = CompiledRealRunner::compile(ev, config)?;
+
+        if runner.app.count_obs != 2 {
+            return Err(anyhow!(
+                "a rational kernel needs 2 outputs (numerator and denominator), not {}",
+                runner.app.count_obs
+            ));
+        }
+
+        Ok(Self { runner })
+    }
+
+    pub fn count_params(&self) -> usize {
+        self.runner.app.count_params
+    }
+
+    /// Evaluates the rows of `args` and writes one ratio per row to `outs`.
+    pub fn evaluate(&self, args: &[f64], outs: &mut [f64]) {
+        let count_params = self.runner.app.count_params;
+        let n = checked_rows(args.len(), count_params);
+        assert!(outs.len() >= n);
+
+        let mut pairs = vec![0.0; 2 * INPLACE_BLOCK];
+
+        for start in (0..n).step_by(INPLACE_BLOCK) {
+            let m = (n - start).min(INPLACE_BLOCK);
+            self.runner.run(
+                &args[start * count_params..(start + m) * count_params],
+                &mut pairs[..2 * m],
+                m,
+            );
+
+            for (y, pq) in outs[start..start + m].iter_mut().zip(pairs.chunks(2)) {
+                *y = if pq[1] == 0.0 {
+                    f64::NAN
+                } else {
+                    pq[0] / pq[1]
+                };
+            }
+        }
+    }
+
+    pub fn is_complex(&self) -> bool {
+        false
+    }
+}
+
+/********************* ThreadedRealRunner ************************/
+
+/// A sealed real kernel that splits large batches into blocks of rows and
+/// evaluates them on several threads. Batches smaller than two blocks run on
+/// the calling thread.
+pub struct ThreadedRealRunner {
+    applet: Applet,
+    count_params: usize,
+    count_obs: usize,
+    num_threads: usize,
+    pin_threads: bool,
+    sequential: bool,
+    flush_denormals: bool,
+    post: Option<PostFn>,
+    counters: Option<Counters>,
+    used_threads: AtomicBool,
+    scratch: Mutex<Vec<Vec<f64>>>,
+}
+
+impl ThreadedRealRunner {
+    /// Compiles `ev` like `CompiledRealRunner::compile`. If the kernel calls
+    /// external functions and `config` does not assume them to be pure
+    /// (`Config::set_assume_pure(false)`), the runner always evaluates on the
+    /// calling thread.
+    pub fn compile(ev: &ExpressionEvaluator<f64>, config: Config) -> Result<Self> {
+        let pin_threads = config.pin_threads();
+        let (instructions, _, _) = ev.export_instructions();
+        let sequential = crate::is_impure(&instructions, &config);
+        let mut runner = CompiledRealRunner::compile(ev, config)?.into_threaded()?;
+        runner.pin_threads = pin_threads;
+
+        if sequential {
+            runner.sequential = true;
+            runner.num_threads = 1;
+        }
+
+        Ok(runner)
+    }
+
+    fn from_application(mut app: Application, flush_denormals: bool) -> Result<Self> {
+        let count_params = app.count_params;
+        let count_obs = app.count_obs;
+        // the blocks already run on separate threads, each of which flushes
+        // on its own; symjit's threads would not
+        take_threads(&mut app, flush_denormals);
+
+        Ok(Self {
+            applet: app.seal()?,
+            count_params,
+            count_obs,
+            num_threads: threads::resolve_threads(0),
+            pin_threads: false,
+            sequential: false,
+            flush_denormals,
+            post: None,
+            counters: None,
+            used_threads: AtomicBool::new(false),
+            scratch: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Evaluates `m` rows on the current thread, with denormals flushed to
+    /// zero if requested, and applies the `map_outputs` closure to them.
+    fn run(&self, args: &[f64], outs: &mut [f64], m: usize) {
+        let _guard = FlushDenormals::new(self.flush_denormals);
+        self.applet.evaluate_matrix(args, outs, m);
+
+        if let Some(post) = &self.post {
+            map_rows(post, outs, self.count_obs, m);
+        }
+
+        if let Some(counters) = &self.counters {
+            counters.record::<f64>(m);
+        }
+    }
+
+    /// Like `CompiledRealRunner::estimated_call_count`, summed over the
+    /// threads.
+    pub fn estimated_call_count(&self) -> Option<u64> {
+        self.counters.as_ref().map(|c| c.estimated_calls())
+    }
+
+    /// Like `CompiledRealRunner::last_call_timestamp`, for the last block of
+    /// rows to finish on any thread.
+    pub fn last_call_timestamp(&self) -> Option<u64> {
+        self.counters.as_ref().map(|c| c.last_timestamp())
+    }
+
+    /// Sets the maximum number of threads; 0 (the default) uses one thread per
+    /// available core. It has no effect on a kernel with impure external
+    /// functions, which runs on the calling thread.
+    pub fn set_num_threads(&mut self, num_threads: usize) {
+        if !self.sequential {
+            self.num_threads = threads::resolve_threads(num_threads);
+        }
+    }
+
+    pub fn evaluate(&self, args: &[f64], outs: &mut [f64]) {
+        let n = args.len() / self.count_params;
+        assert!(outs.len() / self.count_obs >= n);
+
+        let used = threads::for_each_chunk(
+            args,
+            self.count_params,
+            outs,
+            self.count_obs,
+            n,
+            self.num_threads,
+            self.pin_threads,
+            |a, o, m| self.run(a, o, m),
+        );
+
+        self.used_threads.store(used, Ordering::Relaxed);
+    }
+
+    /// Evaluates `nrows` rows like `evaluate`, but the threads check `token`
+    /// before each block of rows and stop once it is set (e.g., by a cancel
+    /// button on another thread). Returns `true` if all the rows were
+    /// evaluated and `false` if the evaluation was cancelled, in which case
+    /// the contents of `outs` are undefined.
+    pub fn evaluate_matrix_cancellable(
+        &self,
+        args: &[f64],
+        outs: &mut [f64],
+        nrows: usize,
+        token: &AtomicBool,
+    ) -> bool {
+        let (cp, co) = (self.count_params, self.count_obs);
+        assert!(args.len() >= nrows * cp && outs.len() >= nrows * co);
+
+        let cancelled = AtomicBool::new(false);
+
+        let used = threads::for_each_chunk(
+            args,
+            cp,
+            outs,
+            co,
+            nrows,
+            self.num_threads,
+            self.pin_threads,
+            |a, o, m| {
+                for s in (0..m).step_by(threads::BLOCK_ROWS) {
+                    if token.load(Ordering::Relaxed) {
+                        cancelled.store(true, Ordering::Relaxed);
+                        return;
+                    }
+
+                    let n = threads::BLOCK_ROWS.min(m - s);
+                    self.run(&a[s * cp..(s + n) * cp], &mut o[s * co..(s + n) * co], n);
+                }
+            },
+        );
+
+        self.used_threads.store(used, Ordering::Relaxed);
+        !cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Evaluates `nrows` rows of the row-major `args` on several threads and
+    /// writes the outputs in the given `layout`. With `ColumnMajor`, each
+    /// thread writes each output into its own contiguous segment of that
+    /// output's column, which suits downstream column-wise consumers (e.g.,
+    /// reductions over one output) better than the interleaved rows.
+    pub fn evaluate_matrix_layout(
+        &self,
+        args: &[f64],
+        outs: &mut [f64],
+        nrows: usize,
+        layout: OutputLayout,
+    ) {
+        assert!(args.len() >= nrows * self.count_params);
+        assert!(outs.len() >= nrows * self.count_obs);
+
+        let f = |a: &[f64], o: &mut [f64], m: usize| self.run(a, o, m);
+
+        let used = match layout {
+            OutputLayout::RowMajor => threads::for_each_chunk(
+                args,
+                self.count_params,
+                outs,
+                self.count_obs,
+                nrows,
+                self.num_threads,
+                self.pin_threads,
+                f,
+            ),
+            OutputLayout::ColumnMajor => threads::for_each_chunk_columns(
+                args,
+                self.count_params,
+                outs,
+                self.count_obs,
+                nrows,
+                self.num_threads,
+                self.pin_threads,
+                f,
+            ),
+        };
+
+        self.used_threads.store(used, Ordering::Relaxed);
+    }
+
+    /// Returns the sum of each output over the `nrows` rows of `args` without
+    /// materializing the whole output matrix. The rows are added in a fixed
+    /// tree order (pairwise within blocks of rows, then pairwise over the
+    /// blocks), so the result is bitwise identical for any number of threads.
+    pub fn evaluate_matrix_sum(&self, args: &[f64], nrows: usize) -> Vec<f64> {
+        assert!(args.len() >= nrows * self.count_params);
+
+        let mut scratch = self.scratch.lock().unwrap();
+
+        let (sums, used) = threads::sum_rows(
+            args,
+            self.count_params,
+            self.count_obs,
+            nrows,
+            self.num_threads,
+            self.pin_threads,
+            &mut scratch,
+            |a, o, m| self.run(a, o, m),
+        );
+
+        self.used_threads.store(used, Ordering::Relaxed);
+        sums
+    }
+
+    /// Evaluates the `nrows` rows of `args` and, instead of storing the
+    /// outputs, calls `bin_fn(outs, bins)` on the outputs of each row to
+    /// accumulate them into `bins` (e.g., to build a histogram). Each thread
+    /// accumulates into its own zeroed copy of `bins`, and the copies are added
+    /// to `bins` at the end, so the threads do not contend. The threads see
+    /// the rows in a different order than a serial loop, so floating point
+    /// sums may differ in the last bits.
+    pub fn evaluate_matrix_accumulate<B>(
+        &self,
+        args: &[f64],
+        nrows: usize,
+        bins: &mut [f64],
+        bin_fn: B,
+    ) where
+        B: Fn(&[f64], &mut [f64]) + Sync,
+    {
+        assert!(args.len() >= nrows * self.count_params);
+
+        let mut scratch = self.scratch.lock().unwrap();
+
+        let (local, used) = threads::accumulate_rows(
+            args,
+            self.count_params,
+            self.count_obs,
+            nrows,
+            bins.len(),
+            self.num_threads,
+            self.pin_threads,
+            &mut scratch,
+            |a, o, m| self.run(a, o, m),
+            bin_fn,
+        );
+
+        for (x, y) in bins.iter_mut().zip(local) {
+            *x += y;
+        }
+
+        self.used_threads.store(used, Ordering::Relaxed);
+    }
+
+    /// Returns the row of the first `nrows` rows of `args` with the smallest
+    /// first output and that output, found in one pass over the rows on
+    /// several threads without storing the outputs. Ties go to the lowest row,
+    /// and NaN outputs are skipped; the result is `None` if there are no rows
+    /// or all the outputs are NaN.
+    pub fn evaluate_matrix_argmin(&self, args: &[f64], nrows: usize) -> Option<(usize, f64)> {
+        self.extremum(args, nrows, f64::lt)
+    }
+
+    /// Like `evaluate_matrix_argmin`, but for the largest first output.
+    pub fn evaluate_matrix_argmax(&self, args: &[f64], nrows: usize) -> Option<(usize, f64)> {
+        self.extremum(args, nrows, f64::gt)
+    }
+
+    fn extremum(
+        &self,
+        args: &[f64],
+        nrows: usize,
+        less: fn(&f64, &f64) -> bool,
+    ) -> Option<(usize, f64)> {
+        assert!(args.len() >= nrows * self.count_params);
+
+        let mut scratch = self.scratch.lock().unwrap();
+
+        let (best, used) = threads::extremum_rows(
+            args,
+            self.count_params,
+            self.count_obs,
+            nrows,
+            self.num_threads,
+            self.pin_threads,
+            &mut scratch,
+            |a, o, m| self.run(a, o, m),
+            less,
+        );
+
+        self.used_threads.store(used, Ordering::Relaxed);
+        best
+    }
+
+    /// Pre-allocates the per-thread scratch buffers for batches of `nrows`
+    /// rows, so that later calls at that size (or smaller) do not allocate
+    /// them again. Calling it is optional; the buffers otherwise grow on
+    /// demand.
+    pub fn prepare(&mut self, nrows: usize) {
+        let workers = threads::workers_for(nrows, self.num_threads);
+        threads::reserve_scratch(self.scratch.get_mut().unwrap(), workers, self.count_obs);
+    }
+
+    /// The total capacity, in `f64`s, of the per-thread scratch buffers,
+    /// which `prepare` reserves up front and the reducing calls grow on demand.
+    pub fn scratch_capacity(&self) -> usize {
+        self.scratch
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|b| b.capacity())
+            .sum()
+    }
+
+    /// Reports whether the most recent `evaluate` or `evaluate_matrix_sum`
+    /// call was split across more than one thread.
+    pub fn last_evaluate_used_threads(&self) -> bool {
+        self.used_threads.load(Ordering::Relaxed)
+    }
+
+    pub fn is_complex(&self) -> bool {
+        false
+    }
+}
+
+/********************* RobustRealRunner ************************/
+
+enum RealBackend {
+    Compiled(CompiledRealRunner),
+    Interpreted(InterpretedRealRunner),
+}
+
+/// A real runner that uses the JIT compiler if possible and falls back to the
+/// bytecode interpreter if compilation fails.
+pub struct RobustRealRunner {
+    backend: RealBackend,
+}
+
+impl RobustRealRunner {
+    pub fn compile(ev: &ExpressionEvaluator<f64>, config: Config) -> Result<Self> {
+        Self::compile_with_funcs(ev, config, 0)
+    }
+
+    pub fn compile_with_funcs(
+        ev: &ExpressionEvaluator<f64>,
+        config: Config,
+        num_params: usize,
+    ) -> Result<Self> {
+        let backend = match CompiledRealRunner::compile_with_funcs(ev, config.clone(), num_params) {
+            Ok(runner) => RealBackend::Compiled(runner),
+            Err(_) => RealBackend::Interpreted(InterpretedRealRunner::compile_with_funcs(
+                ev, config, num_params,
+            )?),
+        };
+
+        Ok(Self { backend })
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        matches!(self.backend, RealBackend::Compiled(_))
+    }
+
+    pub fn is_complex(&self) -> bool {
+        false
+    }
+
+    pub fn evaluate(&mut self, args: &[f64], outs: &mut [f64]) {
+        match &mut self.backend {
+            RealBackend::Compiled(runner) => runner.evaluate(args, outs),
+            RealBackend::Interpreted(runner) => runner.evaluate(args, outs),
+        }
+    }
+
+    pub fn warmup(&mut self) {
+        match &mut self.backend {
+            RealBackend::Compiled(runner) => runner.warmup(),
+            RealBackend::Interpreted(runner) => runner.warmup(),
+        }
     }
 }
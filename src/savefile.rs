@@ -0,0 +1,102 @@
+//! The framed file format of `save` and `load`.
+//!
+//! A file starts with a header, followed by the `Application` as written by
+//! `Application::save`:
+//!
+//! | bytes | contents                                              |
+//! |-------|-------------------------------------------------------|
+//! | 4     | magic number `SJBR`                                   |
+//! | 4     | format version (`SAVE_FORMAT_VERSION`, little-endian) |
+//! | 1     | flags: bit 0 complex, bit 1 SIMD code                 |
+//! | 4     | number of output names (little-endian)                |
+//! | ...   | each name as a 4-byte length followed by UTF-8        |
+//!
+//! The version is bumped whenever the layout changes, and files of other
+//! versions are rejected instead of being misread.
+
+use anyhow::{anyhow, bail, Result};
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 4] = b"SJBR";
+
+/// The version of the file format written by `save`.
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+const COMPLEX: u8 = 1;
+const SIMD: u8 = 2;
+
+/// The metadata stored in front of the code.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Header {
+    pub complex: bool,
+    pub simd: bool,
+    /// The output names, empty if the outputs are not named.
+    pub names: Vec<String>,
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn write_header(w: &mut impl Write, header: &Header) -> Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&SAVE_FORMAT_VERSION.to_le_bytes())?;
+
+    let mut flags = 0;
+    if header.complex {
+        flags |= COMPLEX;
+    }
+    if header.simd {
+        flags |= SIMD;
+    }
+    w.write_all(&[flags])?;
+
+    w.write_all(&(header.names.len() as u32).to_le_bytes())?;
+    for name in &header.names {
+        w.write_all(&(name.len() as u32).to_le_bytes())?;
+        w.write_all(name.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Reads and checks a header, leaving `r` at the start of the code.
+pub(crate) fn read_header(r: &mut impl Read) -> Result<Header> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)
+        .map_err(|_| anyhow!("not a saved runner (the file is too short)"))?;
+
+    if &magic != MAGIC {
+        bail!("not a saved runner (bad magic number)");
+    }
+
+    let version = read_u32(r)?;
+
+    if version != SAVE_FORMAT_VERSION {
+        bail!(
+            "incompatible version {} of the saved runner (expected version {})",
+            version,
+            SAVE_FORMAT_VERSION
+        );
+    }
+
+    let mut flags = [0u8];
+    r.read_exact(&mut flags)?;
+
+    let n = read_u32(r)? as usize;
+    let mut names = Vec::new();
+
+    for _ in 0..n {
+        let mut name = vec![0u8; read_u32(r)? as usize];
+        r.read_exact(&mut name)?;
+        names.push(String::from_utf8(name)?);
+    }
+
+    Ok(Header {
+        complex: flags[0] & COMPLEX != 0,
+        simd: flags[0] & SIMD != 0,
+        names,
+    })
+}
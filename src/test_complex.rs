@@ -6,7 +6,7 @@ fn main() -> Result<()> {
     const NROWS: usize = 97;
 
     let model = std::fs::read_to_string("test_instructions.txt")?;
-    let mut runner = CompiledComplexRunner::compile_string(model, Config::default())?;
+    let runner = CompiledComplexRunner::compile_string(model, Config::default())?;
 
     let mut args: Vec<Complex<f64>> = vec![Complex::default(); NROWS * 3];
     let mut rng = rand::rng();
@@ -21,11 +21,11 @@ fn main() -> Result<()> {
         args[i * 3 + 2] = Complex::new(rng.random::<f64>(), rng.random::<f64>());
     }
 
-    let mut outs = [Complex::default(); NROWS as usize];
+    let mut outs = [Complex::default(); NROWS];
     runner.evaluate(&args, &mut outs);
 
-    for i in 0..NROWS {
-        let delta = outs[i] - 3.0;
+    for &out in outs.iter() {
+        let delta = out - 3.0;
         assert!(delta.abs() < 1e-14);
     }
 
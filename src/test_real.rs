@@ -6,7 +6,7 @@ fn main() -> Result<()> {
     const NROWS: usize = 97;
 
     let model = std::fs::read_to_string("test_instructions.txt")?;
-    let mut runner = CompiledRealRunner::compile_string(model, Config::default())?;
+    let runner = CompiledRealRunner::compile_string(model, Config::default())?;
 
     let mut args: Vec<f64> = vec![0.0; NROWS * 3];
     let mut rng = rand::rng();
@@ -17,11 +17,11 @@ fn main() -> Result<()> {
         args[i * 3 + 2] = rng.random::<f64>();
     }
 
-    let mut outs = [0.0; NROWS as usize];
+    let mut outs = [0.0; NROWS];
     runner.evaluate(&args, &mut outs);
 
-    for i in 0..NROWS {
-        let delta = outs[i] - 3.0;
+    for &out in outs.iter() {
+        let delta = out - 3.0;
         assert!(delta.abs() < 1e-15);
     }
 
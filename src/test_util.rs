@@ -0,0 +1,34 @@
+use crate::Number;
+use std::fmt::Debug;
+
+/// Returns true if `a` and `b` are within `atol + rtol * |b|` of each other,
+/// where `b` is the expected (reference) value. NaNs are never close.
+pub fn is_close<T: Number>(a: &T, b: &T, atol: f64, rtol: f64) -> bool {
+    let a = a.as_complex();
+    let b = b.as_complex();
+    (a - b).norm() <= atol + rtol * b.norm()
+}
+
+pub fn all_close<T: Number>(a: &[T], b: &[T], atol: f64, rtol: f64) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .all(|(x, y)| is_close(x, y, atol, rtol))
+}
+
+#[track_caller]
+pub fn assert_close<T: Number + Debug>(a: &[T], b: &[T], atol: f64, rtol: f64) {
+    assert_eq!(a.len(), b.len(), "length mismatch");
+
+    for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+        assert!(
+            is_close(x, y, atol, rtol),
+            "outputs differ at {}: {:?} vs {:?} (atol = {}, rtol = {})",
+            i,
+            x,
+            y,
+            atol,
+            rtol
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! Row partitioning and reductions for `ThreadedRealRunner`.
+
+use std::thread;
+
+/// The number of rows in a reduction block. The blocks, and hence the order
+/// of the floating point additions, do not depend on the number of threads.
+pub(crate) const BLOCK_ROWS: usize = 1024;
+
+/// Batches smaller than this run on the calling thread.
+pub(crate) const MIN_THREADED_ROWS: usize = 2 * BLOCK_ROWS;
+
+/// Resolves a requested thread count (0 means one per available core).
+pub(crate) fn resolve_threads(requested: usize) -> usize {
+    if requested == 0 {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        requested
+    }
+}
+
+/// The number of threads actually used for `nrows` rows.
+pub(crate) fn workers_for(nrows: usize, num_threads: usize) -> usize {
+    if nrows < MIN_THREADED_ROWS {
+        1
+    } else {
+        num_threads.min(nrows.div_ceil(BLOCK_ROWS)).max(1)
+    }
+}
+
+/// Pins the current thread to the `index`-th core (modulo the number of
+/// cores). It does nothing on platforms without an affinity API.
+fn pin_to_core(index: usize) {
+    if let Some(cores) = core_affinity::get_core_ids() {
+        if !cores.is_empty() {
+            core_affinity::set_for_current(cores[index % cores.len()]);
+        }
+    }
+}
+
+/// Splits `nrows` rows, stored row-major with `cp` parameters and `co`
+/// outputs per row, into contiguous chunks of whole blocks and calls
+/// `f(args, outs, n)` on each chunk, possibly on different threads, which are
+/// pinned to distinct cores if `pin` is true. Returns `true` if more than one
+/// thread was used.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn for_each_chunk<F>(
+    args: &[f64],
+    cp: usize,
+    outs: &mut [f64],
+    co: usize,
+    nrows: usize,
+    num_threads: usize,
+    pin: bool,
+    f: F,
+) -> bool
+where
+    F: Fn(&[f64], &mut [f64], usize) + Sync,
+{
+    let workers = workers_for(nrows, num_threads);
+
+    if workers == 1 {
+        f(&args[..nrows * cp], &mut outs[..nrows * co], nrows);
+        return false;
+    }
+
+    let rows_per_worker = nrows.div_ceil(BLOCK_ROWS).div_ceil(workers) * BLOCK_ROWS;
+
+    thread::scope(|s| {
+        let f = &f;
+
+        for (i, (a, o)) in args[..nrows * cp]
+            .chunks(rows_per_worker * cp)
+            .zip(outs[..nrows * co].chunks_mut(rows_per_worker * co))
+            .enumerate()
+        {
+            let n = o.len() / co;
+            s.spawn(move || {
+                if pin {
+                    pin_to_core(i);
+                }
+                f(a, o, n)
+            });
+        }
+    });
+
+    true
+}
+
+/// Like `for_each_chunk`, but writes the outputs in column-major order
+/// (`outs[j * nrows + i]` holds output `j` of row `i`). Each worker evaluates
+/// its rows block by block into a staging buffer and copies each output into
+/// its own segment of the corresponding column, so the columns are written
+/// contiguously. Returns `true` if more than one thread was used.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn for_each_chunk_columns<F>(
+    args: &[f64],
+    cp: usize,
+    outs: &mut [f64],
+    co: usize,
+    nrows: usize,
+    num_threads: usize,
+    pin: bool,
+    f: F,
+) -> bool
+where
+    F: Fn(&[f64], &mut [f64], usize) + Sync,
+{
+    if nrows == 0 || co == 0 {
+        return false;
+    }
+
+    let workers = workers_for(nrows, num_threads);
+    let rows_per_worker = nrows.div_ceil(BLOCK_ROWS).div_ceil(workers) * BLOCK_ROWS;
+
+    // segments[w][j] is the part of column j written by worker w
+    let mut segments: Vec<Vec<&mut [f64]>> = (0..nrows.div_ceil(rows_per_worker))
+        .map(|_| Vec::with_capacity(co))
+        .collect();
+
+    for col in outs[..nrows * co].chunks_mut(nrows) {
+        for (w, seg) in col.chunks_mut(rows_per_worker).enumerate() {
+            segments[w].push(seg);
+        }
+    }
+
+    let work = |w: usize, mut cols: Vec<&mut [f64]>| {
+        let start = w * rows_per_worker;
+        let n = cols[0].len();
+        let mut staging = vec![0.0; BLOCK_ROWS * co];
+
+        for s in (0..n).step_by(BLOCK_ROWS) {
+            let m = BLOCK_ROWS.min(n - s);
+            let a = &args[(start + s) * cp..(start + s + m) * cp];
+            f(a, &mut staging[..m * co], m);
+
+            for (j, col) in cols.iter_mut().enumerate() {
+                for i in 0..m {
+                    col[s + i] = staging[i * co + j];
+                }
+            }
+        }
+    };
+
+    if segments.len() == 1 {
+        work(0, segments.pop().unwrap());
+        return false;
+    }
+
+    thread::scope(|s| {
+        let work = &work;
+
+        for (w, cols) in segments.into_iter().enumerate() {
+            s.spawn(move || {
+                if pin {
+                    pin_to_core(w);
+                }
+                work(w, cols)
+            });
+        }
+    });
+
+    true
+}
+
+/// Adds the `n` rows of `buf` (`co` values per row) pairwise in a fixed order
+/// and returns the sum, leaving `buf` overwritten.
+fn pairwise(buf: &mut [f64], n: usize, co: usize) -> Vec<f64> {
+    let mut stride = 1;
+
+    while stride < n {
+        for i in (0..n - stride).step_by(2 * stride) {
+            for j in 0..co {
+                buf[i * co + j] += buf[(i + stride) * co + j];
+            }
+        }
+        stride *= 2;
+    }
+
+    if n == 0 {
+        vec![0.0; co]
+    } else {
+        buf[..co].to_vec()
+    }
+}
+
+/// Sums the `co` outputs of `nrows` rows. `eval(args, outs, n)` evaluates `n`
+/// rows. Each block of `BLOCK_ROWS` rows is summed pairwise, then the block
+/// sums are added pairwise in block order, so the result is bitwise identical
+/// for any number of threads. `scratch` holds one block buffer per worker and
+/// is grown as needed. The worker threads are pinned to distinct cores if
+/// `pin` is true. Returns the sums and whether threads were used.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn sum_rows<F>(
+    args: &[f64],
+    cp: usize,
+    co: usize,
+    nrows: usize,
+    num_threads: usize,
+    pin: bool,
+    scratch: &mut Vec<Vec<f64>>,
+    eval: F,
+) -> (Vec<f64>, bool)
+where
+    F: Fn(&[f64], &mut [f64], usize) + Sync,
+{
+    let nblocks = nrows.div_ceil(BLOCK_ROWS);
+    let workers = workers_for(nrows, num_threads);
+    let blocks_per_worker = nblocks.div_ceil(workers).max(1);
+    reserve_scratch(scratch, workers, co);
+
+    let sum_blocks = |first: usize, last: usize, outs: &mut Vec<f64>| -> Vec<f64> {
+        (first..last)
+            .flat_map(|b| {
+                let start = b * BLOCK_ROWS;
+                let n = BLOCK_ROWS.min(nrows - start);
+                eval(&args[start * cp..(start + n) * cp], &mut outs[..n * co], n);
+                pairwise(outs, n, co)
+            })
+            .collect()
+    };
+
+    let mut sums: Vec<f64> = if workers == 1 {
+        sum_blocks(0, nblocks, &mut scratch[0])
+    } else {
+        thread::scope(|s| {
+            let sum_blocks = &sum_blocks;
+
+            let handles: Vec<_> = (0..nblocks)
+                .step_by(blocks_per_worker)
+                .zip(scratch.iter_mut())
+                .enumerate()
+                .map(|(i, (first, outs))| {
+                    let last = (first + blocks_per_worker).min(nblocks);
+                    s.spawn(move || {
+                        if pin {
+                            pin_to_core(i);
+                        }
+                        sum_blocks(first, last, outs)
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        })
+    };
+
+    (pairwise(&mut sums, nblocks, co), workers > 1)
+}
+
+/// Evaluates the `co` outputs of `nrows` rows block by block and calls
+/// `bin(outs, bins)` on the outputs of each row, where `bins` is a local copy
+/// of `nbins` zeros per worker, so that the workers do not contend. The local
+/// copies are added in worker order at the end. `scratch` and `pin` are as in
+/// `sum_rows`. Returns the bins and whether threads were used.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn accumulate_rows<F, B>(
+    args: &[f64],
+    cp: usize,
+    co: usize,
+    nrows: usize,
+    nbins: usize,
+    num_threads: usize,
+    pin: bool,
+    scratch: &mut Vec<Vec<f64>>,
+    eval: F,
+    bin: B,
+) -> (Vec<f64>, bool)
+where
+    F: Fn(&[f64], &mut [f64], usize) + Sync,
+    B: Fn(&[f64], &mut [f64]) + Sync,
+{
+    let nblocks = nrows.div_ceil(BLOCK_ROWS);
+    let workers = workers_for(nrows, num_threads);
+    let blocks_per_worker = nblocks.div_ceil(workers).max(1);
+    reserve_scratch(scratch, workers, co);
+
+    let bin_blocks = |first: usize, last: usize, outs: &mut Vec<f64>| -> Vec<f64> {
+        let mut bins = vec![0.0; nbins];
+
+        for b in first..last {
+            let start = b * BLOCK_ROWS;
+            let n = BLOCK_ROWS.min(nrows - start);
+            eval(&args[start * cp..(start + n) * cp], &mut outs[..n * co], n);
+
+            for row in outs[..n * co].chunks(co) {
+                bin(row, &mut bins);
+            }
+        }
+
+        bins
+    };
+
+    if workers == 1 {
+        return (bin_blocks(0, nblocks, &mut scratch[0]), false);
+    }
+
+    let locals: Vec<Vec<f64>> = thread::scope(|s| {
+        let bin_blocks = &bin_blocks;
+
+        let handles: Vec<_> = (0..nblocks)
+            .step_by(blocks_per_worker)
+            .zip(scratch.iter_mut())
+            .enumerate()
+            .map(|(i, (first, outs))| {
+                let last = (first + blocks_per_worker).min(nblocks);
+                s.spawn(move || {
+                    if pin {
+                        pin_to_core(i);
+                    }
+                    bin_blocks(first, last, outs)
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut bins = vec![0.0; nbins];
+
+    for local in locals {
+        for (x, y) in bins.iter_mut().zip(local) {
+            *x += y;
+        }
+    }
+
+    (bins, true)
+}
+
+/// Finds the row whose first output is the least according to `less` (e.g.,
+/// `f64::lt` for the minimum), evaluating the rows block by block like
+/// `sum_rows`. Each worker keeps the best row of its own blocks, and the
+/// results are combined in worker order, so ties go to the lowest row index
+/// for any number of threads. NaN outputs are skipped. Returns the row and
+/// its value (`None` if there are no rows or all are NaN) and whether
+/// threads were used.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn extremum_rows<F>(
+    args: &[f64],
+    cp: usize,
+    co: usize,
+    nrows: usize,
+    num_threads: usize,
+    pin: bool,
+    scratch: &mut Vec<Vec<f64>>,
+    eval: F,
+    less: fn(&f64, &f64) -> bool,
+) -> (Option<(usize, f64)>, bool)
+where
+    F: Fn(&[f64], &mut [f64], usize) + Sync,
+{
+    let nblocks = nrows.div_ceil(BLOCK_ROWS);
+    let workers = workers_for(nrows, num_threads);
+    let blocks_per_worker = nblocks.div_ceil(workers).max(1);
+    reserve_scratch(scratch, workers, co);
+
+    let better = |best: Option<(usize, f64)>, (i, y): (usize, f64)| match best {
+        _ if y.is_nan() => best,
+        Some((_, b)) if !less(&y, &b) => best,
+        _ => Some((i, y)),
+    };
+
+    let scan_blocks = |first: usize, last: usize, outs: &mut Vec<f64>| -> Option<(usize, f64)> {
+        let mut best = None;
+
+        for b in first..last {
+            let start = b * BLOCK_ROWS;
+            let n = BLOCK_ROWS.min(nrows - start);
+            eval(&args[start * cp..(start + n) * cp], &mut outs[..n * co], n);
+
+            for (i, row) in outs[..n * co].chunks(co).enumerate() {
+                best = better(best, (start + i, row[0]));
+            }
+        }
+
+        best
+    };
+
+    if workers == 1 {
+        return (scan_blocks(0, nblocks, &mut scratch[0]), false);
+    }
+
+    let locals: Vec<Option<(usize, f64)>> = thread::scope(|s| {
+        let scan_blocks = &scan_blocks;
+
+        let handles: Vec<_> = (0..nblocks)
+            .step_by(blocks_per_worker)
+            .zip(scratch.iter_mut())
+            .enumerate()
+            .map(|(i, (first, outs))| {
+                let last = (first + blocks_per_worker).min(nblocks);
+                s.spawn(move || {
+                    if pin {
+                        pin_to_core(i);
+                    }
+                    scan_blocks(first, last, outs)
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    (locals.into_iter().flatten().fold(None, better), true)
+}
+
+/// Makes sure that `scratch` has a block buffer for each of `workers` workers.
+pub(crate) fn reserve_scratch(scratch: &mut Vec<Vec<f64>>, workers: usize, co: usize) {
+    if scratch.len() < workers {
+        scratch.resize(workers, Vec::new());
+    }
+
+    for buf in scratch.iter_mut().take(workers) {
+        buf.resize(BLOCK_ROWS * co, 0.0);
+    }
+}
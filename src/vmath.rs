@@ -0,0 +1,228 @@
+//! Polynomial approximations of `exp`, `log`, `sin`, and `cos` that operate on
+//! whole SIMD vectors (`f64`, `f64x2`, or `f64x4`; see `SimdElem`) instead of
+//! calling the scalar libm functions lane by lane. `InterpretedSimdRealRunner`
+//! uses them under `FpModel::Fast`.
+//!
+//! `exp` is within 2 ULP of the correctly rounded result. `log`, `sin`, and
+//! `cos` are within a few ULP; the argument reduction of `sin` and `cos` is
+//! accurate for `|x| < 1e6` and loses accuracy beyond.
+//!
+//! The `*_reduced` versions truncate the polynomials for speed and are
+//! accurate to about 1e-6 relative error (`Accuracy::Reduced`).
+//!
+//! The arithmetic is done on whole vectors; only the exponent manipulations
+//! and the masks are computed lane by lane.
+
+use crate::SimdElem;
+use std::f64::consts::{FRAC_1_SQRT_2, FRAC_2_PI, LOG2_E};
+
+// ln(2) split into a high part with trailing zeros, so that n * LN2_HI is
+// exact for the exponents of interest, and the low part
+const LN2_HI: f64 = 0.6931471803691238;
+const LN2_LO: f64 = 1.9082149292705877e-10;
+
+// pi/2 split into three parts for the Cody-Waite reduction of sin and cos
+const PIO2_1: f64 = 1.5707963267341256;
+const PIO2_2: f64 = 6.077100506303966e-11;
+const PIO2_3: f64 = 2.0222662487111665e-21;
+
+/// Above this, exp overflows.
+const EXP_MAX: f64 = 709.782712893384;
+/// Below this, exp underflows to zero.
+const EXP_MIN: f64 = -745.1332191019412;
+
+/// 1/k! for k = 0..=13, the Taylor coefficients of exp(r), |r| <= ln(2)/2.
+const EXP_COEFFS: [f64; 14] = [
+    1.0,
+    1.0,
+    1.0 / 2.0,
+    1.0 / 6.0,
+    1.0 / 24.0,
+    1.0 / 120.0,
+    1.0 / 720.0,
+    1.0 / 5040.0,
+    1.0 / 40320.0,
+    1.0 / 362880.0,
+    1.0 / 3628800.0,
+    1.0 / 39916800.0,
+    1.0 / 479001600.0,
+    1.0 / 6227020800.0,
+];
+
+/// 1/(2k+1) for k = 1..=9, the coefficients of (atanh(s) / s - 1) / s^2 in s^2.
+const LOG_COEFFS: [f64; 9] = [
+    1.0 / 3.0,
+    1.0 / 5.0,
+    1.0 / 7.0,
+    1.0 / 9.0,
+    1.0 / 11.0,
+    1.0 / 13.0,
+    1.0 / 15.0,
+    1.0 / 17.0,
+    1.0 / 19.0,
+];
+
+/// (-1)^k / (2k+1)! for k = 0..=9, the coefficients of sin(r) / r in r^2.
+const SIN_COEFFS: [f64; 10] = [
+    1.0,
+    -1.0 / 6.0,
+    1.0 / 120.0,
+    -1.0 / 5040.0,
+    1.0 / 362880.0,
+    -1.0 / 39916800.0,
+    1.0 / 6227020800.0,
+    -1.0 / 1307674368000.0,
+    1.0 / 355687428096000.0,
+    -1.0 / 121645100408832000.0,
+];
+
+/// (-1)^k / (2k)! for k = 0..=10, the coefficients of cos(r) in r^2.
+const COS_COEFFS: [f64; 11] = [
+    1.0,
+    -1.0 / 2.0,
+    1.0 / 24.0,
+    -1.0 / 720.0,
+    1.0 / 40320.0,
+    -1.0 / 3628800.0,
+    1.0 / 479001600.0,
+    -1.0 / 87178291200.0,
+    1.0 / 20922789888000.0,
+    -1.0 / 6402373705728000.0,
+    1.0 / 2432902008176640000.0,
+];
+
+/// The number of coefficients of each series kept by the `*_reduced` functions.
+const EXP_REDUCED: usize = 9;
+const LOG_REDUCED: usize = 4;
+const SIN_REDUCED: usize = 5;
+const COS_REDUCED: usize = 6;
+
+fn horner<T: SimdElem>(x: T, coeffs: &[f64]) -> T {
+    coeffs
+        .iter()
+        .rev()
+        .fold(T::splat(0.0), |acc, c| acc * x + T::splat(*c))
+}
+
+/// A lane mask: 1 where `f` is true and 0 elsewhere, for `SimdElem::select`.
+fn mask<T: SimdElem>(x: T, f: impl Fn(f64) -> bool) -> T {
+    x.map(|v| if f(v) { 1.0 } else { 0.0 })
+}
+
+/// 2^n for integral n in the normal exponent range.
+fn pow2i<T: SimdElem>(n: T) -> T {
+    n.map(|v| f64::from_bits(((v.clamp(-1100.0, 1100.0) as i64 + 1023) as u64) << 52))
+}
+
+pub fn exp<T: SimdElem>(x: T) -> T {
+    exp_with(x, &EXP_COEFFS)
+}
+
+pub fn exp_reduced<T: SimdElem>(x: T) -> T {
+    exp_with(x, &EXP_COEFFS[..EXP_REDUCED])
+}
+
+fn exp_with<T: SimdElem>(x: T, coeffs: &[f64]) -> T {
+    let n = (x * T::splat(LOG2_E)).map(f64::round_ties_even);
+    let r = x - n * T::splat(LN2_HI) - n * T::splat(LN2_LO);
+    let p = horner(r, coeffs);
+
+    // 2^n is applied in two steps, since 2^1024 itself is not representable
+    let h = n.map(|v| (0.5 * v).floor());
+    let y = p * pow2i(h) * pow2i(n - h);
+
+    let y = T::select(mask(x, |v| v > EXP_MAX), T::splat(f64::INFINITY), y);
+    T::select(mask(x, |v| v < EXP_MIN), T::splat(0.0), y)
+}
+
+/// Splits a positive finite `v` into `m * 2^e` with `m` in `[sqrt(1/2), sqrt(2))`.
+fn split(v: f64) -> (f64, f64) {
+    if !(v > 0.0 && v.is_finite()) {
+        return (1.0, 0.0);
+    }
+
+    let (v, bias) = if v < f64::MIN_POSITIVE {
+        (v * 2f64.powi(54), -54)
+    } else {
+        (v, 0)
+    };
+
+    let bits = v.to_bits();
+    let e = ((bits >> 52) & 0x7ff) as i64 - 1022 + bias;
+    let m = f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52));
+
+    if m < FRAC_1_SQRT_2 {
+        (2.0 * m, (e - 1) as f64)
+    } else {
+        (m, e as f64)
+    }
+}
+
+pub fn log<T: SimdElem>(x: T) -> T {
+    log_with(x, &LOG_COEFFS)
+}
+
+pub fn log_reduced<T: SimdElem>(x: T) -> T {
+    log_with(x, &LOG_COEFFS[..LOG_REDUCED])
+}
+
+fn log_with<T: SimdElem>(x: T, coeffs: &[f64]) -> T {
+    let m = x.map(|v| split(v).0);
+    let e = x.map(|v| split(v).1);
+
+    // log(m) = 2 atanh(s) with s = (m - 1) / (m + 1), |s| < 0.172
+    let f = m - T::splat(1.0);
+    let s = f / (T::splat(2.0) + f);
+    let z = s * s;
+    let t = s * z * horner(z, coeffs);
+    let y = e * T::splat(LN2_HI) + (e * T::splat(LN2_LO) + (s + t) * T::splat(2.0));
+
+    let y = T::select(mask(x, |v| v == f64::INFINITY), x, y);
+    let y = T::select(mask(x, |v| v == 0.0), T::splat(f64::NEG_INFINITY), y);
+    T::select(mask(x, |v| v.is_nan() || v < 0.0), T::splat(f64::NAN), y)
+}
+
+/// Reduces `x` to `r` in `[-pi/4, pi/4]` and returns `(sin(r), cos(r), q)`,
+/// where `x = r + q * pi/2 (mod 2pi)`, with `q` in 0..4. `reduced` selects the
+/// truncated series.
+fn reduce<T: SimdElem>(x: T, reduced: bool) -> (T, T, T) {
+    let (sc, cc) = if reduced {
+        (&SIN_COEFFS[..SIN_REDUCED], &COS_COEFFS[..COS_REDUCED])
+    } else {
+        (&SIN_COEFFS[..], &COS_COEFFS[..])
+    };
+
+    let n = (x * T::splat(FRAC_2_PI)).map(f64::round_ties_even);
+    let r = x - n * T::splat(PIO2_1) - n * T::splat(PIO2_2) - n * T::splat(PIO2_3);
+    let z = r * r;
+    let q = n.map(|v| v.rem_euclid(4.0));
+    (r * horner(z, sc), horner(z, cc), q)
+}
+
+pub fn sin<T: SimdElem>(x: T) -> T {
+    sin_with(x, false)
+}
+
+pub fn sin_reduced<T: SimdElem>(x: T) -> T {
+    sin_with(x, true)
+}
+
+fn sin_with<T: SimdElem>(x: T, reduced: bool) -> T {
+    let (s, c, q) = reduce(x, reduced);
+    let v = T::select(mask(q, |q| q == 1.0 || q == 3.0), c, s);
+    T::select(mask(q, |q| q >= 2.0), v * T::splat(-1.0), v)
+}
+
+pub fn cos<T: SimdElem>(x: T) -> T {
+    cos_with(x, false)
+}
+
+pub fn cos_reduced<T: SimdElem>(x: T) -> T {
+    cos_with(x, true)
+}
+
+fn cos_with<T: SimdElem>(x: T, reduced: bool) -> T {
+    let (s, c, q) = reduce(x, reduced);
+    let v = T::select(mask(q, |q| q == 1.0 || q == 3.0), s, c);
+    T::select(mask(q, |q| q == 1.0 || q == 2.0), v * T::splat(-1.0), v)
+}